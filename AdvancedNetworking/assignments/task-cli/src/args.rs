@@ -0,0 +1,36 @@
+use clap::Parser;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Keyword given in the MyCourses assignment.
+    keyword: String,
+
+    /// Address of adnet-agent.
+    #[arg(short, long, default_value = "10.0.0.3:12345")]
+    server_addr: String,
+
+    /// Congestion control algorithm of the socket (TCP_CONGESTION), e.g.
+    /// cubic, reno or bbr.
+    #[arg(long)]
+    cc: Option<String>,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn keyword(&self) -> &String {
+        &self.keyword
+    }
+
+    pub fn server_addr(&self) -> &String {
+        &self.server_addr
+    }
+
+    pub fn cc(&self) -> Option<&String> {
+        self.cc.as_ref()
+    }
+}
@@ -0,0 +1,100 @@
+/*  Tokio implementation of task-cli, selected with the "async" cargo feature.
+    Reading is raced against Ctrl-C with tokio::select!, so that an
+    interrupted transfer still reports what was received so far.
+*/
+
+use std::{io, os::fd::AsRawFd, process, time::Instant};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    signal,
+    task,
+};
+
+use crate::{
+    args::Args,
+    protocol::{context, control_message, Outcome, Transfer},
+};
+
+const BUF_SIZE: usize = 8192;
+
+// Sets up the tokio runtime, so that the function can be called from
+// non-async main.
+#[tokio::main]
+pub async fn run(args: &Args) {
+    println!("Task-CLI starting (async)");
+    match transfer(args).await {
+        Ok(outcome) => outcome.print_results(),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the task once: connects, sends the control message and reads all
+/// data until the server closes the connection, or Ctrl-C is pressed.
+async fn transfer(args: &Args) -> io::Result<Outcome> {
+    println!("Connecting to {}...", args.server_addr());
+
+    // Open TCP connection to adnet-agent server. Happy Eyeballs connector
+    // uses blocking threads, so it is run where blocking is allowed, and the
+    // connected socket is then handed over to tokio.
+    let address = args.server_addr().clone();
+    let (result, report) = task::spawn_blocking(move || happy_eyeballs::connect(&address))
+        .await
+        .expect("connect task panicked");
+    print!("{}", report);
+    let mut stream = result
+        .and_then(|s| {
+            s.set_nonblocking(true)?;
+            TcpStream::from_std(s)
+        })
+        .map_err(|e| context(e, &format!("Failed to connect to {}", args.server_addr())))?;
+
+    println!("Connected.");
+
+    // Choose congestion control before anything is sent
+    if let Some(cc) = args.cc() {
+        let in_effect = tcp_congestion::set(stream.as_raw_fd(), cc)?;
+        println!("Congestion control: {}", in_effect);
+    }
+
+    // Send control message: "TASK-CLI keyword"
+    let command = control_message(args.keyword());
+    stream.write_all(command.as_bytes()).await
+        .map_err(|e| context(e, "Failed to send command"))?;
+    println!("Sent command: {}", command);
+
+    // Start clock to measure transfer duration
+    let start = Instant::now();
+    let mut transfer = Transfer::new();
+    let mut complete = true;
+
+    // Whichever finishes first: the transfer, or Ctrl-C.
+    tokio::select! {
+        result = receive_all(&mut stream, &mut transfer) => {
+            result.map_err(|e| context(e, "Read error"))?;
+        }
+        _ = signal::ctrl_c() => {
+            complete = false;
+        }
+    }
+
+    let duration = start.elapsed();
+    Ok(Outcome { transfer, duration, complete })
+}
+
+/// Read all data until server closes connection.
+async fn receive_all(stream: &mut TcpStream, transfer: &mut Transfer) -> io::Result<()> {
+    let mut buf = [0u8; BUF_SIZE];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            // Server closed the connection
+            return Ok(());
+        }
+        transfer.received(&buf[..n]);
+    }
+}
@@ -0,0 +1,79 @@
+/*  Blocking implementation of task-cli, using std::net::TcpStream.
+*/
+
+use std::{
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    process,
+    time::Instant,
+};
+
+use crate::{
+    args::Args,
+    protocol::{context, control_message, Outcome, Transfer},
+};
+
+const BUF_SIZE: usize = 8192;
+
+pub fn run(args: &Args) {
+    println!("Task-CLI starting");
+    match transfer(args) {
+        Ok(outcome) => outcome.print_results(),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the task once: connects, sends the control message and reads all
+/// data until the server closes the connection.
+fn transfer(args: &Args) -> io::Result<Outcome> {
+    println!("Connecting to {}...", args.server_addr());
+
+    // Open TCP connection to adnet-agent server. If the name has both IPv6
+    // and IPv4 addresses, they are raced, and the attempts are printed.
+    let (result, report) = happy_eyeballs::connect(args.server_addr());
+    print!("{}", report);
+    let mut stream = result
+        .map_err(|e| context(e, &format!("Failed to connect to {}", args.server_addr())))?;
+
+    println!("Connected.");
+
+    // Choose congestion control before anything is sent
+    if let Some(cc) = args.cc() {
+        let in_effect = tcp_congestion::set(stream.as_raw_fd(), cc)?;
+        println!("Congestion control: {}", in_effect);
+    }
+
+    // Send control message: "TASK-CLI keyword"
+    let command = control_message(args.keyword());
+    stream.write_all(command.as_bytes())
+        .map_err(|e| context(e, "Failed to send command"))?;
+    println!("Sent command: {}", command);
+
+    // Start clock to measure transfer duration
+    let start = Instant::now();
+
+    // Read all data until server closes connection
+    let mut buf = [0u8; BUF_SIZE];
+    let mut transfer = Transfer::new();
+
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => {
+                // Server closed the connection
+                break;
+            }
+            Ok(n) => transfer.received(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                // Retry on EINTR
+                continue;
+            }
+            Err(e) => return Err(context(e, "Read error")),
+        }
+    }
+
+    let duration = start.elapsed();
+    Ok(Outcome { transfer, duration, complete: true })
+}
@@ -0,0 +1,70 @@
+/*  Messages to adnet-agent and handling of the received data. Shared by the
+    blocking and the async client implementations.
+*/
+
+use std::{io, time::Duration};
+
+/// Control message that starts the transfer.
+pub fn control_message(keyword: &str) -> String {
+    format!("TASK-CLI {}", keyword)
+}
+
+/// Adds a description of what failed to an I/O error.
+pub fn context(err: io::Error, what: &str) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", what, err))
+}
+
+/// Result of one run of the task.
+pub struct Outcome {
+    pub transfer: Transfer,
+    pub duration: Duration,
+    pub complete: bool,  // false if interrupted before server closed the connection
+}
+
+impl Outcome {
+    pub fn print_results(&self) {
+        if !self.complete {
+            println!("Interrupted, transfer not complete.");
+        }
+        self.transfer.print_results(self.duration);
+    }
+}
+
+/// Keeps track of the data received from adnet-agent.
+pub struct Transfer {
+    total_bytes: usize,
+    last_8: String,
+}
+
+impl Transfer {
+    pub fn new() -> Transfer {
+        Transfer {
+            total_bytes: 0,
+            last_8: String::new(),
+        }
+    }
+
+    /// Account a chunk of data read from the socket.
+    pub fn received(&mut self, data: &[u8]) {
+        self.total_bytes += data.len();
+
+        // Maintain the last 8 characters received
+        let chunk = String::from_utf8_lossy(data);
+        self.last_8.push_str(&chunk);
+        if self.last_8.len() > 8 {
+            let excess = self.last_8.len() - 8;
+            self.last_8 = self.last_8[excess..].to_string();
+        }
+    }
+
+    pub fn print_results(&self, duration: Duration) {
+        println!("--- Results ---");
+        println!("Total bytes received: {}", self.total_bytes);
+        println!("Last 8 characters:    {}", self.last_8);
+        println!(
+            "Transfer duration:    {}.{:03} seconds",
+            duration.as_secs(),
+            duration.subsec_millis()
+        );
+    }
+}
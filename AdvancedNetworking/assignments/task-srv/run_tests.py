@@ -31,10 +31,11 @@ def run_scenario(name, topo_args, desc):
     commands = [
         f'rh1 {ADNET_AGENT} &',
         'sh sleep 2',
-        # Run server in background — it never exits on its own
+        # Run server in background — it exits after all sessions have closed
         f'lh1 {TASK_SRV} {KEYWORD} > {srv_log} 2>&1 &',
         'sh sleep 90',
         f'lh1 cat {srv_log}',
+        # In case the agent did not close all connections
         'lh1 pkill task-srv',
         'rh1 pkill adnet-agent',
         'sh sleep 1',
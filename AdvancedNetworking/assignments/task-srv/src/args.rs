@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use clap::Parser;
+
+use crate::response::Engine;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Keyword given in the MyCourses assignment.
+    keyword: String,
+
+    /// Keep accepting new connections after all sessions have closed,
+    /// instead of printing the summary and exiting.
+    #[arg(short, long)]
+    keep_serving: bool,
+
+    /// After all sessions have closed, wait this many milliseconds for new
+    /// connections before the task is considered complete.
+    #[arg(short, long, default_value_t = 2000)]
+    grace: u64,
+
+    /// Number of sessions adnet-agent is expected to open. When that many
+    /// have closed, the task is complete without waiting for the grace period.
+    #[arg(short, long)]
+    sessions: Option<usize>,
+
+    /// How response data is written to the socket.
+    #[arg(short, long, value_enum, default_value_t = Engine::Vectored)]
+    engine: Engine,
+
+    /// Address of adnet-agent, where the control message is sent.
+    #[arg(short, long, default_value = "10.0.0.3:12345")]
+    agent_addr: String,
+
+    /// Address to listen for incoming connections.
+    #[arg(short, long, default_value = "0.0.0.0:12321")]
+    listen_addr: String,
+
+    /// Address:port told to adnet-agent in the control message.
+    #[arg(short = 'm', long, default_value = "10.0.0.1:12321")]
+    my_addr: String,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn keyword(&self) -> &String {
+        &self.keyword
+    }
+
+    pub fn keep_serving(&self) -> bool {
+        self.keep_serving
+    }
+
+    pub fn grace(&self) -> Duration {
+        Duration::from_millis(self.grace)
+    }
+
+    pub fn sessions(&self) -> Option<usize> {
+        self.sessions
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn agent_addr(&self) -> &String {
+        &self.agent_addr
+    }
+
+    pub fn listen_addr(&self) -> &String {
+        &self.listen_addr
+    }
+
+    pub fn my_addr(&self) -> &String {
+        &self.my_addr
+    }
+}
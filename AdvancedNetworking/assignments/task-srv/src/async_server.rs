@@ -0,0 +1,217 @@
+/*  Tokio implementation of task-srv, selected with the "async" cargo feature.
+    Each client is served by its own tokio task, like in the async-server
+    example. The main task waits for new connections, finished sessions and
+    Ctrl-C at the same time using tokio::select!. On Ctrl-C the client tasks
+    are cancelled, and the summary is printed from what they managed to do.
+*/
+
+use std::{
+    os::fd::AsRawFd,
+    process,
+    sync::Arc,
+    time::Instant,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Interest},
+    net::{TcpListener, TcpStream},
+    signal::{self, unix::SignalKind},
+    sync::{mpsc, watch},
+    task,
+    time,
+};
+
+use crate::{
+    args::Args,
+    protocol::{control_message, Request, REQUEST_LEN},
+    response::{Engine, FillBuffer, FillCache, Responder},
+    session::{SessionStats, Sessions},
+};
+
+// Sets up the tokio runtime, so that the function can be called from
+// non-async main.
+#[tokio::main]
+pub async fn run(args: &Args) {
+    println!("Task-SRV starting (async)");
+
+    // Bind listening socket
+    let listener = TcpListener::bind(args.listen_addr()).await.unwrap_or_else(|e| {
+        eprintln!("Failed to bind {}: {}", args.listen_addr(), e);
+        process::exit(1);
+    });
+    println!("Listening on {}", args.listen_addr());
+
+    // Send control message to adnet-agent
+    let mut agent = TcpStream::connect(args.agent_addr()).await.unwrap_or_else(|e| {
+        eprintln!("Failed to connect to {}: {}", args.agent_addr(), e);
+        process::exit(1);
+    });
+    let command = control_message(args.keyword(), args.my_addr());
+    agent.write_all(command.as_bytes()).await.unwrap_or_else(|e| {
+        eprintln!("Failed to send command: {}", e);
+        process::exit(1);
+    });
+    println!("Sent: {}", command);
+
+    // Client tasks send their statistics to this channel when they finish.
+    let (tx, mut rx) = mpsc::unbounded_channel::<SessionStats>();
+
+    // Changing the value in this channel tells client tasks to stop.
+    let (cancel_tx, cancel_rx) = watch::channel(false);
+
+    let mut sessions = Sessions::new();
+    let engine = args.engine();
+    let cache = Arc::new(FillCache::new(engine));
+    let mut interrupted = false;
+
+    // The signal stream is created once, so that a signal arriving between
+    // select! iterations is not lost.
+    let mut sigint = signal::unix::signal(SignalKind::interrupt()).unwrap_or_else(|e| {
+        eprintln!("Failed to set up signal handling: {}", e);
+        process::exit(1);
+    });
+
+    loop {
+        // While no sessions are active, the task is complete if no new one
+        // arrives before the grace period ends
+        let grace_deadline = sessions.grace_deadline(args.grace());
+        tokio::select! {
+            accepted = listener.accept(), if !interrupted => {
+                let (socket, address) = match accepted {
+                    Ok(a) => a,
+                    Err(e) => {
+                        eprintln!("Accept error: {}", e);
+                        continue;
+                    }
+                };
+                println!("Accepted connection from {}", address);
+                let id = sessions.open();
+
+                // Spawn a new tokio task to handle communication with the client.
+                let tx = tx.clone();
+                let cache = Arc::clone(&cache);
+                let mut cancel = cancel_rx.clone();
+                task::spawn(async move {
+                    let start = Instant::now();
+                    let mut stats = SessionStats::new(id, address);
+
+                    // Whichever finishes first: the client, or cancellation.
+                    // The statistics collected so far are kept in both cases.
+                    tokio::select! {
+                        _ = process_client(socket, &mut stats, engine, &cache) => {}
+                        _ = cancel.changed() => {
+                            println!("Session {} with {} cancelled", id, address);
+                            // Include the interrupted response in write time
+                            stats.stop_writing();
+                        }
+                    }
+                    stats.duration = start.elapsed();
+                    let _ = tx.send(stats);
+                });
+            }
+            Some(stats) = rx.recv() => {
+                let address = stats.address;
+                let last = sessions.close(stats);
+                println!("Connection from {} closed, {} still active", address, sessions.active());
+                if (interrupted && last) || sessions.all_expected_closed(args.sessions()) {
+                    sessions.print_summary();
+                    if interrupted || !args.keep_serving() {
+                        break;
+                    }
+                    println!("Waiting for new connections");
+                }
+            }
+            _ = time::sleep_until(grace_deadline.unwrap_or_else(std::time::Instant::now).into()),
+                if grace_deadline.is_some() && !interrupted => {
+                // All sessions opened by adnet-agent are now closed.
+                sessions.print_summary();
+                if !args.keep_serving() {
+                    break;
+                }
+                println!("Waiting for new connections");
+            }
+            _ = sigint.recv(), if !interrupted => {
+                println!("Interrupted, cancelling {} active sessions", sessions.active());
+                interrupted = true;
+                if sessions.active() == 0 {
+                    sessions.print_summary();
+                    break;
+                }
+                let _ = cancel_tx.send(true);
+            }
+        }
+    }
+}
+
+async fn process_client(
+    mut socket: TcpStream,
+    stats: &mut SessionStats,
+    engine: Engine,
+    cache: &FillCache,
+) {
+    let mut responder = match Responder::new(engine) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Could not set up {:?} engine: {}", engine, e);
+            return;
+        }
+    };
+
+    loop {
+        let mut header = [0u8; REQUEST_LEN];
+        if socket.read_exact(&mut header).await.is_err() {
+            break;
+        }
+        let request = Request::from_bytes(&header);
+        stats.requests += 1;
+
+        // Write `total` bytes filled with `character`, using the shared buffer
+        let fill = match cache.get(request.character) {
+            Ok(fill) => fill,
+            Err(e) => {
+                eprintln!("Could not create fill buffer: {}", e);
+                break;
+            }
+        };
+        stats.start_writing();
+        let result = send_fill(&socket, &mut responder, &fill, request.total, &mut stats.bytes).await;
+        stats.stop_writing();
+        if let Err(e) = result {
+            eprintln!("Write error: {}", e);
+            break;
+        }
+
+        println!("Wrote {} bytes of byte {}", request.total, request.character);
+    }
+}
+
+/// Async counterpart of send_fill in threaded_server.rs. Tokio sockets are
+/// non-blocking, so we wait until the socket is writable, and let the
+/// responder do one system call at a time. If the socket buffer is full,
+/// try_io clears the readiness and we wait again.
+async fn send_fill(
+    socket: &TcpStream,
+    responder: &mut Responder,
+    fill: &FillBuffer,
+    total: u64,
+    written: &mut u64,
+) -> std::io::Result<()> {
+    let mut remaining = total;
+    while remaining > 0 {
+        socket.writable().await?;
+        let result = socket.try_io(Interest::WRITABLE, || {
+            responder.send_chunk(socket.as_raw_fd(), fill, remaining)
+        });
+        match result {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                remaining -= n as u64;
+                *written += n as u64;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
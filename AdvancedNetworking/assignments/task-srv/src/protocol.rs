@@ -0,0 +1,27 @@
+/*  Messages between task-srv and adnet-agent. Shared by the blocking and
+    the async server implementations.
+*/
+
+/// Length of a request sent by adnet-agent.
+pub const REQUEST_LEN: usize = 5;
+
+/// Request for `total` bytes, all containing `character`.
+pub struct Request {
+    pub total: u64,
+    pub character: u8,
+}
+
+impl Request {
+    /// Parse 5-byte request: 4-byte u32 (big-endian) byte count + 1-byte fill
+    pub fn from_bytes(header: &[u8; REQUEST_LEN]) -> Request {
+        Request {
+            total: u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64,
+            character: header[4],
+        }
+    }
+}
+
+/// Control message telling adnet-agent where to open its connections.
+pub fn control_message(keyword: &str, my_addr: &str) -> String {
+    format!("TASK-SRV {} {}", keyword, my_addr)
+}
@@ -0,0 +1,225 @@
+/*  Response engine for task-srv.
+
+    All response bytes of a request are the same, so there is no need to
+    allocate and fill a new buffer for every request. Instead we keep one
+    pre-filled buffer per fill byte, shared by all client threads, and just
+    write it out as many times as needed. Because the content is uniform, a
+    partial write never requires us to remember an offset inside the buffer.
+
+    There are different ways to push the buffer into the socket:
+    - write:    plain write calls, one buffer at a time
+    - vectored: write_vectored with several references to the same buffer,
+                so that one system call can move more data
+    - sendfile: the buffer is placed in an in-memory file (memfd), and the
+                kernel copies from the file to socket without user space
+    - splice:   the memfd pages are moved to a pipe, and from the pipe to the
+                socket, avoiding copies through user space
+*/
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, IoSlice, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{Arc, Mutex},
+};
+
+use clap::ValueEnum;
+
+/// Size of the shared pre-filled buffer.
+const FILL_SIZE: usize = 256 * 1024;
+
+/// Number of buffer references given to one write_vectored call.
+const IOV_COUNT: usize = 16;
+
+/// Pipe capacity we request for splice. The kernel may refuse, in which case
+/// the default (64 KiB) is used.
+const PIPE_SIZE: libc::c_int = 1024 * 1024;
+
+/// How response data is written to the socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Engine {
+    Write,
+    Vectored,
+    Sendfile,
+    Splice,
+}
+
+impl Engine {
+    /// Does this engine read from the memfd instead of the user space buffer.
+    fn needs_file(&self) -> bool {
+        matches!(self, Engine::Sendfile | Engine::Splice)
+    }
+}
+
+/// Pre-filled buffer for one fill byte, and the same content in memfd if the
+/// engine needs it.
+pub struct FillBuffer {
+    data: Box<[u8]>,
+    file: Option<File>,
+}
+
+impl FillBuffer {
+    fn new(character: u8, engine: Engine) -> io::Result<FillBuffer> {
+        let data = vec![character; FILL_SIZE].into_boxed_slice();
+        let file = if engine.needs_file() {
+            // Anonymous file that lives only in memory
+            let fd = unsafe { libc::memfd_create(c"task-srv-fill".as_ptr(), libc::MFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut file = unsafe { File::from_raw_fd(fd) };
+            file.write_all(&data)?;
+            Some(file)
+        } else {
+            None
+        };
+        Ok(FillBuffer { data, file })
+    }
+}
+
+/// Shared cache of fill buffers, one per fill byte. Buffers are created when a
+/// byte is requested for the first time.
+pub struct FillCache {
+    engine: Engine,
+    buffers: Mutex<HashMap<u8, Arc<FillBuffer>>>,
+}
+
+impl FillCache {
+    pub fn new(engine: Engine) -> FillCache {
+        FillCache {
+            engine,
+            buffers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, character: u8) -> io::Result<Arc<FillBuffer>> {
+        let mut buffers = self.buffers.lock().unwrap();
+        if let Some(buffer) = buffers.get(&character) {
+            return Ok(Arc::clone(buffer));
+        }
+        let buffer = Arc::new(FillBuffer::new(character, self.engine)?);
+        buffers.insert(character, Arc::clone(&buffer));
+        Ok(buffer)
+    }
+}
+
+/// Per-connection state of the response engine. Splice needs a pipe, which
+/// is created once per connection and reused for all requests.
+pub struct Responder {
+    engine: Engine,
+    pipe: Option<(OwnedFd, OwnedFd)>,
+    in_pipe: usize,  // bytes moved to pipe, but not yet to socket
+}
+
+impl Responder {
+    pub fn new(engine: Engine) -> io::Result<Responder> {
+        let pipe = if engine == Engine::Splice {
+            Some(create_pipe()?)
+        } else {
+            None
+        };
+        Ok(Responder { engine, pipe, in_pipe: 0 })
+    }
+
+    /// Do one system call worth of writing to socket `fd`, at most
+    /// `remaining` bytes. Returns the number of bytes that went to the socket.
+    /// Works also with non-blocking sockets, in which case `WouldBlock` error
+    /// is returned when the socket buffer is full.
+    pub fn send_chunk(&mut self, fd: RawFd, fill: &FillBuffer, remaining: u64) -> io::Result<usize> {
+        match self.engine {
+            Engine::Write => write_plain(fd, fill, remaining),
+            Engine::Vectored => write_vectored(fd, fill, remaining),
+            Engine::Sendfile => write_sendfile(fd, fill, remaining),
+            Engine::Splice => self.write_splice(fd, fill, remaining),
+        }
+    }
+
+    fn write_splice(&mut self, fd: RawFd, fill: &FillBuffer, remaining: u64) -> io::Result<usize> {
+        let file = fill.file.as_ref().unwrap();
+        let (rd, wr) = self.pipe.as_ref().unwrap();
+
+        // Move file pages into the pipe, if the previous round was fully
+        // delivered to the socket.
+        if self.in_pipe == 0 {
+            let mut offset: libc::loff_t = 0;
+            self.in_pipe = cvt(unsafe {
+                libc::splice(
+                    file.as_raw_fd(),
+                    &mut offset,
+                    wr.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    chunk(remaining, FILL_SIZE),
+                    libc::SPLICE_F_MOVE,
+                )
+            })?;
+        }
+
+        // Then from the pipe to the socket. This may move only part of the
+        // pipe content, the rest goes on the next call.
+        let n = cvt(unsafe {
+            libc::splice(
+                rd.as_raw_fd(),
+                std::ptr::null_mut(),
+                fd,
+                std::ptr::null_mut(),
+                self.in_pipe,
+                libc::SPLICE_F_MOVE | libc::SPLICE_F_MORE,
+            )
+        })?;
+        self.in_pipe -= n;
+        Ok(n)
+    }
+}
+
+fn chunk(remaining: u64, max: usize) -> usize {
+    remaining.min(max as u64) as usize
+}
+
+fn write_plain(fd: RawFd, fill: &FillBuffer, remaining: u64) -> io::Result<usize> {
+    let len = chunk(remaining, FILL_SIZE);
+    cvt(unsafe { libc::write(fd, fill.data.as_ptr() as *const libc::c_void, len) })
+}
+
+fn write_vectored(fd: RawFd, fill: &FillBuffer, remaining: u64) -> io::Result<usize> {
+    // All slices point to the same buffer, only the last one may be shorter.
+    // IoSlice has the same memory layout as struct iovec.
+    let mut left = chunk(remaining, FILL_SIZE * IOV_COUNT);
+    let mut slices: Vec<IoSlice> = Vec::with_capacity(IOV_COUNT);
+    while left > 0 {
+        let len = left.min(FILL_SIZE);
+        slices.push(IoSlice::new(&fill.data[..len]));
+        left -= len;
+    }
+    cvt(unsafe {
+        libc::writev(fd, slices.as_ptr() as *const libc::iovec, slices.len() as libc::c_int)
+    })
+}
+
+fn write_sendfile(fd: RawFd, fill: &FillBuffer, remaining: u64) -> io::Result<usize> {
+    let file = fill.file.as_ref().unwrap();
+    // Always start from the beginning of the file, the content is the same
+    let mut offset: libc::off_t = 0;
+    cvt(unsafe { libc::sendfile(fd, file.as_raw_fd(), &mut offset, chunk(remaining, FILL_SIZE)) })
+}
+
+fn create_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds: [RawFd; 2] = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (rd, wr) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    // Larger pipe means fewer system calls. Failure is not fatal.
+    unsafe { libc::fcntl(wr.as_raw_fd(), libc::F_SETPIPE_SZ, PIPE_SIZE) };
+    Ok((rd, wr))
+}
+
+/// Convert return value of a libc call to io::Result.
+fn cvt(n: libc::ssize_t) -> io::Result<usize> {
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
@@ -0,0 +1,149 @@
+/*  Bookkeeping of client sessions, shared by the blocking and the async
+    server implementations. The task is complete when all sessions opened
+    by adnet-agent have been closed. adnet-agent may open a new connection
+    right after closing the previous one, so the servers wait for a grace
+    period with no active sessions before declaring the task complete,
+    unless they know how many sessions to expect.
+*/
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Statistics collected from one client session.
+pub struct SessionStats {
+    pub id: usize,
+    pub address: SocketAddr,
+    pub requests: u64,
+    pub bytes: u64,
+    pub duration: Duration,
+    pub busy: Duration,  // time spent writing responses
+    writing_since: Option<Instant>,
+}
+
+impl SessionStats {
+    pub fn new(id: usize, address: SocketAddr) -> SessionStats {
+        SessionStats {
+            id,
+            address,
+            requests: 0,
+            bytes: 0,
+            duration: Duration::ZERO,
+            busy: Duration::ZERO,
+            writing_since: None,
+        }
+    }
+
+    /// Call when starting to write a response.
+    pub fn start_writing(&mut self) {
+        self.writing_since = Some(Instant::now());
+    }
+
+    /// Call when a response is written, or writing was interrupted.
+    pub fn stop_writing(&mut self) {
+        if let Some(since) = self.writing_since.take() {
+            self.busy += since.elapsed();
+        }
+    }
+}
+
+/// Keeps track of active sessions and statistics of the finished ones.
+pub struct Sessions {
+    active: usize,
+    next_id: usize,
+    finished: Vec<SessionStats>,
+    start: Option<Instant>,
+    idle_since: Option<Instant>,  // when the last active session closed
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions {
+            active: 0,
+            next_id: 1,
+            finished: Vec::new(),
+            start: None,
+            idle_since: None,
+        }
+    }
+
+    /// New session was accepted. Returns identifier for the session.
+    pub fn open(&mut self) -> usize {
+        self.start.get_or_insert_with(Instant::now);
+        self.idle_since = None;
+        self.active += 1;
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Session was closed. Returns true if it was the last active session.
+    pub fn close(&mut self, stats: SessionStats) -> bool {
+        self.active -= 1;
+        self.finished.push(stats);
+        if self.active == 0 {
+            self.idle_since = Some(Instant::now());
+        }
+        self.active == 0
+    }
+
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Whether the task is complete without waiting any longer: no sessions
+    /// are active, and `expected` sessions (if given) have finished.
+    pub fn all_expected_closed(&self, expected: Option<usize>) -> bool {
+        self.active == 0 && expected.is_some_and(|n| self.finished.len() >= n)
+    }
+
+    /// When the grace period for new connections ends, if no sessions are
+    /// active. None while sessions are active or before the first one.
+    pub fn grace_deadline(&self, grace: Duration) -> Option<Instant> {
+        self.idle_since.map(|since| since + grace)
+    }
+
+    /// Print summary of finished sessions, and start collecting a new round.
+    /// The grace period after the last session does not count in duration.
+    pub fn print_summary(&mut self) {
+        let end = self.idle_since.take().unwrap_or_else(Instant::now);
+        let elapsed = self.start.take().map(|s| end - s).unwrap_or_default();
+        let sessions = &mut self.finished;
+        sessions.sort_by_key(|s| s.id);
+        let requests: u64 = sessions.iter().map(|s| s.requests).sum();
+        let bytes: u64 = sessions.iter().map(|s| s.bytes).sum();
+
+        println!("--- Summary ---");
+        println!("Connections: {}", sessions.len());
+        println!("Requests:    {}", requests);
+        println!("Bytes:       {}", bytes);
+        println!(
+            "Duration:    {}.{:03} seconds",
+            elapsed.as_secs(),
+            elapsed.subsec_millis()
+        );
+        println!("Throughput:  {:.1} Mbit/s", mbit_per_sec(bytes, elapsed));
+        for s in sessions.iter() {
+            // Per-connection throughput is calculated over the time spent
+            // writing, so that waiting for the next request does not count.
+            println!(
+                "  #{} {}: {} requests, {} bytes, {}.{:03} seconds, {:.1} Mbit/s",
+                s.id,
+                s.address,
+                s.requests,
+                s.bytes,
+                s.duration.as_secs(),
+                s.duration.subsec_millis(),
+                mbit_per_sec(s.bytes, s.busy)
+            );
+        }
+        sessions.clear();
+    }
+}
+
+fn mbit_per_sec(bytes: u64, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        return 0.0;
+    }
+    bytes as f64 * 8.0 / duration.as_secs_f64() / 1_000_000.0
+}
@@ -0,0 +1,228 @@
+/*  Thread-per-client implementation of task-srv. Accepting runs in its own
+    thread, and the main thread follows the sessions through a channel.
+*/
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::fd::AsRawFd,
+    process,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    args::Args,
+    protocol::{control_message, Request, REQUEST_LEN},
+    response::{Engine, FillBuffer, FillCache, Responder},
+    session::{SessionStats, Sessions},
+};
+
+/// Events delivered from the accepting thread and client threads to the main
+/// thread, that keeps track of active sessions.
+enum Event {
+    Accepted(TcpStream, SocketAddr),
+    Closed(SessionStats),
+}
+
+pub fn run(args: &Args) {
+    println!("Task-SRV starting");
+
+    // Bind listening socket
+    let listener = TcpListener::bind(args.listen_addr()).unwrap_or_else(|e| {
+        eprintln!("Failed to bind {}: {}", args.listen_addr(), e);
+        process::exit(1);
+    });
+    println!("Listening on {}", args.listen_addr());
+
+    // Send control message to adnet-agent
+    let mut agent = TcpStream::connect(args.agent_addr()).unwrap_or_else(|e| {
+        eprintln!("Failed to connect to {}: {}", args.agent_addr(), e);
+        process::exit(1);
+    });
+    let command = control_message(args.keyword(), args.my_addr());
+    agent.write_all(command.as_bytes()).unwrap_or_else(|e| {
+        eprintln!("Failed to send command: {}", e);
+        process::exit(1);
+    });
+    println!("Sent: {}", command);
+
+    // All events are delivered to the main thread through a channel. Accepting
+    // is done in a separate thread, so that the main thread can notice when
+    // the last session closes even though accept would still be blocking.
+    let (tx, rx) = mpsc::channel();
+    let accept_tx = tx.clone();
+    thread::spawn(move || accept_loop(listener, accept_tx));
+
+    let mut sessions = Sessions::new();
+
+    // Fill buffers are shared by all client threads
+    let engine = args.engine();
+    let cache = Arc::new(FillCache::new(engine));
+
+    // The channel never disconnects, because we hold a sender ourselves.
+    loop {
+        // While no sessions are active, wait for a new one only until the
+        // grace period ends
+        let event = match sessions.grace_deadline(args.grace()) {
+            Some(deadline) => match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    // All sessions opened by adnet-agent are now closed.
+                    sessions.print_summary();
+                    if !args.keep_serving() {
+                        break;
+                    }
+                    println!("Waiting for new connections");
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+        };
+        match event {
+            Event::Accepted(socket, address) => {
+                println!("Accepted connection from {}", address);
+                let id = sessions.open();
+
+                // Spawn a thread per client. Each thread reports its statistics
+                // back when the client has closed the connection.
+                let client_tx = tx.clone();
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    let stats = process_client(socket, id, address, engine, &cache);
+                    let _ = client_tx.send(Event::Closed(stats));
+                });
+            }
+            Event::Closed(stats) => {
+                let address = stats.address;
+                sessions.close(stats);
+                println!("Connection from {} closed, {} still active", address, sessions.active());
+                if sessions.all_expected_closed(args.sessions()) {
+                    sessions.print_summary();
+                    if !args.keep_serving() {
+                        break;
+                    }
+                    println!("Waiting for new connections");
+                }
+            }
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, tx: Sender<Event>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(socket) => {
+                let addr = match socket.peer_addr() {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        eprintln!("Could not get peer address: {}", e);
+                        continue;
+                    }
+                };
+                if tx.send(Event::Accepted(socket, addr)).is_err() {
+                    // Main thread has finished
+                    return;
+                }
+            }
+            Err(e) => {
+                eprintln!("Accept error: {}", e);
+            }
+        }
+    }
+}
+
+fn process_client(
+    mut socket: TcpStream,
+    id: usize,
+    address: SocketAddr,
+    engine: Engine,
+    cache: &FillCache,
+) -> SessionStats {
+    let start = Instant::now();
+    let mut stats = SessionStats::new(id, address);
+
+    let mut responder = match Responder::new(engine) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Could not set up {:?} engine: {}", engine, e);
+            return stats;
+        }
+    };
+
+    loop {
+        let mut header = [0u8; REQUEST_LEN];
+        if read_exact(&mut socket, &mut header).is_err() {
+            break;
+        }
+        let request = Request::from_bytes(&header);
+        stats.requests += 1;
+
+        // Write `total` bytes filled with `character`, using the shared buffer
+        let fill = match cache.get(request.character) {
+            Ok(fill) => fill,
+            Err(e) => {
+                eprintln!("Could not create fill buffer: {}", e);
+                break;
+            }
+        };
+        stats.start_writing();
+        let result = send_fill(&socket, &mut responder, &fill, request.total, &mut stats.bytes);
+        stats.stop_writing();
+        if let Err(e) = result {
+            eprintln!("Write error: {}", e);
+            break;
+        }
+
+        println!("Wrote {} bytes of byte {}", request.total, request.character);
+    }
+
+    stats.duration = start.elapsed();
+    stats
+}
+
+/// Write `total` bytes from `fill` to socket. `written` is updated as data
+/// goes out, so that the caller knows how much was sent also if there is an
+/// error.
+fn send_fill(
+    socket: &TcpStream,
+    responder: &mut Responder,
+    fill: &FillBuffer,
+    total: u64,
+    written: &mut u64,
+) -> io::Result<()> {
+    let mut remaining = total;
+    while remaining > 0 {
+        match responder.send_chunk(socket.as_raw_fd(), fill, remaining) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                remaining -= n as u64;
+                *written += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn read_exact(stream: &mut TcpStream, buf: &mut [u8]) -> Result<(), io::Error> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match stream.read(&mut buf[pos..]) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "closed")),
+            Ok(n) => pos += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
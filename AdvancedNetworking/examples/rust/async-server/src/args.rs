@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use clap::Parser;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Address to bind, as <IP>:<port>.
+    address: String,
+
+    /// Maximum number of clients served at the same time. Connections above
+    /// the limit are closed right after accepting.
+    #[arg(short, long, default_value_t = 100)]
+    max_connections: usize,
+
+    /// Seconds to wait for a single read or write before the connection is
+    /// closed.
+    #[arg(short, long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
+    /// Total echo bandwidth of all clients together, in bytes per second.
+    /// 0 means no limit.
+    #[arg(short, long, default_value_t = 0)]
+    rate: u64,
+
+    /// Address for the metrics endpoint, as <IP>:<port>. Try it with curl.
+    #[arg(short = 'M', long)]
+    metrics_addr: Option<String>,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn address(&self) -> &String {
+        &self.address
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn metrics_addr(&self) -> Option<&String> {
+        self.metrics_addr.as_ref()
+    }
+}
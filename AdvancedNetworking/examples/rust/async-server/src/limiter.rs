@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::Mutex,
+    time::{self, Instant},
+};
+
+/// Token bucket shared by all client tasks, limiting the total bandwidth.
+///
+/// The bucket fills at `rate` bytes per second, up to one second worth of
+/// data. A task that wants to send takes bytes from the bucket, and if there
+/// are not enough, sleeps until the bucket has filled up again. The bucket may
+/// go negative, so that a write larger than the bucket is also possible.
+pub struct Limiter {
+    rate: u64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Limiter {
+    /// Rate 0 means unlimited.
+    pub fn new(rate: u64) -> Limiter {
+        Limiter {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until `bytes` can be sent without exceeding the rate.
+    pub async fn consume(&self, bytes: usize) {
+        if self.rate == 0 {
+            return;
+        }
+
+        // Holding the lock while sleeping makes the tasks queue up in order,
+        // because tokio Mutex is fair.
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        bucket.updated = now;
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens < 0.0 {
+            let wait = Duration::from_secs_f64(-bucket.tokens / self.rate as f64);
+            time::sleep(wait).await;
+        }
+    }
+}
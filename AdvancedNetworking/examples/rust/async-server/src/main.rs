@@ -2,81 +2,265 @@
  * For each incoming connection, a new async task is spawned to handle the connection.
  * For each connection, read incoming data and echo it back, until connection closes.
  * Bind to "0.0.0.0:<port>" if connections are allowed from any interface.
+ *
+ * The number of concurrent clients is limited with a semaphore: a connection
+ * that arrives when all permits are taken is closed immediately. Reads and
+ * writes that take longer than the timeout close the connection, and the
+ * total echo bandwidth of all clients can be limited. Optionally, counters
+ * of the server are available from a separate metrics address over HTTP.
+ *
+ * On SIGINT (Ctrl-C) or SIGTERM the server stops accepting new connections,
+ * waits for the active tasks to finish within a deadline, cancels those that
+ * did not, prints how many bytes were echoed to each client, and exits.
+ * A second signal exits immediately.
  * 
- * Usage: cargo run -- <IP>:<port>
+ * Usage: cargo run -- <IP>:<port> [--max-connections N] [--timeout SECS]
+ *                     [--rate BYTES_PER_SEC] [--metrics-addr <IP>:<port>]
  */
 
 use std::{
-    env,
     error::Error,
     net::SocketAddr,
+    process,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     // different from the std versions. These support async use
     net::{TcpListener, TcpStream},
-    task,
+    signal::{self, unix::SignalKind},
+    sync::{watch, OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time,
 };
 
+use crate::{args::Args, limiter::Limiter, metrics::Metrics};
+
+// How long active connections may take after shutdown was requested.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long to wait before accepting again after accept failed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// State shared by all client tasks.
+struct Shared {
+    limiter: Limiter,
+    metrics: Arc<Metrics>,
+    timeout: Duration,
+}
+
 
 // The below annotation sets up the tokio runtime for task scheduling with async/await.
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Collect command-line arguments into a vector
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() != 2 {
-        eprintln!("arguments: <host>:<port>");
-        return Err("Invalid command".into());
-    }
+    let args = Args::new();
 
     // Create a passive server socket and bind to address given as command line argument.
     // If there is an error in bind, exit the main function with error.
     // Note that tokio version of bind uses await to allow scheduling of other tasks.
-    let server = TcpListener::bind(&args[1]).await?;
+    let server = TcpListener::bind(args.address()).await?;
+
+    let metrics = Arc::new(Metrics::new());
+    if let Some(addr) = args.metrics_addr() {
+        let listener = TcpListener::bind(addr).await?;
+        println!("Metrics available at http://{}/", addr);
+        tokio::spawn(Arc::clone(&metrics).serve(listener));
+    }
+
+    // Each client task holds one permit for as long as it runs
+    let permits = Arc::new(Semaphore::new(args.max_connections()));
+    let shared = Arc::new(Shared {
+        limiter: Limiter::new(args.rate()),
+        metrics: Arc::clone(&metrics),
+        timeout: args.timeout(),
+    });
+
+    // Signal streams are created once, so that no signal arriving between
+    // select! iterations is lost.
+    let mut sigint = signal::unix::signal(SignalKind::interrupt())?;
+    let mut sigterm = signal::unix::signal(SignalKind::terminate())?;
+
+    // Changing the value in this channel tells client tasks to stop.
+    let (cancel_tx, cancel_rx) = watch::channel(false);
+
+    // JoinSet keeps track of spawned tasks, and gives their return values
+    // (address and bytes echoed) when they finish.
+    let mut tasks = JoinSet::new();
+    let mut finished: Vec<(SocketAddr, u64)> = Vec::new();
 
     loop {
-        // Wait until new connection request comes in.
-        // accept returns active socket and address of the connecting host as tuple.
-        // tokio await prevents blocking here
-        let (socket, address) = server.accept().await?;
-        println!("Accepting connection from {}", address.to_string());
-
-        // Spawn a new tokio task to handle communication with the client.
-        task::spawn(async move {
-            process_client(socket, address).await;
-        });
+        // Wait for whichever happens first.
+        tokio::select! {
+            // Wait until new connection request comes in.
+            // accept returns active socket and address of the connecting host as tuple.
+            // tokio await prevents blocking here
+            accepted = server.accept() => {
+                // Accept fails for example when the process runs out of
+                // file descriptors. Other clients may still finish, so wait
+                // a moment and try again.
+                let (socket, address) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        println!("Error accepting connection: {}", e);
+                        time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+
+                // If all permits are taken, drop the socket, which closes it.
+                let permit = match Arc::clone(&permits).try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        println!("Too many connections, rejecting {}", address);
+                        metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                };
+                println!("Accepting connection from {}", address);
+                metrics.accepted.fetch_add(1, Ordering::Relaxed);
+
+                // Spawn a new tokio task to handle communication with the client.
+                tasks.spawn(process_client(
+                    socket, address, permit, Arc::clone(&shared), cancel_rx.clone()));
+            }
+            // Collect the results of finished tasks as we go
+            Some(result) = tasks.join_next() => {
+                if let Ok(client) = result {
+                    finished.push(client);
+                }
+            }
+            _ = sigint.recv() => break,
+            _ = sigterm.recv() => break,
+        }
     }
+
+    // Stop accepting, new connection attempts are refused
+    drop(server);
+    println!("Shutting down, waiting up to {} seconds for {} active clients",
+        DRAIN_TIMEOUT.as_secs(), tasks.len());
+
+    // Wait for the remaining tasks. If the deadline passes, tell them to stop,
+    // and keep waiting, which should not take long anymore.
+    let deadline = time::sleep(DRAIN_TIMEOUT);
+    tokio::pin!(deadline);
+    let mut cancelled = false;
+    loop {
+        tokio::select! {
+            result = tasks.join_next() => match result {
+                Some(Ok(client)) => finished.push(client),
+                Some(Err(e)) => println!("Task failed: {}", e),
+                None => break,  // all tasks done
+            },
+            _ = &mut deadline, if !cancelled => {
+                let _ = cancel_tx.send(true);
+                cancelled = true;
+            }
+            _ = sigint.recv() => {
+                println!("Second signal, exiting immediately");
+                process::exit(1);
+            }
+            _ = sigterm.recv() => {
+                println!("Second signal, exiting immediately");
+                process::exit(1);
+            }
+        }
+    }
+
+    println!("Bytes echoed per client:");
+    for (address, n) in finished {
+        println!("  {}: {}", address, n);
+    }
+    print!("{}", metrics.report());
+    Ok(())
 }
 
 
-async fn process_client(mut socket: TcpStream, address: SocketAddr) {
+// Echo data until the client closes the connection, or the task is told to
+// stop. Returns client address and the number of bytes echoed. The permit is
+// released when the function returns.
+async fn process_client(
+    mut socket: TcpStream,
+    address: SocketAddr,
+    _permit: OwnedSemaphorePermit,
+    shared: Arc<Shared>,
+    mut cancel: watch::Receiver<bool>,
+) -> (SocketAddr, u64) {
+    let metrics = &shared.metrics;
+    metrics.active.fetch_add(1, Ordering::Relaxed);
+
+    let mut echoed: u64 = 0;
     loop {
         let mut buf: [u8; 160] = [0; 160];
-        // Test whether read call returns Ok or Err result variant
-        match socket.read(&mut buf).await {
-            Ok(n) => {
+        // Test whether read call returns Ok or Err result variant.
+        // At the same time, watch for cancellation. The read is wrapped in
+        // timeout, which gives Err if the read did not finish in time.
+        let result = tokio::select! {
+            result = time::timeout(shared.timeout, socket.read(&mut buf)) => result,
+            _ = cancel.changed() => {
+                println!("Closing connection to {}", address);
+                break;
+            }
+        };
+        match result {
+            Ok(Ok(n)) => {
                 // return value of 0 bytes means that socket is closed by
                 // the other end.
                 if n == 0 {
-                    println!("Client {} closed connection", address.to_string());
-                    break;  // exit the function and terminate the thread
+                    println!("Client {} closed connection", address);
+                    break;  // exit the function and terminate the task
                 }
-                println!("read {} bytes from client {}", n, address.to_string());
+                println!("read {} bytes from client {}", n, address);
 
-                // Echo the same bytes back to client. 
-                if let Err(e) = socket.write(&buf[..n]).await {
-                    // If there is an error, terminate task
-                    println!("Error writing to client: {}", e);
-                    break;
+                // Wait for our share of the bandwidth, then echo the same
+                // bytes back to client. With a low rate or a slow client
+                // this can take long, so watch for cancellation here too.
+                let result = tokio::select! {
+                    result = async {
+                        shared.limiter.consume(n).await;
+                        time::timeout(shared.timeout, socket.write_all(&buf[..n])).await
+                    } => result,
+                    _ = cancel.changed() => {
+                        println!("Closing connection to {}", address);
+                        break;
+                    }
+                };
+                match result {
+                    Ok(Ok(())) => {
+                        echoed += n as u64;
+                        metrics.bytes.fetch_add(n as u64, Ordering::Relaxed);
+                    }
+                    Ok(Err(e)) => {
+                        // If there is an error, terminate task
+                        println!("Error writing to client: {}", e);
+                        break;
+                    }
+                    Err(_) => {
+                        println!("Write to {} timed out", address);
+                        metrics.timed_out.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
                 }
             },
-            Err(e) => {
+            Ok(Err(e)) => {
                 println!("Error reading from {}: {}",
-                    address.to_string(), e);
+                    address, e);
                 break;  // terminating task
             }
+            Err(_) => {
+                println!("Client {} idle for too long", address);
+                metrics.timed_out.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
         }
     }
-}
\ No newline at end of file
+
+    metrics.active.fetch_sub(1, Ordering::Relaxed);
+    (address, echoed)
+}
+
+
+mod args;
+mod limiter;
+mod metrics;
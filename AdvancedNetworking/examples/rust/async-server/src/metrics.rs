@@ -0,0 +1,74 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Counters updated by the server tasks. Atomics are enough, because the
+/// values do not need to be consistent with each other.
+#[derive(Default)]
+pub struct Metrics {
+    pub active: AtomicU64,
+    pub accepted: AtomicU64,
+    pub rejected: AtomicU64,
+    pub timed_out: AtomicU64,
+    pub bytes: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Current values as text, one counter per line.
+    pub fn report(&self) -> String {
+        let mut text = String::new();
+        for (name, counter) in [
+            ("active_tasks", &self.active),
+            ("accepted_connections", &self.accepted),
+            ("rejected_connections", &self.rejected),
+            ("timed_out_connections", &self.timed_out),
+            ("bytes_echoed", &self.bytes),
+        ] {
+            let _ = writeln!(text, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+        text
+    }
+
+    /// Answer every connection to `listener` with a minimal HTTP response
+    /// containing the report, so that it can be fetched with curl or a
+    /// browser. The request itself is not parsed.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (mut socket, _) = match listener.accept().await {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Metrics accept error: {}", e);
+                    continue;
+                }
+            };
+            let metrics = Arc::clone(&self);
+            tokio::spawn(async move {
+                // Read the request, so that closing the socket does not reset
+                // the connection while the client is still sending.
+                let mut buf = [0; 1024];
+                let _ = socket.read(&mut buf).await;
+
+                let body = metrics.report();
+                let response = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+}
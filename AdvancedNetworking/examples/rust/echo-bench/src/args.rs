@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use clap::Parser;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Address of the echo server, as <host>:<port>.
+    address: String,
+
+    /// Number of concurrent connections.
+    #[arg(short, long, default_value_t = 10)]
+    connections: usize,
+
+    /// Size of each message in bytes (at least 8).
+    #[arg(short, long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(8..))]
+    size: u32,
+
+    /// How long to run the benchmark, in seconds.
+    #[arg(short, long, default_value_t = 10)]
+    duration: u64,
+
+    /// Total messages per second over all connections. 0 means closed loop:
+    /// each connection sends the next message as soon as the previous echo
+    /// has arrived.
+    #[arg(short, long, default_value_t = 0)]
+    rate: u64,
+
+    /// Open a new connection for every message, in closed loop. Needed for
+    /// simple-server, that closes the connection after one echo.
+    #[arg(long, conflicts_with = "rate")]
+    reconnect: bool,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn address(&self) -> &String {
+        &self.address
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration)
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn reconnect(&self) -> bool {
+        self.reconnect
+    }
+}
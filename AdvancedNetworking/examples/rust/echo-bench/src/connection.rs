@@ -0,0 +1,202 @@
+/* One benchmark connection. Messages carry a sequence number followed by a
+ * pattern that depends on it, so that the echo can be checked byte by byte:
+ * a server that loses, duplicates or reorders data is noticed.
+ *
+ * Closed loop: the next message is sent when the previous echo has arrived.
+ * Throughput is then limited by latency, and a slow server gets less load.
+ * With reconnect, every message uses a new connection, and the latency
+ * includes the connection setup.
+ *
+ * Open loop: messages are sent on a fixed schedule whether or not the echoes
+ * have arrived, from a separate thread. Latency is measured from the time
+ * the message was scheduled to be sent, not when it was actually sent, so
+ * that a server that stalls the sender gets charged for the delay too.
+ */
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use hdrhistogram::Histogram;
+
+// How long we wait for an echo before giving up on the connection.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Latencies are recorded in microseconds, up to one minute.
+const MAX_LATENCY_US: u64 = 60_000_000;
+
+pub struct Results {
+    pub messages: u64,              // messages echoed correctly
+    pub errors: u64,                // connections that ended in error
+    pub latency: Histogram<u64>,    // in microseconds
+}
+
+impl Results {
+    pub fn new() -> Results {
+        Results {
+            messages: 0,
+            errors: 0,
+            // 3 significant digits is plenty for latencies
+            latency: Histogram::new_with_bounds(1, MAX_LATENCY_US, 3).unwrap(),
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        self.messages += 1;
+        self.latency.saturating_record(latency.as_micros() as u64);
+    }
+
+    fn error(&mut self, what: &str, e: io::Error) {
+        println!("{}: {}", what, e);
+        self.errors += 1;
+    }
+
+    /// Combine results of another connection into these.
+    pub fn add(&mut self, other: &Results) {
+        self.messages += other.messages;
+        self.errors += other.errors;
+        self.latency.add(&other.latency).unwrap();
+    }
+}
+
+
+/// Send a message, wait for its echo, repeat until `deadline`.
+pub fn closed_loop(mut socket: TcpStream, size: usize, deadline: Instant) -> Results {
+    let mut results = Results::new();
+    if let Err(e) = socket.set_read_timeout(Some(READ_TIMEOUT)) {
+        results.error("Could not set timeout", e);
+        return results;
+    }
+
+    let mut message = vec![0u8; size];
+    let mut seq: u64 = 0;
+    while Instant::now() < deadline {
+        fill_message(&mut message, seq);
+        let sent = Instant::now();
+        if let Err(e) = echo_message(&mut socket, &message, seq) {
+            results.error("Echo failed", e);
+            break;
+        }
+        results.record(sent.elapsed());
+        seq += 1;
+    }
+    results
+}
+
+
+/// Like closed loop, but connect for every message.
+pub fn reconnect_loop(address: &str, size: usize, deadline: Instant) -> Results {
+    let mut results = Results::new();
+    let mut message = vec![0u8; size];
+    let mut seq: u64 = 0;
+    while Instant::now() < deadline {
+        fill_message(&mut message, seq);
+        let sent = Instant::now();
+        let result = TcpStream::connect(address).and_then(|mut socket| {
+            socket.set_nodelay(true)?;
+            socket.set_read_timeout(Some(READ_TIMEOUT))?;
+            echo_message(&mut socket, &message, seq)
+        });
+        if let Err(e) = result {
+            results.error("Echo failed", e);
+            break;
+        }
+        results.record(sent.elapsed());
+        seq += 1;
+    }
+    results
+}
+
+
+// Send message and check that the same comes back.
+fn echo_message(socket: &mut TcpStream, message: &[u8], seq: u64) -> io::Result<()> {
+    socket.write_all(message)?;
+    // The server may echo the message in several pieces
+    let mut echo = vec![0u8; message.len()];
+    socket.read_exact(&mut echo)?;
+    if echo != message {
+        return Err(io::Error::other(format!("echo of message {} differs", seq)));
+    }
+    Ok(())
+}
+
+
+/// Send a message every `interval` starting from `start` until `deadline`,
+/// and read the echoes at the same time.
+pub fn open_loop(
+    socket: TcpStream,
+    size: usize,
+    interval: Duration,
+    start: Instant,
+    deadline: Instant,
+) -> Results {
+    let mut results = Results::new();
+    let mut reader = match socket.try_clone() {
+        Ok(s) => s,
+        Err(e) => {
+            results.error("Could not clone socket", e);
+            return results;
+        }
+    };
+    if let Err(e) = reader.set_read_timeout(Some(READ_TIMEOUT)) {
+        results.error("Could not set timeout", e);
+        return results;
+    }
+
+    // Sender tells the scheduled send time of each message through a
+    // channel. TCP keeps the order, so echoes arrive in the same order.
+    let (tx, rx) = mpsc::channel::<Instant>();
+    let sender = thread::spawn(move || {
+        let mut socket = socket;
+        let mut message = vec![0u8; size];
+        let mut seq: u64 = 0;
+        let mut next = start;
+        while next < deadline {
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+            fill_message(&mut message, seq);
+            if socket.write_all(&message).is_err() || tx.send(next).is_err() {
+                break;  // the receiving side reports the problem
+            }
+            seq += 1;
+            next += interval;
+        }
+        // Tell the server we are done, it closes after the last echo.
+        let _ = socket.shutdown(Shutdown::Write);
+    });
+
+    let mut expected = vec![0u8; size];
+    let mut echo = vec![0u8; size];
+    for (seq, scheduled) in rx.iter().enumerate() {
+        if let Err(e) = reader.read_exact(&mut echo) {
+            results.error("Read failed", e);
+            break;
+        }
+        fill_message(&mut expected, seq as u64);
+        if echo != expected {
+            results.error("Echo failed", io::Error::other(format!("echo of message {} differs", seq)));
+            break;
+        }
+        results.record(scheduled.elapsed());
+    }
+
+    // If we stopped early, the sender may be blocked in write. Shutting down
+    // the socket makes the write fail.
+    let _ = reader.shutdown(Shutdown::Both);
+    let _ = sender.join();
+    results
+}
+
+
+// Message starts with its sequence number, the rest is filled with a byte
+// that changes from message to message.
+fn fill_message(message: &mut [u8], seq: u64) {
+    message[..8].copy_from_slice(&seq.to_be_bytes());
+    message[8..].fill((seq % 251) as u8);
+}
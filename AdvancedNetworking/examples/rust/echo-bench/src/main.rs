@@ -0,0 +1,130 @@
+/* Benchmark client for the echo servers. Opens a number of concurrent
+ * connections, sends messages of given size and checks that they come back
+ * unchanged. At the end, reports throughput and latency percentiles.
+ *
+ * By default each connection runs in closed loop: the next message is sent
+ * when the previous echo has arrived. With "--rate", messages are sent at a
+ * fixed total rate instead (open loop), which shows how latency grows as the
+ * server gets closer to its capacity. See connection.rs for details.
+ *
+ * Each connection is served by its own thread (two in open loop mode), so
+ * that the client itself is simple and the server is the bottleneck.
+ *
+ * Try with the different servers, for example:
+ *   cargo run -- --connections 50 --size 160 127.0.0.1:2000
+ *   cargo run -- --connections 50 --rate 20000 127.0.0.1:2000
+ * simple-server echoes one message per connection, and serves one connection
+ * at a time, so use "--reconnect --connections 1" with it.
+ *
+ * Usage: cargo run -- [--connections N] [--size BYTES] [--duration SECS]
+ *                     [--rate MSGS_PER_SEC | --reconnect] <host>:<port>
+ */
+
+use std::{
+    error::Error,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    args::Args,
+    connection::{closed_loop, open_loop, reconnect_loop, Results},
+};
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::new();
+    let n = args.connections();
+    let size = args.size();
+
+    if args.reconnect() {
+        let start = Instant::now();
+        let deadline = start + args.duration();
+        let handles: Vec<_> = (0..n)
+            .map(|_| {
+                let address = args.address().clone();
+                thread::spawn(move || reconnect_loop(&address, size, deadline))
+            })
+            .collect();
+        let total = collect(handles);
+        print_results(&args, &total, start.elapsed().as_secs_f64());
+        return Ok(());
+    }
+
+    // Open all connections before starting the clock
+    let mut sockets = Vec::with_capacity(n);
+    for _ in 0..n {
+        let socket = TcpStream::connect(args.address())?;
+        // Send small messages right away, instead of waiting for the ACK of
+        // the previous segment (Nagle's algorithm).
+        socket.set_nodelay(true)?;
+        sockets.push(socket);
+    }
+    println!("Connected {} connections to {}", n, args.address());
+
+    let start = Instant::now();
+    let deadline = start + args.duration();
+
+    // In open loop each connection sends its share of the total rate. The
+    // connections start at different times, so that the messages do not
+    // leave in bursts.
+    let interval = (args.rate() > 0)
+        .then(|| Duration::from_secs_f64(n as f64 / args.rate() as f64));
+
+    let handles: Vec<_> = sockets.into_iter().enumerate()
+        .map(|(i, socket)| thread::spawn(move || match interval {
+            Some(interval) => {
+                let offset = interval.mul_f64(i as f64 / n as f64);
+                open_loop(socket, size, interval, start + offset, deadline)
+            }
+            None => closed_loop(socket, size, deadline),
+        }))
+        .collect();
+
+    let total = collect(handles);
+    print_results(&args, &total, start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+
+// Wait for the connection threads, and combine their results.
+fn collect(handles: Vec<thread::JoinHandle<Results>>) -> Results {
+    let mut total = Results::new();
+    for handle in handles {
+        total.add(&handle.join().expect("connection thread panicked"));
+    }
+    total
+}
+
+
+fn print_results(args: &Args, results: &Results, elapsed: f64) {
+    let mode = match args.rate() {
+        _ if args.reconnect() => "closed loop, new connection per message".to_string(),
+        0 => "closed loop".to_string(),
+        rate => format!("open loop, {} msg/s", rate),
+    };
+    println!("{} connections, {} byte messages, {}", args.connections(), args.size(), mode);
+    println!("Messages: {} in {:.2} s, {} connections failed",
+        results.messages, elapsed, results.errors);
+
+    let rate = results.messages as f64 / elapsed;
+    let mbits = rate * args.size() as f64 * 8.0 / 1_000_000.0;
+    println!("Throughput: {:.0} msg/s, {:.2} Mbit/s each direction", rate, mbits);
+
+    if results.messages == 0 {
+        return;
+    }
+    let h = &results.latency;
+    println!("Latency (us): min {}  p50 {}  p90 {}  p99 {}  p99.9 {}  max {}",
+        h.min(),
+        h.value_at_quantile(0.5),
+        h.value_at_quantile(0.9),
+        h.value_at_quantile(0.99),
+        h.value_at_quantile(0.999),
+        h.max());
+}
+
+
+mod args;
+mod connection;
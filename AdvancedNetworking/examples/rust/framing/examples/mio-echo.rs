@@ -0,0 +1,93 @@
+/* Framed echo server using MIO and nonblocking::FrameBuffer. Each complete
+ * message is echoed back as one message, however the bytes were split into
+ * TCP segments. Messages are also printed, so it is easy to see that a
+ * message split over several reads is handled as one.
+ *
+ * Usage: cargo run --example mio-echo -- <IP>:<port> <length|newline|fixed:N>
+ */
+
+use std::{collections::HashMap, env, error::Error, io};
+
+use framing::{nonblocking::FrameBuffer, Codec};
+use mio::{net::TcpListener, net::TcpStream, Events, Interest, Poll, Token};
+
+const LISTEN_TOKEN: Token = Token(0);
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("arguments: <host>:<port> <length|newline|fixed:N>");
+        return Err("Invalid command".into());
+    }
+    let codec: Codec = args[2].parse()?;
+
+    let mut server = TcpListener::bind(args[1].parse()?)?;
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(128);
+    poll.registry().register(&mut server, LISTEN_TOKEN, Interest::READABLE)?;
+
+    // Simple token allocation is enough for this example
+    let mut clients: HashMap<Token, (TcpStream, FrameBuffer)> = HashMap::new();
+    let mut next_token = 1;
+
+    loop {
+        poll.poll(&mut events, None)?;
+        for event in events.iter() {
+            if event.token() == LISTEN_TOKEN {
+                while let Ok((mut socket, address)) = server.accept() {
+                    println!("Accepting connection from {}", address);
+                    let token = Token(next_token);
+                    next_token += 1;
+                    poll.registry().register(&mut socket, token, Interest::READABLE)?;
+                    clients.insert(token, (socket, FrameBuffer::new(codec)));
+                }
+                continue;
+            }
+            let Some((socket, frames)) = clients.get_mut(&event.token()) else {
+                continue;
+            };
+
+            let result = echo(socket, frames);
+
+            // Writable events are needed only if the socket did not take
+            // everything.
+            let interest = if frames.wants_write() {
+                Interest::READABLE | Interest::WRITABLE
+            } else {
+                Interest::READABLE
+            };
+            let done = frames.is_closed() && !frames.wants_write();
+            match result {
+                Ok(()) if !done => poll.registry().reregister(socket, event.token(), interest)?,
+                Ok(()) => {
+                    println!("Client closed connection");
+                    clients.remove(&event.token());
+                }
+                Err(e) => {
+                    println!("Error serving client: {}", e);
+                    clients.remove(&event.token());
+                }
+            }
+        }
+    }
+}
+
+
+// Read what is available, and echo all complete frames.
+fn echo(socket: &mut TcpStream, frames: &mut FrameBuffer) -> io::Result<()> {
+    loop {
+        // The buffer may fill up before the socket is drained. Then we take
+        // the frames and read more, because the readable event is not
+        // repeated for the data that is already waiting.
+        let full = frames.read_from(socket)?;
+        while let Some(message) = frames.next_frame()? {
+            println!("Message of {} bytes: {}", message.len(), String::from_utf8_lossy(&message));
+            frames.queue(&message)?;
+        }
+        if !full {
+            break;
+        }
+    }
+    frames.write_to(socket)
+}
@@ -0,0 +1,51 @@
+/* Framed echo server using tokio and asynchronous::AsyncFramed. Each complete
+ * message is echoed back as one message, and printed.
+ *
+ * Usage: cargo run --features tokio --example tokio-echo -- <IP>:<port> <length|newline|fixed:N>
+ */
+
+use std::{env, error::Error};
+
+use framing::{asynchronous::AsyncFramed, Codec};
+use tokio::net::TcpListener;
+
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("arguments: <host>:<port> <length|newline|fixed:N>");
+        return Err("Invalid command".into());
+    }
+    let codec: Codec = args[2].parse()?;
+
+    let server = TcpListener::bind(&args[1]).await?;
+    loop {
+        let (socket, address) = server.accept().await?;
+        println!("Accepting connection from {}", address);
+
+        tokio::spawn(async move {
+            let mut framed = AsyncFramed::new(socket, codec);
+            loop {
+                match framed.recv().await {
+                    Ok(Some(message)) => {
+                        println!("Message of {} bytes from {}: {}",
+                            message.len(), address, String::from_utf8_lossy(&message));
+                        if let Err(e) = framed.send(&message).await {
+                            println!("Error writing to {}: {}", address, e);
+                            break;
+                        }
+                    }
+                    Ok(None) => {
+                        println!("Client {} closed connection", address);
+                        break;
+                    }
+                    Err(e) => {
+                        println!("Error reading from {}: {}", address, e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::Codec;
+
+/// Sends and receives whole messages over a tokio stream, such as
+/// tokio::net::TcpStream. Same as blocking::Framed, but waiting is done with
+/// await, so other tasks can run meanwhile.
+pub struct AsyncFramed<S> {
+    stream: S,
+    codec: Codec,
+    inbound: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncFramed<S> {
+    pub fn new(stream: S, codec: Codec) -> AsyncFramed<S> {
+        AsyncFramed {
+            stream,
+            codec,
+            inbound: Vec::new(),
+        }
+    }
+
+    /// Send one message.
+    pub async fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::new();
+        self.codec.encode(payload, &mut frame)?;
+        self.stream.write_all(&frame).await
+    }
+
+    /// Receive one message. Returns None if the other end closed the
+    /// connection between messages.
+    pub async fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(payload) = self.codec.decode(&mut self.inbound)? {
+                return Ok(Some(payload));
+            }
+            match self.stream.read(&mut buf).await? {
+                0 if self.inbound.is_empty() => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => self.inbound.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::{self, Read, Write};
+
+use crate::Codec;
+
+/// Sends and receives whole messages over a blocking stream, such as
+/// std::net::TcpStream.
+pub struct Framed<S> {
+    stream: S,
+    codec: Codec,
+    inbound: Vec<u8>,  // received bytes not yet returned as frames
+}
+
+impl<S: Read + Write> Framed<S> {
+    pub fn new(stream: S, codec: Codec) -> Framed<S> {
+        Framed {
+            stream,
+            codec,
+            inbound: Vec::new(),
+        }
+    }
+
+    /// Send one message. Returns when all of it has been written.
+    pub fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::new();
+        self.codec.encode(payload, &mut frame)?;
+        self.stream.write_all(&frame)
+    }
+
+    /// Receive one message, reading as many times as needed. Returns None if
+    /// the other end closed the connection between messages. Closing in the
+    /// middle of a message is an error.
+    pub fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            // The previous read may have brought more than one frame
+            if let Some(payload) = self.codec.decode(&mut self.inbound)? {
+                return Ok(Some(payload));
+            }
+            match self.stream.read(&mut buf) {
+                Ok(0) if self.inbound.is_empty() => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.inbound.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stream stand-in that returns at most `step` bytes per read.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        step: usize,
+        written: Vec<u8>,
+    }
+
+    impl Trickle {
+        fn new(data: &[u8], step: usize) -> Trickle {
+            Trickle { data: data.to_vec(), pos: 0, step, written: Vec::new() }
+        }
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frames_arriving_in_pieces() {
+        let mut framed = Framed::new(Trickle::new(b"\0\0\0\x03abc\0\0\0\x01d", 2), Codec::LengthPrefixed);
+        assert_eq!(framed.recv().unwrap().as_deref(), Some(&b"abc"[..]));
+        assert_eq!(framed.recv().unwrap().as_deref(), Some(&b"d"[..]));
+        assert_eq!(framed.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn several_frames_in_one_read() {
+        let mut framed = Framed::new(Trickle::new(b"a\nb\nc\n", 100), Codec::Newline);
+        for expected in [b"a", b"b", b"c"] {
+            assert_eq!(framed.recv().unwrap().as_deref(), Some(&expected[..]));
+        }
+        assert_eq!(framed.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn eof_in_middle_of_frame_is_an_error() {
+        let mut framed = Framed::new(Trickle::new(b"complete\nparti", 3), Codec::Newline);
+        assert_eq!(framed.recv().unwrap().as_deref(), Some(&b"complete"[..]));
+        let err = framed.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn too_long_frame_is_an_error() {
+        let prefix = ((crate::codec::MAX_FRAME + 1) as u32).to_be_bytes();
+        let mut framed = Framed::new(Trickle::new(&prefix, 4), Codec::LengthPrefixed);
+        assert_eq!(framed.recv().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn send_writes_one_frame() {
+        let mut framed = Framed::new(Trickle::new(b"", 1), Codec::Fixed(3));
+        framed.send(b"xyz").unwrap();
+        assert!(framed.send(b"toolong").is_err());
+        assert_eq!(framed.into_inner().written, b"xyz");
+    }
+}
@@ -0,0 +1,225 @@
+use std::{fmt, io, str::FromStr};
+
+/// Longest message accepted by the length-prefixed codec. Without a limit,
+/// a corrupted or malicious length would make us allocate without bound.
+pub const MAX_FRAME: usize = 1024 * 1024;
+
+/// Length of the length prefix: 32-bit unsigned integer in network byte order.
+const PREFIX_LEN: usize = 4;
+
+/// Most bytes a complete frame can take with any codec. A receive buffer
+/// holding more than this has a complete frame or an error at its start.
+pub const MAX_FRAME_BYTES: usize = MAX_FRAME + PREFIX_LEN;
+
+/// How messages are separated in the byte stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// 4-byte big-endian length followed by the message.
+    LengthPrefixed,
+    /// Message followed by '\n'. The message cannot contain '\n'.
+    Newline,
+    /// Every message has exactly this many bytes, 1 to MAX_FRAME.
+    Fixed(usize),
+}
+
+impl Codec {
+    /// Append `payload` as a frame to `out`.
+    pub fn encode(&self, payload: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        self.check()?;
+        match *self {
+            Codec::LengthPrefixed => {
+                if payload.len() > MAX_FRAME {
+                    return Err(invalid("message too long"));
+                }
+                out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                out.extend_from_slice(payload);
+            }
+            Codec::Newline => {
+                if payload.contains(&b'\n') {
+                    return Err(invalid("message contains a line feed"));
+                }
+                out.extend_from_slice(payload);
+                out.push(b'\n');
+            }
+            Codec::Fixed(size) => {
+                if payload.len() != size {
+                    return Err(invalid(&format!("message must be {} bytes", size)));
+                }
+                out.extend_from_slice(payload);
+            }
+        }
+        Ok(())
+    }
+
+    /// Take one complete frame from the beginning of `buf`, and return its
+    /// payload. Returns None if `buf` does not yet contain a complete frame,
+    /// in which case more data must be read first.
+    pub fn decode(&self, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        self.check()?;
+        let (start, end, frame_len) = match *self {
+            Codec::LengthPrefixed => {
+                if buf.len() < PREFIX_LEN {
+                    return Ok(None);
+                }
+                let len = u32::from_be_bytes(buf[..PREFIX_LEN].try_into().unwrap()) as usize;
+                if len > MAX_FRAME {
+                    return Err(invalid("frame too long"));
+                }
+                (PREFIX_LEN, PREFIX_LEN + len, PREFIX_LEN + len)
+            }
+            Codec::Newline => match buf.iter().position(|&b| b == b'\n') {
+                Some(pos) => (0, pos, pos + 1),
+                None if buf.len() > MAX_FRAME => return Err(invalid("line too long")),
+                None => return Ok(None),
+            },
+            Codec::Fixed(size) => (0, size, size),
+        };
+        if buf.len() < frame_len {
+            return Ok(None);
+        }
+        let payload = buf[start..end].to_vec();
+        buf.drain(..frame_len);
+        Ok(Some(payload))
+    }
+
+    // Fixed-size frames of 0 bytes would be decoded forever from an empty
+    // buffer, and frames longer than MAX_FRAME would not fit in the buffers.
+    fn check(&self) -> io::Result<()> {
+        match *self {
+            Codec::Fixed(0) => Err(invalid("fixed frame size must be at least 1")),
+            Codec::Fixed(size) if size > MAX_FRAME => Err(invalid("fixed frame size too large")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses "length", "newline" or "fixed:<size>", e.g., from command line.
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Codec, String> {
+        match s {
+            "length" => Ok(Codec::LengthPrefixed),
+            "newline" => Ok(Codec::Newline),
+            _ => match s.strip_prefix("fixed:").map(str::parse) {
+                Some(Ok(size)) if size > 0 && size <= MAX_FRAME => Ok(Codec::Fixed(size)),
+                _ => Err(format!("unknown framing '{}', use length, newline or fixed:<size>", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Codec::LengthPrefixed => write!(f, "length"),
+            Codec::Newline => write!(f, "newline"),
+            Codec::Fixed(size) => write!(f, "fixed:{}", size),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [Codec; 3] = [Codec::LengthPrefixed, Codec::Newline, Codec::Fixed(5)];
+
+    fn encoded(codec: Codec, payloads: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for payload in payloads {
+            codec.encode(payload, &mut out).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn partial_frame_is_not_decoded() {
+        for codec in CODECS {
+            let bytes = encoded(codec, &[b"hello"]);
+            let mut buf = Vec::new();
+            for (i, &b) in bytes.iter().enumerate() {
+                buf.push(b);
+                let frame = codec.decode(&mut buf).unwrap();
+                if i + 1 < bytes.len() {
+                    assert_eq!(frame, None, "{} after {} bytes", codec, i + 1);
+                } else {
+                    assert_eq!(frame.as_deref(), Some(&b"hello"[..]), "{}", codec);
+                }
+            }
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn several_frames_in_one_buffer() {
+        for codec in CODECS {
+            let mut buf = encoded(codec, &[b"first", b"secnd", b"third"]);
+            buf.extend_from_slice(&encoded(codec, &[b"parti"])[..3]);
+            assert_eq!(codec.decode(&mut buf).unwrap().as_deref(), Some(&b"first"[..]));
+            assert_eq!(codec.decode(&mut buf).unwrap().as_deref(), Some(&b"secnd"[..]));
+            assert_eq!(codec.decode(&mut buf).unwrap().as_deref(), Some(&b"third"[..]));
+            assert_eq!(codec.decode(&mut buf).unwrap(), None);
+            assert_eq!(buf.len(), 3, "{}: start of the partial frame is kept", codec);
+        }
+    }
+
+    #[test]
+    fn empty_messages() {
+        for codec in [Codec::LengthPrefixed, Codec::Newline] {
+            let mut buf = encoded(codec, &[b"", b""]);
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(Vec::new()));
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(Vec::new()));
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn frame_over_max_is_rejected() {
+        let too_long = vec![b'x'; MAX_FRAME + 1];
+        let mut out = Vec::new();
+        assert!(Codec::LengthPrefixed.encode(&too_long, &mut out).is_err());
+
+        // Length prefix is checked before the frame has arrived
+        let mut buf = ((MAX_FRAME + 1) as u32).to_be_bytes().to_vec();
+        assert!(Codec::LengthPrefixed.decode(&mut buf).is_err());
+
+        let mut buf = too_long.clone();
+        assert!(Codec::Newline.decode(&mut buf).is_err());
+
+        // Exactly MAX_FRAME is fine
+        let mut buf = encoded(Codec::LengthPrefixed, &[&too_long[..MAX_FRAME]]);
+        assert_eq!(Codec::LengthPrefixed.decode(&mut buf).unwrap().map(|f| f.len()), Some(MAX_FRAME));
+    }
+
+    #[test]
+    fn invalid_messages_are_not_encoded() {
+        let mut out = Vec::new();
+        assert!(Codec::Newline.encode(b"two\nlines", &mut out).is_err());
+        assert!(Codec::Fixed(5).encode(b"four", &mut out).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn fixed_size_zero_is_an_error() {
+        let mut buf = Vec::new();
+        assert!(Codec::Fixed(0).decode(&mut buf).is_err());
+        assert!(Codec::Fixed(0).encode(b"", &mut buf).is_err());
+        assert!(Codec::Fixed(MAX_FRAME + 1).decode(&mut buf).is_err());
+        assert!("fixed:0".parse::<Codec>().is_err());
+        assert!(format!("fixed:{}", MAX_FRAME + 1).parse::<Codec>().is_err());
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for codec in CODECS {
+            assert_eq!(codec.to_string().parse::<Codec>(), Ok(codec));
+        }
+        assert!("lines".parse::<Codec>().is_err());
+    }
+}
@@ -0,0 +1,32 @@
+/* Message framing on top of TCP.
+ *
+ * TCP delivers a stream of bytes, not messages. One write may arrive in
+ * several reads, and several writes may arrive in one read, depending on how
+ * the data was segmented and how fast the receiver reads. If an application
+ * wants to exchange messages, it must mark where each message ends. This
+ * library offers three common ways (see codec.rs):
+ *
+ * - length-prefixed: each message starts with its length
+ * - newline-delimited: each message ends with a line feed
+ * - fixed-size: all messages have the same length
+ *
+ * The codec only converts between messages and bytes. The adapters connect
+ * it to sockets:
+ *
+ * - blocking::Framed for std sockets, where a read waits for data
+ * - nonblocking::FrameBuffer for MIO event loops, where data is read as it
+ *   arrives, and complete frames are taken out when available
+ * - asynchronous::AsyncFramed for tokio, with the "tokio" feature
+ *
+ * See simple-client for use of the blocking adapter, and the examples
+ * directory for the other two.
+ */
+
+pub mod blocking;
+pub mod codec;
+pub mod nonblocking;
+
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+
+pub use codec::Codec;
@@ -0,0 +1,222 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+};
+
+use crate::{codec::MAX_FRAME_BYTES, Codec};
+
+/// Frame buffers for a non-blocking socket in an event loop, such as MIO.
+///
+/// The socket is not owned by this structure, because the event loop needs
+/// it for registering. When the socket is readable, call `read_from`, and
+/// then take complete frames with `next_frame`. At most a frame's worth of
+/// data is buffered: if `read_from` returns true, it stopped before the
+/// socket would block, and must be called again after taking the frames.
+/// Messages to send are queued with `queue`, and written with `write_to`
+/// until `wants_write` is false. Writable events are needed only while there
+/// is something queued.
+pub struct FrameBuffer {
+    codec: Codec,
+    inbound: Vec<u8>,
+    outbound: VecDeque<u8>,
+    closed: bool,
+}
+
+impl FrameBuffer {
+    pub fn new(codec: Codec) -> FrameBuffer {
+        FrameBuffer {
+            codec,
+            inbound: Vec::new(),
+            outbound: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    /// Read everything available from `src`, until it would block or the
+    /// other end has closed. Returns true if reading stopped early because
+    /// the buffer is full: then the buffer holds a complete frame (or a frame
+    /// that is too long, which `next_frame` reports as an error).
+    pub fn read_from<R: Read>(&mut self, src: &mut R) -> io::Result<bool> {
+        let mut buf = [0u8; 4096];
+        while !self.closed {
+            if self.inbound.len() > MAX_FRAME_BYTES {
+                return Ok(true);
+            }
+            match src.read(&mut buf) {
+                Ok(0) => self.closed = true,
+                Ok(n) => self.inbound.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Take the next complete frame that has been read, if there is one.
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.codec.decode(&mut self.inbound)
+    }
+
+    /// True if the other end has closed the connection. There may still be
+    /// frames to take with `next_frame`. If bytes of an incomplete frame are
+    /// left after that, the connection was closed in the middle of a frame.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// True if bytes have been read that do not yet form a complete frame
+    /// (after taking all complete frames with `next_frame`).
+    pub fn has_partial_frame(&self) -> bool {
+        !self.inbound.is_empty()
+    }
+
+    /// Add a message to be sent.
+    pub fn queue(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::new();
+        self.codec.encode(payload, &mut frame)?;
+        self.outbound.extend(frame);
+        Ok(())
+    }
+
+    /// Write queued data to `dst` until all is written or `dst` would block.
+    pub fn write_to<W: Write>(&mut self, dst: &mut W) -> io::Result<()> {
+        while !self.outbound.is_empty() {
+            let (front, _) = self.outbound.as_slices();
+            match dst.write(front) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outbound.drain(..n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// True if there is queued data that has not been written yet.
+    pub fn wants_write(&self) -> bool {
+        !self.outbound.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::MAX_FRAME;
+
+    // Non-blocking socket stand-in: each read returns the next chunk, then
+    // WouldBlock, or end of stream if `eof` is set.
+    struct Chunks {
+        chunks: VecDeque<Vec<u8>>,
+        eof: bool,
+    }
+
+    impl Chunks {
+        fn new(chunks: &[&[u8]], eof: bool) -> Chunks {
+            Chunks { chunks: chunks.iter().map(|c| c.to_vec()).collect(), eof }
+        }
+    }
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.chunks.front_mut() {
+                Some(chunk) => {
+                    let n = chunk.len().min(buf.len());
+                    buf[..n].copy_from_slice(&chunk[..n]);
+                    chunk.drain(..n);
+                    if chunk.is_empty() {
+                        self.chunks.pop_front();
+                    }
+                    Ok(n)
+                }
+                None if self.eof => Ok(0),
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn frame_split_over_reads() {
+        let mut frames = FrameBuffer::new(Codec::LengthPrefixed);
+        assert!(!frames.read_from(&mut Chunks::new(&[b"\0\0"], false)).unwrap());
+        assert_eq!(frames.next_frame().unwrap(), None);
+        frames.read_from(&mut Chunks::new(&[b"\0\x05he"], false)).unwrap();
+        assert_eq!(frames.next_frame().unwrap(), None);
+        frames.read_from(&mut Chunks::new(&[b"llo"], false)).unwrap();
+        assert_eq!(frames.next_frame().unwrap().as_deref(), Some(&b"hello"[..]));
+        assert!(!frames.has_partial_frame());
+        assert!(!frames.is_closed());
+    }
+
+    #[test]
+    fn several_frames_per_read() {
+        let mut frames = FrameBuffer::new(Codec::Newline);
+        frames.read_from(&mut Chunks::new(&[b"one\ntwo\nthr"], false)).unwrap();
+        assert_eq!(frames.next_frame().unwrap().as_deref(), Some(&b"one"[..]));
+        assert_eq!(frames.next_frame().unwrap().as_deref(), Some(&b"two"[..]));
+        assert_eq!(frames.next_frame().unwrap(), None);
+        assert!(frames.has_partial_frame());
+    }
+
+    #[test]
+    fn eof_in_middle_of_frame() {
+        let mut frames = FrameBuffer::new(Codec::Fixed(4));
+        frames.read_from(&mut Chunks::new(&[b"abcdef"], true)).unwrap();
+        assert!(frames.is_closed());
+        assert_eq!(frames.next_frame().unwrap().as_deref(), Some(&b"abcd"[..]));
+        assert_eq!(frames.next_frame().unwrap(), None);
+        assert!(frames.has_partial_frame());
+    }
+
+    #[test]
+    fn reading_stops_when_buffer_is_full() {
+        // A line longer than MAX_FRAME, in more chunks than fit the buffer
+        let chunk = vec![b'x'; 64 * 1024];
+        let chunks: Vec<&[u8]> = vec![&chunk; 2 * MAX_FRAME / chunk.len()];
+        let mut src = Chunks::new(&chunks, false);
+        let mut frames = FrameBuffer::new(Codec::Newline);
+        assert!(frames.read_from(&mut src).unwrap());
+        assert!(frames.inbound.len() <= MAX_FRAME_BYTES + 4096);
+        assert!(!src.chunks.is_empty());
+        assert!(frames.next_frame().is_err());
+    }
+
+    #[test]
+    fn full_buffer_of_frames_is_read_in_rounds() {
+        let mut data = Vec::new();
+        let message = vec![b'y'; 1000];
+        for _ in 0..3000 {
+            Codec::LengthPrefixed.encode(&message, &mut data).unwrap();
+        }
+        let mut src = Chunks::new(&[&data], false);
+        let mut frames = FrameBuffer::new(Codec::LengthPrefixed);
+        let mut count = 0;
+        loop {
+            let full = frames.read_from(&mut src).unwrap();
+            while let Some(frame) = frames.next_frame().unwrap() {
+                assert_eq!(frame, message);
+                count += 1;
+            }
+            if !full {
+                break;
+            }
+        }
+        assert_eq!(count, 3000);
+    }
+
+    #[test]
+    fn queued_frames_are_written() {
+        let mut frames = FrameBuffer::new(Codec::Newline);
+        frames.queue(b"hi").unwrap();
+        frames.queue(b"there").unwrap();
+        assert!(frames.wants_write());
+        let mut out = Vec::new();
+        frames.write_to(&mut out).unwrap();
+        assert_eq!(out, b"hi\nthere\n");
+        assert!(!frames.wants_write());
+    }
+}
@@ -0,0 +1,409 @@
+/* Happy Eyeballs (RFC 8305) connection establishment for dual-stack hosts.
+ *
+ * TcpStream::connect tries the resolved addresses one at a time, in the order
+ * the resolver returns them. If the first address is IPv6 and the IPv6 path
+ * is broken so that packets are silently dropped, the connection attempt
+ * waits for the TCP connect timeout (often more than a minute) before trying
+ * IPv4. Happy Eyeballs avoids this by racing the address families:
+ *
+ * 1. AAAA (IPv6) and A (IPv4) queries are made in parallel. If the A answer
+ *    arrives first, we wait a short Resolution Delay for the AAAA answer
+ *    before starting, to give IPv6 a fair chance.
+ * 2. Addresses are tried alternating between families, IPv6 first.
+ * 3. A new attempt starts when the previous one fails, or when the Connection
+ *    Attempt Delay has passed without an answer. Earlier attempts are kept
+ *    running, and the first one to connect wins.
+ *
+ * The Report tells how long the resolution took, when each attempt started,
+ * and how it ended, so that the race can be followed.
+ */
+
+use std::{
+    collections::VecDeque,
+    ffi::{CStr, CString},
+    fmt,
+    io,
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream},
+    ptr,
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for AAAA answer after A answer has arrived (RFC 8305
+/// recommends 50 ms).
+pub const RESOLUTION_DELAY: Duration = Duration::from_millis(50);
+
+/// How long to wait for a connection attempt before starting the next one
+/// (RFC 8305 recommends 250 ms).
+pub const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Timeout of a single connection attempt.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a connection attempt ended.
+pub enum Outcome {
+    Connected(Duration),
+    Failed(Duration, io::Error),
+    Abandoned,  // another attempt won before this finished
+}
+
+/// One connection attempt. Times are from the start of `connect`.
+pub struct Attempt {
+    pub address: SocketAddr,
+    pub started: Duration,
+    pub outcome: Outcome,
+}
+
+/// Address family of a name resolution query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    V6,
+    V4,
+}
+
+impl Family {
+    fn of(address: &SocketAddr) -> Family {
+        if address.is_ipv6() { Family::V6 } else { Family::V4 }
+    }
+
+    fn af(self) -> libc::c_int {
+        match self {
+            Family::V6 => libc::AF_INET6,
+            Family::V4 => libc::AF_INET,
+        }
+    }
+}
+
+/// Result of name resolution for one address family.
+pub struct Resolution {
+    pub family: Family,
+    pub elapsed: Duration,
+    pub result: Result<usize, String>,  // number of addresses, or error
+}
+
+/// What happened during `connect`.
+#[derive(Default)]
+pub struct Report {
+    pub resolutions: Vec<Resolution>,
+    pub attempts: Vec<Attempt>,
+    pub winner: Option<SocketAddr>,
+}
+
+// Events delivered to the connecting thread from the helper threads.
+enum Event {
+    Resolved(Family, Duration, io::Result<Vec<SocketAddr>>),
+    Attempted(usize, Duration, io::Result<TcpStream>),
+}
+
+
+/// Connect to `address` given as "<host>:<port>" using Happy Eyeballs. The
+/// report is returned also when connecting fails.
+pub fn connect(address: &str) -> (io::Result<TcpStream>, Report) {
+    let mut report = Report::default();
+    let result = race(address, &mut report);
+    (result, report)
+}
+
+
+fn race(address: &str, report: &mut Report) -> io::Result<TcpStream> {
+    let (host, port) = split_host_port(address)?;
+    let start = Instant::now();
+    let (tx, rx) = mpsc::channel();
+
+    // Both queries run in their own threads, because getaddrinfo blocks.
+    for family in [Family::V6, Family::V4] {
+        let tx = tx.clone();
+        let host = host.clone();
+        thread::spawn(move || {
+            let result = resolve(&host, port, family.af());
+            let _ = tx.send(Event::Resolved(family, start.elapsed(), result));
+        });
+    }
+
+    let mut candidates = Candidates::default();
+    let mut v6_answered = false;
+    let mut answers = 0;
+
+    // Earliest time the next attempt may start. None until we have addresses.
+    let mut next_start: Option<Instant> = None;
+    let mut pending = 0;  // attempts that have not finished
+    let mut last_error: Option<io::Error> = None;
+
+    loop {
+        let now = Instant::now();
+
+        // Start the next attempt if it is time, alternating families.
+        if next_start.is_some_and(|t| t <= now) {
+            if let Some(addr) = candidates.next() {
+                start_attempt(report.attempts.len(), addr, tx.clone());
+                report.attempts.push(Attempt {
+                    address: addr,
+                    started: start.elapsed(),
+                    outcome: Outcome::Abandoned,
+                });
+                pending += 1;
+                next_start = Some(now + ATTEMPT_DELAY);
+            }
+        }
+
+        // Give up if there is nothing more to wait for
+        if answers == 2 && pending == 0 && candidates.is_empty() {
+            return Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no addresses for {}", host))
+            }));
+        }
+
+        // Wait for the next event, but not past the next attempt start
+        let event = match next_start.filter(|_| !candidates.is_empty()) {
+            Some(t) => match rx.recv_timeout(t.saturating_duration_since(Instant::now())) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => unreachable!("we hold a sender"),
+            },
+            None => rx.recv().expect("we hold a sender"),
+        };
+
+        match event {
+            Event::Resolved(family, elapsed, result) => {
+                answers += 1;
+                report.resolutions.push(Resolution {
+                    family,
+                    elapsed,
+                    result: result.as_ref().map(|a| a.len()).map_err(|e| e.to_string()),
+                });
+                let added = candidates.extend(result.unwrap_or_default());
+                if family == Family::V6 {
+                    v6_answered = true;
+                }
+                if next_start.is_none() && !candidates.is_empty() {
+                    // Start right away, unless only IPv4 answer has arrived,
+                    // in which case IPv6 gets a short head start.
+                    next_start = Some(if v6_answered {
+                        Instant::now()
+                    } else {
+                        Instant::now() + RESOLUTION_DELAY
+                    });
+                }
+                if family == Family::V6 && added > 0 {
+                    // AAAA answer arrived during the resolution delay
+                    next_start = next_start.map(|t| t.min(Instant::now()));
+                }
+            }
+            Event::Attempted(index, elapsed, Ok(stream)) => {
+                // First to connect wins. The others keep running in their
+                // threads, and their sockets are closed when they finish.
+                report.attempts[index].outcome = Outcome::Connected(elapsed);
+                report.winner = Some(report.attempts[index].address);
+                return Ok(stream);
+            }
+            Event::Attempted(index, elapsed, Err(e)) => {
+                pending -= 1;
+                report.attempts[index].outcome = Outcome::Failed(elapsed, io::Error::new(e.kind(), e.to_string()));
+                last_error = Some(e);
+                // Failure does not need to wait for the attempt delay
+                if next_start.is_some() {
+                    next_start = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
+
+// Addresses waiting for a connection attempt, handed out alternating between
+// families, IPv6 first.
+#[derive(Default)]
+struct Candidates {
+    v6: VecDeque<SocketAddr>,
+    v4: VecDeque<SocketAddr>,
+    last: Option<Family>,
+}
+
+impl Candidates {
+    // Add addresses to the end of their family's queue. Returns how many.
+    fn extend(&mut self, addrs: Vec<SocketAddr>) -> usize {
+        let count = addrs.len();
+        for addr in addrs {
+            match Family::of(&addr) {
+                Family::V6 => self.v6.push_back(addr),
+                Family::V4 => self.v4.push_back(addr),
+            }
+        }
+        count
+    }
+
+    fn next(&mut self) -> Option<SocketAddr> {
+        let (first, second) = match self.last {
+            Some(Family::V6) => (&mut self.v4, &mut self.v6),
+            _ => (&mut self.v6, &mut self.v4),
+        };
+        let addr = first.pop_front().or_else(|| second.pop_front())?;
+        self.last = Some(Family::of(&addr));
+        Some(addr)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.v6.is_empty() && self.v4.is_empty()
+    }
+}
+
+// Try to connect in a separate thread, and report the result through `tx`.
+fn start_attempt(index: usize, address: SocketAddr, tx: Sender<Event>) {
+    thread::spawn(move || {
+        let began = Instant::now();
+        let result = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT);
+        let _ = tx.send(Event::Attempted(index, began.elapsed(), result));
+    });
+}
+
+// Split "host:port" or "[v6 address]:port". An IPv6 address must be in
+// brackets, because otherwise its last group could be taken for the port.
+fn split_host_port(address: &str) -> io::Result<(String, u16)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid address {}", address));
+    let (host, port) = match address.strip_prefix('[') {
+        Some(rest) => rest.split_once("]:").ok_or_else(invalid)?,
+        None => address.rsplit_once(':').filter(|(host, _)| !host.contains(':')).ok_or_else(invalid)?,
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let port = port.parse().map_err(|_| invalid())?;
+    Ok((host.to_string(), port))
+}
+
+// Resolve addresses of one family with getaddrinfo. The standard library
+// always asks for both families at once, so we call libc directly.
+fn resolve(host: &str, port: u16, family: libc::c_int) -> io::Result<Vec<SocketAddr>> {
+    let c_host = CString::new(host)?;
+    let mut hints: libc::addrinfo = unsafe { mem::zeroed() };
+    hints.ai_family = family;
+    hints.ai_socktype = libc::SOCK_STREAM;
+
+    let mut list: *mut libc::addrinfo = ptr::null_mut();
+    let rc = unsafe { libc::getaddrinfo(c_host.as_ptr(), ptr::null(), &hints, &mut list) };
+    if rc != 0 {
+        let msg = unsafe { CStr::from_ptr(libc::gai_strerror(rc)) };
+        return Err(io::Error::other(msg.to_string_lossy().into_owned()));
+    }
+
+    // Walk through the linked list of results, and convert the socket
+    // addresses to Rust types.
+    let mut addrs = Vec::new();
+    let mut ai = list;
+    while !ai.is_null() {
+        let entry = unsafe { &*ai };
+        let addr = match entry.ai_family {
+            libc::AF_INET => {
+                let sin = unsafe { &*(entry.ai_addr as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                Some(SocketAddr::new(IpAddr::V4(ip), port))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(entry.ai_addr as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                Some(SocketAddr::V6(SocketAddrV6::new(ip, port, sin6.sin6_flowinfo, sin6.sin6_scope_id)))
+            }
+            _ => None,
+        };
+        if let Some(addr) = addr.filter(|a| !addrs.contains(a)) {
+            addrs.push(addr);
+        }
+        ai = entry.ai_next;
+    }
+    unsafe { libc::freeaddrinfo(list) };
+    Ok(addrs)
+}
+
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in &self.resolutions {
+            match &r.result {
+                Ok(n) => writeln!(f, "{} resolved in {} ms: {} addresses", r.family, r.elapsed.as_millis(), n)?,
+                Err(e) => writeln!(f, "{} resolved in {} ms: {}", r.family, r.elapsed.as_millis(), e)?,
+            }
+        }
+        for a in &self.attempts {
+            write!(f, "  {:<42} started at {:>5} ms, ", a.address.to_string(), a.started.as_millis())?;
+            match &a.outcome {
+                Outcome::Connected(t) => writeln!(f, "connected in {} ms  <- winner", t.as_millis())?,
+                Outcome::Failed(t, e) => writeln!(f, "failed in {} ms: {}", t.as_millis(), e)?,
+                Outcome::Abandoned => writeln!(f, "abandoned")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Family::V6 => write!(f, "IPv6"),
+            Family::V4 => write!(f, "IPv4"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(list: &[&str]) -> Vec<SocketAddr> {
+        list.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn host_and_port() {
+        assert_eq!(split_host_port("example.com:80").unwrap(), ("example.com".to_string(), 80));
+        assert_eq!(split_host_port("10.0.0.1:2000").unwrap(), ("10.0.0.1".to_string(), 2000));
+    }
+
+    #[test]
+    fn bracketed_ipv6() {
+        assert_eq!(split_host_port("[::1]:80").unwrap(), ("::1".to_string(), 80));
+        assert_eq!(split_host_port("[fe80::1%eth0]:443").unwrap(), ("fe80::1%eth0".to_string(), 443));
+        assert!(split_host_port("[::1]").is_err());
+        assert!(split_host_port("[::1:80").is_err());
+        assert!(split_host_port("[]:80").is_err());
+    }
+
+    #[test]
+    fn missing_port() {
+        for address in ["example.com", "example.com:", ":80", "example.com:http", "example.com:70000"] {
+            assert!(split_host_port(address).is_err(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn bare_ipv6_is_rejected() {
+        assert!(split_host_port("::1").is_err());
+        assert!(split_host_port("::1:80").is_err());
+        assert!(split_host_port("2001:db8::1").is_err());
+    }
+
+    #[test]
+    fn families_alternate_ipv6_first() {
+        let mut candidates = Candidates::default();
+        candidates.extend(addrs(&["10.0.0.1:80", "10.0.0.2:80", "10.0.0.3:80"]));
+        candidates.extend(addrs(&["[2001:db8::1]:80", "[2001:db8::2]:80"]));
+        let order: Vec<_> = std::iter::from_fn(|| candidates.next()).collect();
+        assert_eq!(order, addrs(&[
+            "[2001:db8::1]:80", "10.0.0.1:80", "[2001:db8::2]:80", "10.0.0.2:80", "10.0.0.3:80",
+        ]));
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn late_family_joins_the_alternation() {
+        // IPv4 answered first and one attempt started before the AAAA answer
+        let mut candidates = Candidates::default();
+        candidates.extend(addrs(&["10.0.0.1:80", "10.0.0.2:80"]));
+        assert_eq!(candidates.next(), Some(addrs(&["10.0.0.1:80"])[0]));
+        candidates.extend(addrs(&["[2001:db8::1]:80", "[2001:db8::2]:80"]));
+        let order: Vec<_> = std::iter::from_fn(|| candidates.next()).collect();
+        assert_eq!(order, addrs(&["[2001:db8::1]:80", "10.0.0.2:80", "[2001:db8::2]:80"]));
+    }
+}
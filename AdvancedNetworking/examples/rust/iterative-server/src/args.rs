@@ -0,0 +1,36 @@
+use clap::Parser;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Address to bind, as <IP>:<port>.
+    address: String,
+
+    /// Broadcast lines to other clients instead of echoing data back.
+    #[arg(short, long)]
+    chat: bool,
+
+    /// Number of event loop threads, each with its own listening socket.
+    #[arg(short, long, default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    reactors: usize,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn address(&self) -> &String {
+        &self.address
+    }
+
+    pub fn chat(&self) -> bool {
+        self.chat
+    }
+
+    pub fn reactors(&self) -> usize {
+        self.reactors
+    }
+}
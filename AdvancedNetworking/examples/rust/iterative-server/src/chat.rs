@@ -0,0 +1,94 @@
+/* Chat mode: each complete line a client sends is delivered to all other
+ * clients, prefixed with the sender's nickname. The first line from a client
+ * is its nickname, and others are told when someone joins or leaves.
+ *
+ * Fan-out is simple in a single-threaded event loop: a line is appended to
+ * the outbound queue of every other client, and written as far as their
+ * sockets accept. The rest goes out on writable events, like echoed data.
+ * We cannot stop reading from the sender because one receiver is slow, so a
+ * receiver whose queue grows too long is disconnected instead.
+ */
+
+use mio::{Registry, Token};
+
+use crate::{client::Client, tokenmanager::TokenManager};
+
+/// Receivers with more than this many bytes queued are disconnected.
+const MAX_QUEUED: usize = 64 * 1024;
+
+pub const PROMPT: &[u8] = b"Welcome to chat! Enter your nickname:\n";
+
+/// Handle lines received from client `token`. Returns tokens of clients that
+/// should be removed because they could not keep up.
+pub fn process_lines(
+    clients: &mut TokenManager<Client>,
+    token: Token,
+    registry: &Registry,
+) -> Vec<Token> {
+    let Some(c) = clients.get_mut(token) else {
+        return Vec::new();
+    };
+    let mut slow = Vec::new();
+    for line in c.take_lines() {
+        let c = clients.get_mut(token).unwrap();
+        let message = match c.nick() {
+            Some(nick) => format!("<{}> {}\n", nick, line),
+            None => {
+                // First line is the nickname. Ask again if it is empty.
+                let nick = line.trim();
+                if nick.is_empty() {
+                    if !send_to(c, token, PROMPT, registry) {
+                        slow.push(token);
+                    }
+                    continue;
+                }
+                c.set_nick(nick);
+                println!("Client {} joined chat as {}", c.address(), nick);
+                format!("* {} joined\n", nick)
+            }
+        };
+        // Skip also the clients that already failed, they will be removed
+        let mut exclude = slow.clone();
+        exclude.push(token);
+        slow.extend(broadcast(clients, &exclude, &message, registry));
+    }
+    slow
+}
+
+/// Deliver `message` to all clients that have a nickname, except those in
+/// `exclude`. Returns the clients that have too much data queued, or failed.
+pub fn broadcast(
+    clients: &mut TokenManager<Client>,
+    exclude: &[Token],
+    message: &str,
+    registry: &Registry,
+) -> Vec<Token> {
+    // Collect the tokens first, because the token manager cannot be
+    // modified while we iterate over it.
+    let receivers: Vec<Token> = clients.iter()
+        .filter(|(token, c)| !exclude.contains(token) && c.nick().is_some())
+        .map(|(token, _)| token)
+        .collect();
+
+    receivers.into_iter()
+        .filter(|&token| !send_to(clients.get_mut(token).unwrap(), token, message.as_bytes(), registry))
+        .collect()
+}
+
+/// Send data to one client. Returns false if the client should be removed.
+pub fn send_to(c: &mut Client, token: Token, data: &[u8], registry: &Registry) -> bool {
+    // Writable event is needed if everything did not fit in the socket
+    let result = c.send(data)
+        .and_then(|queued| c.update_interest(registry, token).map(|_| queued));
+    match result {
+        Ok(queued) if queued <= MAX_QUEUED => true,
+        Ok(queued) => {
+            println!("Client {} has {} bytes queued, disconnecting", c.address(), queued);
+            false
+        }
+        Err(e) => {
+            println!("Error sending to client {}: {}", c.address(), e);
+            false
+        }
+    }
+}
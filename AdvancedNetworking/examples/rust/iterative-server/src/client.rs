@@ -0,0 +1,212 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    time::Instant,
+};
+
+use mio::{net::TcpStream, Interest, Registry, Token};
+
+/// When this many bytes are waiting to be written back, we stop reading from
+/// the client. Otherwise a client that sends but does not read would make us
+/// buffer without limit.
+const HIGH_WATER: usize = 64 * 1024;
+
+/// Reading is resumed when the outbound queue has drained below this.
+const LOW_WATER: usize = 16 * 1024;
+
+/// Longest line accepted in chat mode.
+const MAX_LINE: usize = 1024;
+
+/// Information for each client currently being served by our server.
+pub struct Client {
+    socket: TcpStream,
+    address: SocketAddr,
+    outbound: VecDeque<u8>,  // data waiting to be written to socket
+    paused: bool,            // reading stopped because outbound queue is full
+    read_closed: bool,       // client has closed its sending direction
+    interest: Interest,      // events currently registered to MIO
+    last_activity: Instant,  // last time data was read or written
+    echoed: u64,             // bytes written back to client
+    chat: Option<Chat>,      // set in chat mode
+}
+
+/// Chat mode state: incoming data is collected into lines that are
+/// broadcast to others, instead of echoing it back.
+struct Chat {
+    inbound: Vec<u8>,      // received data not yet forming a complete line
+    nick: Option<String>,  // given in the first line
+}
+
+impl Client {
+    pub fn new(socket: TcpStream, address: SocketAddr, chat: bool) -> Client {
+        Client {
+            socket,
+            address,
+            outbound: VecDeque::new(),
+            paused: false,
+            read_closed: false,
+            interest: Interest::READABLE,
+            last_activity: Instant::now(),
+            echoed: 0,
+            chat: chat.then(|| Chat { inbound: Vec::new(), nick: None }),
+        }
+    }
+
+    /// Register the client to MIO for readable events.
+    pub fn register(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        registry.register(&mut self.socket, token, self.interest)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
+
+    pub fn echoed(&self) -> u64 {
+        self.echoed
+    }
+
+    /// Nickname of chat client, None until the client has given one.
+    pub fn nick(&self) -> Option<&str> {
+        self.chat.as_ref()?.nick.as_deref()
+    }
+
+    pub fn set_nick(&mut self, nick: &str) {
+        if let Some(chat) = self.chat.as_mut() {
+            chat.nick = Some(nick.to_string());
+        }
+    }
+
+    /// Queue data for sending and write what the socket accepts now. Returns
+    /// the number of bytes left in the queue, so that the caller can decide
+    /// what to do with a client that does not keep up.
+    pub fn send(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.outbound.extend(data);
+        self.flush()?;
+        Ok(self.outbound.len())
+    }
+
+    /// Take the complete lines received in chat mode, without line endings.
+    pub fn take_lines(&mut self) -> Vec<String> {
+        let Some(chat) = self.chat.as_mut() else {
+            return Vec::new();
+        };
+        let mut lines = Vec::new();
+        while let Some(pos) = chat.inbound.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = chat.inbound.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            lines.push(line.trim_end_matches(['\r', '\n']).to_string());
+        }
+        lines
+    }
+
+    /// Serve the client after MIO has reported an event for it. Reads all
+    /// available data into the outbound queue and writes as much of the queue
+    /// as the socket accepts. Returns false when the client has closed the
+    /// connection and all data has been written back, i.e., the client can be
+    /// removed.
+    pub fn serve(&mut self) -> io::Result<bool> {
+        loop {
+            self.receive()?;
+            self.flush()?;
+
+            // If writing made room while reading was paused, resume reading.
+            // MIO events are edge-triggered, so there may not be a new
+            // readable event for data that is already waiting in the socket.
+            if self.paused && self.outbound.len() <= LOW_WATER {
+                println!("Resuming reading from client {}", self.address);
+                self.paused = false;
+                continue;
+            }
+            break;
+        }
+        Ok(!(self.read_closed && self.outbound.is_empty()))
+    }
+
+    /// Tell MIO which events we are interested in, if that has changed.
+    /// Readable events are needed unless reading is paused, and writable
+    /// events only when there is data in the outbound queue.
+    pub fn update_interest(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        let readable = !self.paused && !self.read_closed;
+        let writable = !self.outbound.is_empty();
+        let interest = match (readable, writable) {
+            (true, true) => Interest::READABLE | Interest::WRITABLE,
+            (true, false) => Interest::READABLE,
+            (false, true) => Interest::WRITABLE,
+            (false, false) => return Ok(()),  // client is about to be removed
+        };
+        if interest != self.interest {
+            registry.reregister(&mut self.socket, token, interest)?;
+            self.interest = interest;
+        }
+        Ok(())
+    }
+
+    /// Read until the socket would block, the client closes the connection,
+    /// or the outbound queue becomes too full.
+    fn receive(&mut self) -> io::Result<()> {
+        let mut buf: [u8; 4096] = [0; 4096];
+        while !self.paused && !self.read_closed {
+            match self.socket.read(&mut buf) {
+                // return value of 0 bytes means that socket is closed by
+                // the other end. We may still have data to write back.
+                Ok(0) => {
+                    println!("Client {} closed connection", self.address);
+                    self.read_closed = true;
+                }
+                Ok(n) => {
+                    println!("read {} bytes from client {}", n, self.address);
+                    self.last_activity = Instant::now();
+
+                    // In chat mode data is not echoed, but collected into
+                    // lines. A client that never sends a line feed cannot
+                    // make us buffer without limit either.
+                    if let Some(chat) = self.chat.as_mut() {
+                        chat.inbound.extend(&buf[..n]);
+                        if chat.inbound.len() > MAX_LINE && !chat.inbound.contains(&b'\n') {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+                        }
+                        continue;
+                    }
+
+                    self.outbound.extend(&buf[..n]);
+                    if self.outbound.len() >= HIGH_WATER {
+                        println!("Pausing reading from client {}, {} bytes queued",
+                            self.address, self.outbound.len());
+                        self.paused = true;
+                    }
+                }
+                // No more data for now, wait for the next readable event
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write from outbound queue until it is empty, or the socket would block.
+    /// In the latter case writable event tells when we can continue.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outbound.is_empty() {
+            // VecDeque may store its content in two parts, write the first
+            let (front, _) = self.outbound.as_slices();
+            match self.socket.write(front) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outbound.drain(..n);
+                    self.echoed += n as u64;
+                    self.last_activity = Instant::now();
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
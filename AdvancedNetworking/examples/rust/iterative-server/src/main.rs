@@ -3,133 +3,372 @@
  * until the client closes them, i.e., we must handle multiple simultaneous
  * connections at a time. Bind to "0.0.0.0:<port>" if connections are allowed
  * from any interface.
+ *
+ * Data to be echoed is first placed in a client-specific outbound queue, and
+ * written when the socket can take it. If a client does not read its echoes,
+ * the queue fills up, and we stop reading from that client until it drains.
+ *
+ * Clients that have not sent or received anything for a while are closed.
+ * This and periodic statistics printing are driven by timers, whose next
+ * deadline is used as the timeout for waiting MIO events.
+ *
+ * On SIGINT (Ctrl-C) or SIGTERM the server stops accepting new connections,
+ * and keeps serving the active clients until they close or a deadline
+ * passes. Then it prints how many bytes were echoed to each client, and
+ * exits. A second signal exits immediately.
+ *
+ * With "--chat", the server is a chat room instead of echoing: lines from one
+ * client are broadcast to all others (see chat.rs). Try with several netcats.
+ *
+ * With "--reactors N", N threads each run their own event loop (reactor) with
+ * their own listening socket. The sockets are bound to the same address with
+ * SO_REUSEPORT option, and the kernel distributes new connections between
+ * them. At exit, the number of connections each reactor got is printed.
  * 
- * Usage: cargo run -- <IP>:<port>
+ * Usage: cargo run -- [--chat] <IP>:<port>
+ *        cargo run -- --reactors 4 <IP>:<port>
  */
 
+mod args;          // Command line arguments.
+mod chat;          // Broadcasting lines between clients in chat mode.
+mod client;        // Client structure with its outbound queue.
 mod tokenmanager;  // This example also has tokenmanger module in separate file.
 
 use std::{
-    collections::HashMap,
-    env,
     error::Error,
-    io::{Read, Write},
-    net::{SocketAddr},
+    io,
+    net::SocketAddr,
+    process,
+    thread,
+    time::{Duration, Instant},
 };
 
 use mio::{
     Events,
     Interest,
     Poll,
-    net::{TcpListener, TcpStream},
+    net::TcpListener,
+    Registry,
     Token
 };
+use signal_hook::consts::TERM_SIGNALS;
+use signal_hook_mio::v1_0::Signals;
+use socket2::{Domain, Socket, Type};
+use timers::Timers;
 
-use crate::tokenmanager::TokenManager;
+use crate::{
+    args::Args,
+    client::Client,
+    tokenmanager::TokenManager,
+};
+
+// Token for the listening socket. Token manager never allocates tokens with
+// the highest bit set, so this cannot collide with client tokens.
+const LISTEN_TOKEN: Token = Token(usize::MAX);
+
+// Token for signal events.
+const SIGNAL_TOKEN: Token = Token(usize::MAX - 1);
+
+// How long active clients may take after shutdown was requested.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
+// Clients that are idle for this long are disconnected.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
-// Small structure to hold information for each client currently being served by
-// our server
-struct Client {
-    socket: TcpStream,
-    address: SocketAddr,
+// How often statistics are printed.
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+// What to do when a timer expires.
+enum TimerEvent {
+    Idle(Token),  // check whether client has been idle too long
+    Stats,        // print statistics
+    Shutdown,     // close remaining clients and exit
+}
+
+// What each reactor reports when it finishes.
+struct Report {
+    accepted: u64,                      // connections accepted
+    finished: Vec<(SocketAddr, u64)>,   // bytes echoed to each client
 }
 
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Collect command-line arguments into a vector
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() != 2 {
-        eprintln!("arguments: <host>:<port>");
-        return Err("Invalid command".into());
+    // Parse command line arguments
+    let args = Args::new();
+    if args.chat() && args.reactors() > 1 {
+        // Chat clients in different reactors could not see each other
+        return Err("Chat needs a single reactor".into());
     }
 
     // Create a passive server socket and bind to address given as command line argument.
     // If there is an error in bind, exit the main function with error.
-    let addr = args[1].parse()?;
-    let mut server = TcpListener::bind(addr)?;
+    let addr: SocketAddr = args.address().parse()?;
+
+    let reports = if args.reactors() == 1 {
+        // Single reactor runs in the main thread.
+        vec![serve(0, TcpListener::bind(addr)?, args.chat())?]
+    } else {
+        // Bind all sockets before starting threads, so that errors are
+        // noticed right away. Each reactor thread owns its socket.
+        let mut servers = Vec::new();
+        for _ in 0..args.reactors() {
+            servers.push(bind_reuseport(addr)?);
+        }
+        let handles: Vec<_> = servers.into_iter().enumerate()
+            .map(|(id, server)| thread::spawn(move || serve(id, server, false)))
+            .collect();
+        let mut reports = Vec::new();
+        for handle in handles {
+            reports.push(handle.join().expect("reactor thread panicked")?);
+        }
+        reports
+    };
+
+    println!("Bytes echoed per client:");
+    for (address, n) in reports.iter().flat_map(|r| r.finished.iter()) {
+        println!("  {}: {}", address, n);
+    }
+    if reports.len() > 1 {
+        // How evenly the kernel spread the connections
+        let total: u64 = reports.iter().map(|r| r.accepted).sum();
+        println!("Connections per reactor:");
+        for (id, report) in reports.iter().enumerate() {
+            let share = if total > 0 { 100.0 * report.accepted as f64 / total as f64 } else { 0.0 };
+            println!("  reactor {}: {} connections ({:.1} %)", id, report.accepted, share);
+        }
+    }
+    Ok(())
+}
+
+
+// Create listening socket with SO_REUSEPORT option. Several sockets with the
+// option can be bound to the same address and port. MIO's bind does not
+// offer this option, so the socket is created with socket2 crate and then
+// converted to a MIO listener.
+fn bind_reuseport(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;  // MIO requires non-blocking sockets
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into()))
+}
+
+
+// Event loop of one reactor. Serves clients that connect to `server` until
+// shutdown is requested and the clients are gone.
+fn serve(id: usize, server: TcpListener, chat_mode: bool) -> io::Result<Report> {
+    // The listener is dropped on shutdown, so it is kept in an Option.
+    let mut server = Some(server);
+    let mut accepted: u64 = 0;
 
     // Set up MIO event engine for handling concurrent I/O operations.
-    let mut tokenmanager = TokenManager::new();  // our own little token manager
     let mut poll = Poll::new()?;  // MIO's Poll service
     let mut events = Events::with_capacity(128);  // Container for max 128 events at a time
 
-    // Allocate a token for the listening socket and register it to MIO for
-    // receiving events
-    let listen_token = tokenmanager.allocate_token();
+    // Register the listening socket to MIO for receiving events
+    if let Some(server) = server.as_mut() {
+        poll.registry()
+            .register(server, LISTEN_TOKEN, Interest::READABLE)?;
+    }
+
+    // Signals are delivered as MIO events, like I/O. signal-hook takes care
+    // of the details: the signal handler writes to a pipe that MIO listens.
+    let mut signals = Signals::new(TERM_SIGNALS)?;
     poll.registry()
-        .register(&mut server, listen_token, Interest::READABLE)?;
+        .register(&mut signals, SIGNAL_TOKEN, Interest::READABLE)?;
+    let mut shutting_down = false;
+
+    // Currently active clients. Our own little token manager allocates a token
+    // for each client, and stores the client structure behind the token.
+    let mut clients: TokenManager<Client> = TokenManager::new();
 
-    // HashMap for currently active clients
-    let mut clients: HashMap<Token, Client> = HashMap::new();
+    // Timers, and the number of bytes echoed to clients that are gone.
+    let mut timers: Timers<TimerEvent> = Timers::new();
+    timers.add_after(STATS_INTERVAL, TimerEvent::Stats);
+    let mut finished: Vec<(SocketAddr, u64)> = Vec::new();
 
-    loop {
+    // When shutting down, exit the loop as soon as the last client is gone
+    while !(shutting_down && clients.iter().next().is_none()) {
         // Wait for the next MIO event. There may be multiple events returned,
         // if we are busy. The second parameter is for optional timeout for
-        // situations when there are no events for a while.
-        poll.poll(&mut events, None)?;
+        // situations when there are no events for a while: we wait at most
+        // until the next timer expires.
+        let timeout = timers.next_timeout(Instant::now());
+        if let Err(e) = poll.poll(&mut events, timeout) {
+            // Arriving signal interrupts the wait. The signal itself is
+            // delivered as an event when we poll again.
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
 
         // Process each event.
         for event in events.iter() {
-            // If there is event for listening socket, there is a new connection
-            // arriving. Process it using accept.
-            if event.token() == listen_token {
-                // Accept incoming connection
-                let (socket, address) = server.accept()?;
-                println!("Accepting connection from {}", address.to_string());
-
-                // Create new client instance for our hashmap.
-                let mut c = Client {
-                    socket,
-                    address,
+            // If there is event for listening socket, there are new connections
+            // arriving. Process them using accept.
+            if event.token() == SIGNAL_TOKEN {
+                if signals.pending().next().is_none() {
+                    continue;
+                }
+                if shutting_down {
+                    println!("Second signal, exiting immediately");
+                    process::exit(1);
+                }
+                // Stop accepting: dropping the listener closes it, and new
+                // connection attempts are refused.
+                println!("Reactor {}: shutting down, waiting up to {} seconds for {} active clients",
+                    id, DRAIN_TIMEOUT.as_secs(), clients.iter().count());
+                server = None;
+                shutting_down = true;
+                timers.add_after(DRAIN_TIMEOUT, TimerEvent::Shutdown);
+            }
+            else if event.token() == LISTEN_TOKEN {
+                let Some(server) = server.as_ref() else {
+                    continue;  // already shutting down
                 };
-                let token = tokenmanager.allocate_token();  // create token for client
+                // MIO events are edge-triggered: we get one event even if
+                // several connections are waiting. Therefore accept until
+                // there are no more connections, indicated by WouldBlock.
+                loop {
+                    let (socket, address) = match server.accept() {
+                        Ok(a) => a,
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            println!("Error accepting connection: {}", e);
+                            break;
+                        }
+                    };
+                    println!("Reactor {}: accepting connection from {}", id, address);
+                    accepted += 1;
 
-                // Tell MIO to deliver events whenever there
-                // is something to read from the socket.
-                poll.registry().register(&mut c.socket, token, Interest::READABLE)?;
-                clients.insert(token, c);  // Add new client to hashmap
+                    // Store the client in token manager, that gives us token
+                    // for the client. Then tell MIO to deliver events with
+                    // this token whenever there is something to read from the
+                    // socket.
+                    let token = clients.insert(Client::new(socket, address, chat_mode));
+                    let c = clients.get_mut(token).unwrap();
+                    if let Err(e) = c.register(poll.registry(), token) {
+                        println!("Error registering client: {}", e);
+                        clients.remove(token);
+                        continue;
+                    }
+                    if chat_mode && !chat::send_to(c, token, chat::PROMPT, poll.registry()) {
+                        clients.remove(token);
+                        continue;
+                    }
+                    timers.add_after(IDLE_TIMEOUT, TimerEvent::Idle(token));
+                }
             }
-            // true if the hashmap contains client with received event token
-            // 'c' will contain the client structure instance from hashmap
-            else if let Some(c) = clients.get_mut(&event.token()) {
-                let mut buf: [u8; 160] = [0; 160];
-
-                // Test whether read call returns Ok or Err result variant
-                match c.socket.read(&mut buf) {
-                    Ok(n) => {
-                        // return value of 0 bytes means that socket is closed by
-                        // the other end. Remove the token from tokenmanager,
-                        // and the client from hashmap.
-                        if n == 0 {
-                            println!("Client {} closed connection", c.address.to_string());
-                            tokenmanager.free_token(event.token());
-                            clients.remove(&event.token());
-                            continue;  // move to next event
-                        }
-                        println!("read {} bytes from client {}", n, c.address.to_string());
-
-                        // Echo the same bytes back to client. If we wanted to be
-                        // decently prepared for the write call to be blocked, we should
-                        // register a writable event and place the data to be written
-                        // into client-specific buffer.
-                        if let Err(e) = c.socket.write(&buf[..n]) {
-                            // if there is a error, remove client and close the socket
-                            // without terminating the event loop
-                            println!("Error writing to client: {}", e);
-                            tokenmanager.free_token(event.token());
-                            clients.remove(&event.token());
-                        }
-                    },
+            // true if the token manager contains client with received event
+            // token. Events for already closed clients do not match, even if
+            // their token slot has been reused for a new client.
+            // 'c' will contain the client structure instance
+            else if let Some(c) = clients.get_mut(event.token()) {
+                // Read what is available and write what we can. Then update
+                // the events we want from MIO: writable if there is data left
+                // in the outbound queue, readable unless the queue is full.
+                let address = c.address();
+                let result = c.serve().and_then(|active| {
+                    if active {
+                        c.update_interest(poll.registry(), event.token())?;
+                    }
+                    Ok(active)
+                });
+
+                // In chat mode, deliver the complete lines to other clients.
+                // Those that cannot keep up are removed.
+                if chat_mode {
+                    let slow = chat::process_lines(&mut clients, event.token(), poll.registry());
+                    for token in slow {
+                        remove_client(&mut clients, token, &mut finished, poll.registry());
+                    }
+                }
+
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        // Client has closed and got all its data back.
+                        // Removing the client from token manager releases
+                        // the token and closes the socket.
+                        remove_client(&mut clients, event.token(), &mut finished, poll.registry());
+                    }
                     Err(e) => {
-                        println!("Error reading from {}: {}",
-                            c.address.to_string(), e);
-                        tokenmanager.free_token(event.token());
-                        clients.remove(&event.token());
+                        // if there is a error, remove client and close the socket
+                        // without terminating the event loop
+                        println!("Error serving client {}: {}", address, e);
+                        remove_client(&mut clients, event.token(), &mut finished, poll.registry());
+                    }
+                }
+            }
+        }
+
+        // Handle the timers that have expired while we were waiting or
+        // processing events.
+        let now = Instant::now();
+        while let Some(timer) = timers.pop_expired(now) {
+            match timer {
+                TimerEvent::Idle(token) => {
+                    // If the client is already gone, there is nothing to do
+                    let Some(c) = clients.get_mut(token) else {
+                        continue;
+                    };
+                    let deadline = c.last_activity() + IDLE_TIMEOUT;
+                    if deadline <= now {
+                        println!("Client {} idle for {} seconds, closing",
+                            c.address(), IDLE_TIMEOUT.as_secs());
+                        remove_client(&mut clients, token, &mut finished, poll.registry());
+                    } else {
+                        // Client has been active, check again later
+                        timers.add(deadline, TimerEvent::Idle(token));
+                    }
+                }
+                TimerEvent::Stats => {
+                    let active = clients.iter().count();
+                    let echoed: u64 = finished.iter().map(|(_, n)| n).sum::<u64>()
+                        + clients.iter().map(|(_, c)| c.echoed()).sum::<u64>();
+                    println!("Reactor {}: active clients: {}, bytes echoed in total: {}",
+                        id, active, echoed);
+                    timers.add(now + STATS_INTERVAL, TimerEvent::Stats);
+                }
+                TimerEvent::Shutdown => {
+                    // Deadline passed, close the clients that are still here
+                    let tokens: Vec<Token> = clients.iter().map(|(token, _)| token).collect();
+                    for token in tokens {
+                        if let Some(c) = clients.get_mut(token) {
+                            println!("Closing connection to {}", c.address());
+                        }
+                        remove_client(&mut clients, token, &mut finished, poll.registry());
                     }
                 }
             }
         }
     }
+
+    Ok(Report { accepted, finished })
+}
+
+
+// Remove client from token manager, which closes the socket, and record how
+// many bytes were echoed to it. If a chat client leaves, the others are told,
+// and clients that cannot take the notice are removed as well.
+fn remove_client(
+    clients: &mut TokenManager<Client>,
+    token: Token,
+    finished: &mut Vec<(SocketAddr, u64)>,
+    registry: &Registry,
+) {
+    let mut pending = vec![token];
+    while let Some(token) = pending.pop() {
+        if let Some(c) = clients.remove(token) {
+            finished.push((c.address(), c.echoed()));
+            if let Some(nick) = c.nick() {
+                let message = format!("* {} left\n", nick);
+                let slow = chat::broadcast(clients, &pending, &message, registry);
+                pending.extend(slow);
+            }
+        }
+    }
 }
@@ -1,40 +1,153 @@
-use std::collections::HashSet;
-
 use mio::Token;
 
-/// Allocates and manages MIO tokens, to ensure unique tokens for its users.
-pub struct TokenManager {
-    used_tokens: HashSet<Token>,
-    free_tokens: Vec<Token>,
-    next: usize,
+/// Allocates MIO tokens and stores the state associated with each token.
+///
+/// Tokens are indexes to a slab-like vector, whose free slots are reused.
+/// If a slot was reused directly, an event that was already queued for a
+/// closed connection could be delivered to a new connection that got the
+/// same slot. Therefore each slot has a generation counter that is increased
+/// every time the slot is freed, and the generation is encoded in the token
+/// value together with the index. Tokens of earlier generations are rejected.
+///
+/// The highest bit is never set in allocated tokens, so tokens with the
+/// highest bit set (e.g. `Token(usize::MAX)`) can be used for other
+/// purposes, such as the listening socket.
+pub struct TokenManager<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+}
+
+struct Slot<T> {
+    generation: usize,
+    value: Option<T>,
 }
 
-impl TokenManager {
-    pub fn new() -> TokenManager {
+/// Lower half of the token value is the index, upper half the generation.
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+/// Generation uses the upper half except the highest bit.
+const GENERATION_MASK: usize = (1 << (usize::BITS - INDEX_BITS - 1)) - 1;
+
+impl<T> TokenManager<T> {
+    pub fn new() -> TokenManager<T> {
         TokenManager {
-            used_tokens: HashSet::new(),
-            free_tokens: Vec::new(),
-            next: 0,
+            slots: Vec::new(),
+            free: Vec::new(),
         }
     }
 
-    /// Allocate an unused token.
-    pub fn allocate_token(&mut self) -> Token {
-        if let Some(token) = self.free_tokens.pop() {
-            self.used_tokens.insert(token);
-            token
+    /// Store `value` and allocate a token for it.
+    pub fn insert(&mut self, value: T) -> Token {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index];
+            slot.value = Some(value);
+            make_token(index, slot.generation)
         } else {
-            let token = Token(self.next);
-            self.next += 1;
-            self.used_tokens.insert(token);
-            token
+            let index = self.slots.len();
+            assert!(index <= INDEX_MASK, "too many tokens");
+            self.slots.push(Slot {
+                generation: 0,
+                value: Some(value),
+            });
+            make_token(index, 0)
         }
     }
 
-    /// Release a token.
-    pub fn free_token(&mut self, token: Token) {
-        if self.used_tokens.remove(&token) {
-            self.free_tokens.push(token);
+    /// Returns the value for `token`, or None if the token is not in use
+    /// or is from an earlier generation.
+    pub fn get_mut(&mut self, token: Token) -> Option<&mut T> {
+        let (index, generation) = split_token(token);
+        match self.slots.get_mut(index) {
+            Some(slot) if slot.generation == generation => slot.value.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Release a token and return its value. Stale tokens are ignored.
+    pub fn remove(&mut self, token: Token) -> Option<T> {
+        let (index, generation) = split_token(token);
+        let slot = self.slots.get_mut(index)?;
+        if slot.generation != generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+
+        // Tokens given out from now on for this slot differ from the old one.
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free.push(index);
+        Some(value)
+    }
+
+    /// Iterate over tokens in use and their values.
+    pub fn iter(&self) -> impl Iterator<Item = (Token, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value.as_ref().map(|value| (make_token(index, slot.generation), value))
+        })
+    }
+}
+
+fn make_token(index: usize, generation: usize) -> Token {
+    Token((generation << INDEX_BITS) | index)
+}
+
+fn split_token(token: Token) -> (usize, usize) {
+    (token.0 & INDEX_MASK, token.0 >> INDEX_BITS)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_token_is_rejected_after_reuse() {
+        let mut tokens = TokenManager::new();
+        let old = tokens.insert("old client");
+        assert_eq!(tokens.remove(old), Some("old client"));
+
+        // New client gets the same slot, but a different token
+        let new = tokens.insert("new client");
+        assert_ne!(old, new);
+        assert_eq!(split_token(old).0, split_token(new).0);
+
+        // A late event for the old client must not reach the new one
+        assert_eq!(tokens.get_mut(old), None);
+        assert_eq!(tokens.get_mut(new), Some(&mut "new client"));
+    }
+
+    #[test]
+    fn stale_remove_does_not_free_new_owner() {
+        let mut tokens = TokenManager::new();
+        let old = tokens.insert(1);
+        tokens.remove(old);
+        let new = tokens.insert(2);
+
+        // Removing twice, e.g. on both read error and write error of the
+        // same event batch, must not drop the client that reused the slot.
+        assert_eq!(tokens.remove(old), None);
+        assert_eq!(tokens.get_mut(new), Some(&mut 2));
+    }
+
+    #[test]
+    fn generation_wraps_within_mask() {
+        let mut tokens = TokenManager::new();
+        let first = tokens.insert(());
+        tokens.slots[0].generation = GENERATION_MASK;
+        let last = make_token(0, GENERATION_MASK);
+        assert_eq!(tokens.remove(last), Some(()));
+
+        // Highest bit stays free for tokens reserved by the application
+        let wrapped = tokens.insert(());
+        assert_eq!(wrapped, first);
+        assert_eq!(last.0 & (1 << (usize::BITS - 1)), 0);
+    }
+
+    #[test]
+    fn reserved_token_is_never_valid() {
+        let mut tokens = TokenManager::new();
+        for i in 0..100 {
+            tokens.insert(i);
         }
+        assert_eq!(tokens.get_mut(Token(usize::MAX)), None);
     }
 }
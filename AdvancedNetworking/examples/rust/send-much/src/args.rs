@@ -1,4 +1,6 @@
-use clap::Parser;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
 
 /// Command line arguments parser for this application.
 #[derive(Parser, Debug)]
@@ -15,13 +17,58 @@ pub struct Args {
     /// When operating as client, number of bytes to write.
     #[arg(short, long, default_value_t = 100000)]
     bytes: usize,
+
+    /// Set socket send buffer size (SO_SNDBUF), in bytes.
+    #[arg(long)]
+    sndbuf: Option<usize>,
+
+    /// Set socket receive buffer size (SO_RCVBUF), in bytes.
+    #[arg(long)]
+    rcvbuf: Option<usize>,
+
+    /// Interval of printing socket state, in milliseconds. 0 disables.
+    #[arg(short, long, default_value_t = 100)]
+    interval: u64,
+
+    /// How the client writes when the send buffer is full.
+    #[arg(short, long, value_enum, default_value_t = WriteMode::Blocking)]
+    write_mode: WriteMode,
+
+    /// Send timeout (SO_SNDTIMEO) in timeout mode, in milliseconds.
+    #[arg(short, long, default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
+    /// Congestion control algorithm of the client socket (TCP_CONGESTION),
+    /// e.g. cubic, reno or bbr. With --compare, a comma-separated list.
+    #[arg(long)]
+    cc: Option<String>,
+
+    /// Run one transfer with each congestion control algorithm and compare
+    /// them. The server must be running with --sink.
+    #[arg(long)]
+    compare: bool,
+
+    /// Server reads each connection right away without waiting for key
+    /// press, and keeps accepting new connections.
+    #[arg(long)]
+    sink: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WriteMode {
+    /// Write blocks until there is room in the send buffer
+    Blocking,
+    /// Write returns WouldBlock immediately, and the client retries after a pause
+    Nonblocking,
+    /// Wait for writable event from MIO poll before writing again
+    Mio,
+    /// Write blocks, but at most for the time set with SO_SNDTIMEO
+    Timeout,
 }
 
 impl Args {
     pub fn new() -> Args {
-        let args = Args::parse();
-
-        args
+        Args::parse()
     }
 
     pub fn connect_addr(&self) -> &String {
@@ -35,4 +82,36 @@ impl Args {
     pub fn bytes(&self) -> usize {
         self.bytes
     }
+
+    pub fn sndbuf(&self) -> Option<usize> {
+        self.sndbuf
+    }
+
+    pub fn rcvbuf(&self) -> Option<usize> {
+        self.rcvbuf
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        (self.interval > 0).then(|| Duration::from_millis(self.interval))
+    }
+
+    pub fn write_mode(&self) -> WriteMode {
+        self.write_mode
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout)
+    }
+
+    pub fn cc(&self) -> Option<&String> {
+        self.cc.as_ref()
+    }
+
+    pub fn compare(&self) -> bool {
+        self.compare
+    }
+
+    pub fn sink(&self) -> bool {
+        self.sink
+    }
 }
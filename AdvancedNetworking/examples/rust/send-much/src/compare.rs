@@ -0,0 +1,119 @@
+/* Comparing congestion control algorithms with back-to-back transfers.
+ *
+ * The client opens one connection per algorithm, writes the requested number
+ * of bytes and waits until the server has read everything and closed the
+ * connection. Then it reads the number of retransmissions from TCP_INFO
+ * before closing its own socket. The server runs in sink mode, reading each
+ * connection as fast as it can.
+ *
+ * On loopback all algorithms look alike, because there is no bottleneck and
+ * no packet loss. Run the comparison over a link with limited bandwidth and
+ * some delay or loss, e.g. in Mininet or with netem.
+ */
+
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener},
+    os::fd::AsRawFd,
+    time::{Duration, Instant},
+};
+
+use socket2::SockRef;
+
+use crate::{args::Args, monitor};
+
+struct Run {
+    algorithm: String,
+    elapsed: Duration,
+    retrans: u32,
+}
+
+
+// Run one transfer with each algorithm given with --cc, or all available
+// algorithms, and print a table of the results.
+pub fn client(args: &Args) -> Result<(), Box<dyn Error>> {
+    let algorithms = match args.cc() {
+        Some(list) => list.split(',').map(String::from).collect(),
+        None => tcp_congestion::available()?,
+    };
+    println!("Comparing {} with {} bytes each", algorithms.join(", "), args.bytes());
+
+    let mut runs = Vec::new();
+    for algorithm in &algorithms {
+        match transfer(args, algorithm) {
+            Ok(run) => {
+                println!("{}: {} ms", run.algorithm, run.elapsed.as_millis());
+                runs.push(run);
+            }
+            // Skip algorithms the kernel refuses, but stop on other errors
+            Err(e) if tcp_congestion::is_refused(&e) => {
+                println!("{}: skipped: {}", algorithm, e);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    println!();
+    println!("{:<12} {:>10} {:>12} {:>8}", "algorithm", "time_ms", "Mbit/s", "retrans");
+    for run in &runs {
+        // A tiny transfer over loopback may take no measurable time
+        let secs = run.elapsed.as_secs_f64();
+        let mbps = if secs > 0.0 { args.bytes() as f64 * 8.0 / secs / 1e6 } else { 0.0 };
+        println!("{:<12} {:>10} {:>12.2} {:>8}",
+            run.algorithm, run.elapsed.as_millis(), mbps, run.retrans);
+    }
+    Ok(())
+}
+
+
+fn transfer(args: &Args, algorithm: &str) -> io::Result<Run> {
+    let (result, _) = happy_eyeballs::connect(args.connect_addr());
+    let mut socket = result?;
+    let socket_ref = SockRef::from(&socket);
+    if let Some(size) = args.sndbuf() {
+        socket_ref.set_send_buffer_size(size)?;
+    }
+    let algorithm = tcp_congestion::set(socket.as_raw_fd(), algorithm)?;
+
+    let start = Instant::now();
+    let buffer = [b'A'; 10000];
+    let mut total = 0;
+    while total < args.bytes() {
+        let n = socket.write(&buffer[..buffer.len().min(args.bytes() - total)])?;
+        total += n;
+    }
+
+    // Write returns when the data is in the send buffer. The transfer is
+    // complete only when the server has read everything: it closes the
+    // connection after end of stream, and we see it as end of stream too.
+    socket.shutdown(Shutdown::Write)?;
+    let mut buf = [0; 100];
+    while socket.read(&mut buf)? > 0 {}
+    let elapsed = start.elapsed();
+
+    let retrans = monitor::sample(socket.as_raw_fd())?.retrans;
+    Ok(Run { algorithm, elapsed, retrans })
+}
+
+
+// Server for the comparison: read each connection right away until the
+// client closes it, then accept the next one. A failed connection is only
+// reported, so that one reset does not stop the whole comparison.
+pub fn sink(server: TcpListener) -> Result<(), Box<dyn Error>> {
+    println!("Reading connections in sink mode");
+    loop {
+        let (mut socket, address) = match server.accept() {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Accept failed: {}", e);
+                continue;
+            }
+        };
+        let start = Instant::now();
+        match io::copy(&mut socket, &mut io::sink()) {
+            Ok(n) => println!("Read {} bytes from {} in {} ms", n, address, start.elapsed().as_millis()),
+            Err(e) => eprintln!("Reading from {} failed: {}", address, e),
+        }
+    }
+}
@@ -1,39 +1,81 @@
 /* Simple example to illustrate effect of buffering on socket API
  * Server accepts connection, then waits for user input before starting to read.
  * Client just writes a large number of bytes to the server.
- * 
- * Server usage: cargo run -- -s <address:port>
- * Client usage: cargo run -- -c <address:port> -b <number of bytes>
+ *
+ * Both sides print a timeline of the socket state while running (see
+ * monitor.rs): how much data sits in the send and receive queues, and TCP
+ * state from TCP_INFO. The client also tells when write first blocked.
+ * The socket buffer sizes can be changed with --sndbuf and --rcvbuf, to see
+ * how they affect the point where write starts blocking.
+ *
+ * The client can handle a full send buffer in different ways, selected with
+ * --write-mode: blocking writes (default), non-blocking writes that return
+ * WouldBlock, writes driven by MIO writable events, or blocking writes with
+ * a send timeout (SO_SNDTIMEO). See writer.rs.
+ *
+ * The client can choose its congestion control algorithm with --cc. With
+ * --compare, it runs one transfer with each algorithm against a server in
+ * --sink mode, and prints the throughput and retransmissions of each
+ * (see compare.rs).
+ *
+ * Server usage: cargo run -- -s <address:port> [--rcvbuf <bytes>]
+ * Client usage: cargo run -- -c <address:port> -b <number of bytes> [--sndbuf <bytes>]
+ *               [--write-mode blocking|nonblocking|mio|timeout] [--timeout <ms>] [--cc <name>]
+ * Comparison:   cargo run -- -s <address:port> --sink
+ *               cargo run -- -c <address:port> -b <number of bytes> --compare [--cc <name,name,...>]
  */
 
 use std::{
-    cmp::min,
     error::Error,
-    io::{stdin, Read, Write},
-    net::TcpStream, net::TcpListener,
+    io::{self, stdin, Read},
+    net::TcpListener,
+    os::fd::AsRawFd,
+    sync::Arc,
 };
 
-use crate::args::Args;
+use socket2::SockRef;
+
+use crate::{
+    args::Args,
+    monitor::{start_sampler, Progress},
+};
 
 
 fn client(args: &Args) -> Result<(), Box<dyn Error>> {
     // Connect TCP socket. If connection fails, exit the main function with error.
-    // The connect function does both name resolution and connection establishment.
-    let mut socket = TcpStream::connect(args.connect_addr())?;
+    // The connect function does both name resolution and connection establishment,
+    // racing IPv6 and IPv4 addresses (Happy Eyeballs).
+    let (result, report) = happy_eyeballs::connect(args.connect_addr());
+    print!("{}", report);
+    let mut socket = result?;
+    set_buffer_sizes(SockRef::from(&socket), args)?;
+    if let Some(cc) = args.cc() {
+        let in_effect = tcp_congestion::set(socket.as_raw_fd(), cc)?;
+        println!("Congestion control: {}", in_effect);
+    }
 
-    let mut total: usize = 0;
+    let progress = Arc::new(Progress::new());
+    // Sampler stops when dropped, also if writing fails
+    let _sampler = args.interval()
+        .map(|interval| start_sampler(&socket, interval, "write", Arc::clone(&progress)));
 
-    // Just write the given number of bytes in 10000-byte chunks,
-    // containing 'A' to the socket.
-    // All errors cause the function to exit.
-    while args.bytes() - total > 0 {
-        let buffer: [u8; 10000] = [b'A'; 10000];
-        let to_write = min(args.bytes() - total, 10000);
-        let n = socket.write(&buffer[..to_write])?;
-        total += n;
-        println!("Wrote {} bytes, total written: {}", n, total);
-    }
+    writer::write_all(&mut socket, args, &progress)?;
+    Ok(())
+}
 
+
+// Apply buffer sizes given on command line, and print the sizes in effect.
+// Linux doubles the requested value to leave room for bookkeeping, and
+// limits it with net.core.wmem_max and net.core.rmem_max sysctls.
+fn set_buffer_sizes(socket: SockRef, args: &Args) -> io::Result<()> {
+    if let Some(size) = args.sndbuf() {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = args.rcvbuf() {
+        socket.set_recv_buffer_size(size)?;
+    }
+    println!("SO_SNDBUF: {} bytes, SO_RCVBUF: {} bytes",
+        socket.send_buffer_size()?, socket.recv_buffer_size()?);
     Ok(())
 }
 
@@ -41,9 +83,23 @@ fn client(args: &Args) -> Result<(), Box<dyn Error>> {
 fn server(args: &Args) -> Result<(), Box<dyn Error>> {
     let server = TcpListener::bind(args.server_addr())?;
 
+    // Accepted socket inherits the buffer sizes of the listening socket.
+    // Receive buffer size must be set before the connection is established,
+    // because it affects the window scaling negotiated in the handshake.
+    set_buffer_sizes(SockRef::from(&server), args)?;
+    if args.sink() {
+        return compare::sink(server);
+    }
+
     let (mut socket, address) = server.accept()?;
     println!("Accepted connection from {}. Press some key to start reading", address);
 
+    // While we wait for the key, the timeline shows the receive queue fill up
+    let progress = Arc::new(Progress::new());
+    // Sampler stops when dropped, also if reading fails
+    let _sampler = args.interval()
+        .map(|interval| start_sampler(&socket, interval, "read", Arc::clone(&progress)));
+
     let mut keypress = [0; 1];
     stdin().read_exact(&mut keypress)?;
 
@@ -52,7 +108,9 @@ fn server(args: &Args) -> Result<(), Box<dyn Error>> {
     let mut total: usize = 0;
     loop {
         let mut buf: [u8; 10000] = [0; 10000];
+        progress.begin_call();
         let n = socket.read(&mut buf)?;
+        progress.end_call(n);
         if n == 0 {
             println!("Connection closed, exiting.");
             break;
@@ -60,7 +118,6 @@ fn server(args: &Args) -> Result<(), Box<dyn Error>> {
         total += n;
         println!("Read {} bytes, total read: {}.", n, total);
     }
-
     Ok(())
 }
 
@@ -69,8 +126,10 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Parse command line arguments.
     let args = Args::new();
 
-    if args.server_addr().len() > 0 {
+    if !args.server_addr().is_empty() {
         server(&args)?;
+    } else if args.compare() {
+        compare::client(&args)?;
     } else {
         client(&args)?;
     }
@@ -79,3 +138,6 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 mod args;
+mod compare;
+mod monitor;
+mod writer;
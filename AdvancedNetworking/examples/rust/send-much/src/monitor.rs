@@ -0,0 +1,162 @@
+/* Sampling the kernel's view of a TCP connection while data is transferred.
+ *
+ * - TCP_INFO socket option gives the TCP state: congestion window (in
+ *   segments), smoothed RTT, number of unacknowledged segments and the total
+ *   number of retransmissions.
+ * - SIOCOUTQ ioctl gives the number of bytes in the send buffer, i.e., data
+ *   written by the application but not yet acknowledged by the receiver.
+ * - SIOCINQ ioctl gives the number of bytes in the receive buffer, i.e.,
+ *   data that has arrived but the application has not read yet.
+ *
+ * A sampler thread prints these at regular intervals, together with what
+ * the main thread is doing. When the receiver does not read, its receive
+ * queue fills up, then the sender's send queue fills up, and then write
+ * blocks.
+ */
+
+use std::{
+    io, mem,
+    net::TcpStream,
+    os::fd::{AsRawFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// One sample of the connection state.
+pub struct Sample {
+    pub cwnd: u32,     // congestion window, segments
+    pub rtt_us: u32,   // smoothed round-trip time, microseconds
+    pub unacked: u32,  // segments sent but not acknowledged
+    pub retrans: u32,  // retransmissions during the connection
+    pub outq: i32,     // bytes in send buffer
+    pub inq: i32,      // bytes in receive buffer
+}
+
+pub fn sample(fd: RawFd) -> io::Result<Sample> {
+    let mut info: libc::tcp_info = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_INFO,
+            &mut info as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Sample {
+        cwnd: info.tcpi_snd_cwnd,
+        rtt_us: info.tcpi_rtt,
+        unacked: info.tcpi_unacked,
+        retrans: info.tcpi_total_retrans,
+        outq: queue_len(fd, libc::TIOCOUTQ)?,  // SIOCOUTQ has the same value
+        inq: queue_len(fd, libc::FIONREAD)?,   // and so does SIOCINQ
+    })
+}
+
+fn queue_len(fd: RawFd, request: libc::Ioctl) -> io::Result<i32> {
+    let mut n: libc::c_int = 0;
+    if unsafe { libc::ioctl(fd, request, &mut n) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n)
+}
+
+/// Progress of the main thread, shared with the sampler.
+pub struct Progress {
+    start: Instant,
+    bytes: AtomicUsize,     // total bytes written or read
+    call_since: AtomicU64,  // microseconds from start when the current call
+                            // began, u64::MAX if not in a call
+    done: AtomicBool,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress {
+            start: Instant::now(),
+            bytes: AtomicUsize::new(0),
+            call_since: AtomicU64::new(u64::MAX),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Time since start, for the timeline.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Main thread is about to call write or read.
+    pub fn begin_call(&self) {
+        self.call_since.store(self.start.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The call returned `n` bytes. Returns how long the call took.
+    pub fn end_call(&self, n: usize) -> Duration {
+        let began = self.call_since.swap(u64::MAX, Ordering::Relaxed);
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+        self.start.elapsed().saturating_sub(Duration::from_micros(began))
+    }
+
+    fn finish(&self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+}
+
+
+/// Running sampler thread. Dropping it stops the thread and waits for it,
+/// so it must be dropped before the socket it samples. Declaring it after
+/// the socket does that also when the main thread exits with an error.
+pub struct Sampler {
+    progress: Arc<Progress>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.progress.finish();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+
+/// Start a thread that prints a sample every `interval`, until the returned
+/// Sampler is dropped.
+pub fn start_sampler(
+    socket: &TcpStream,
+    interval: Duration,
+    call: &'static str,
+    progress: Arc<Progress>,
+) -> Sampler {
+    let fd = socket.as_raw_fd();
+    println!("{:>8} {:>10} {:>14} {:>8} {:>8} {:>6} {:>8} {:>8} {:>8}",
+        "time_ms", "bytes", "call", "sendq", "recvq", "cwnd", "rtt_ms", "unacked", "retrans");
+    // The socket lives in the main thread until the Sampler is dropped, so
+    // the raw descriptor stays valid while the sampler runs.
+    let shared = Arc::clone(&progress);
+    let thread = thread::spawn(move || {
+        while !progress.done.load(Ordering::Relaxed) {
+            let now = progress.elapsed();
+            let since = progress.call_since.load(Ordering::Relaxed);
+            let state = if since == u64::MAX {
+                "-".to_string()
+            } else {
+                // How long the current call has been waiting
+                let waiting = now.saturating_sub(Duration::from_micros(since));
+                format!("{} {}ms", call, waiting.as_millis())
+            };
+            match sample(fd) {
+                Ok(s) => println!("{:>8} {:>10} {:>14} {:>8} {:>8} {:>6} {:>8.2} {:>8} {:>8}",
+                    now.as_millis(), progress.bytes.load(Ordering::Relaxed), state,
+                    s.outq, s.inq, s.cwnd, s.rtt_us as f64 / 1000.0, s.unacked, s.retrans),
+                Err(e) => println!("Sampling failed: {}", e),
+            }
+            thread::sleep(interval);
+        }
+    });
+    Sampler { progress: shared, thread: Some(thread) }
+}
@@ -0,0 +1,211 @@
+/* Different ways to write to a TCP socket when the send buffer is full.
+ *
+ * - Blocking: write() sleeps until there is room in the send buffer.
+ * - Non-blocking: write() returns WouldBlock immediately. The application
+ *   must retry later, here simply after a short pause (busy polling).
+ * - MIO: same non-blocking socket, but instead of guessing when to retry,
+ *   the application waits for a writable event from the operating system.
+ * - Timeout: blocking socket with SO_SNDTIMEO, write() blocks at most for
+ *   the given time, then returns WouldBlock if nothing could be written.
+ *
+ * In all modes write() may also write less than was asked (partial write),
+ * so the application must keep track of how much of its data is still unsent.
+ */
+
+use std::{
+    cmp::min,
+    io::{self, ErrorKind, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use mio::{Events, Interest, Poll, Token};
+
+use crate::{
+    args::{Args, WriteMode},
+    monitor::Progress,
+};
+
+const CHUNK: usize = 10000;
+
+// A write that takes longer than this is considered to have blocked.
+const BLOCK_THRESHOLD: Duration = Duration::from_millis(5);
+
+// How long the non-blocking mode sleeps before trying again.
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+
+// Write the number of bytes given on command line, containing 'A',
+// using the write mode given on command line.
+pub fn write_all(socket: &mut TcpStream, args: &Args, progress: &Progress) -> io::Result<()> {
+    match args.write_mode() {
+        WriteMode::Blocking => blocking(socket, args.bytes(), progress),
+        WriteMode::Nonblocking => nonblocking(socket, args.bytes(), progress),
+        WriteMode::Mio => mio_driven(socket, args.bytes(), progress),
+        WriteMode::Timeout => with_timeout(socket, args.bytes(), args.timeout(), progress),
+    }
+}
+
+
+fn blocking(socket: &mut TcpStream, bytes: usize, progress: &Progress) -> io::Result<()> {
+    let buffer = [b'A'; CHUNK];
+    let mut total: usize = 0;
+    let mut blocked = false;
+
+    // Just write the given number of bytes in 10000-byte chunks.
+    // All errors cause the function to exit.
+    while total < bytes {
+        let to_write = min(bytes - total, CHUNK);
+        progress.begin_call();
+        let n = socket.write(&buffer[..to_write])?;
+        let took = progress.end_call(n);
+        total += n;
+        println!("Wrote {} bytes, total written: {}", n, total);
+
+        // Tell when the send buffer became full for the first time
+        if !blocked && took >= BLOCK_THRESHOLD {
+            blocked = true;
+            println!("First blocking write: at {} ms, blocked {} ms, {} bytes written before it",
+                (progress.elapsed() - took).as_millis(), took.as_millis(), total - n);
+        }
+    }
+    Ok(())
+}
+
+
+fn nonblocking(socket: &mut TcpStream, bytes: usize, progress: &Progress) -> io::Result<()> {
+    socket.set_nonblocking(true)?;
+    let buffer = [b'A'; CHUNK];
+    let mut total: usize = 0;
+    let mut retries: usize = 0;   // failed attempts since the last successful write
+    let mut since = Instant::now();
+
+    while total < bytes {
+        let to_write = min(bytes - total, CHUNK);
+        progress.begin_call();
+        match socket.write(&buffer[..to_write]) {
+            Ok(n) => {
+                progress.end_call(n);
+                total += n;
+                if retries > 0 {
+                    println!("Writable again after {} ms and {} retries",
+                        since.elapsed().as_millis(), retries);
+                    retries = 0;
+                }
+                if n < to_write {
+                    println!("Partial write: {} of {} bytes, total written: {}",
+                        n, to_write, total);
+                } else {
+                    println!("Wrote {} bytes, total written: {}", n, total);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                // Nothing was written. Instead of sleeping in write(), we
+                // return to the application, which could do other work here.
+                progress.end_call(0);
+                if retries == 0 {
+                    println!("Write would block at {} ms, {} bytes written. Retrying every {} ms",
+                        progress.elapsed().as_millis(), total, RETRY_DELAY.as_millis());
+                    since = Instant::now();
+                }
+                retries += 1;
+                thread::sleep(RETRY_DELAY);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+
+fn mio_driven(socket: &mut TcpStream, bytes: usize, progress: &Progress) -> io::Result<()> {
+    // MIO takes ownership of the socket, so give it a duplicate descriptor.
+    // The original stays open for the sampler thread.
+    socket.set_nonblocking(true)?;
+    let mut stream = mio::net::TcpStream::from_std(socket.try_clone()?);
+
+    const SOCKET: Token = Token(0);
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(16);
+    poll.registry().register(&mut stream, SOCKET, Interest::WRITABLE)?;
+
+    let buffer = [b'A'; CHUNK];
+    let mut total: usize = 0;
+
+    // Write as long as the socket accepts data, then wait for the next
+    // writable event. MIO events are edge-triggered: a new event comes only
+    // after write has returned WouldBlock.
+    while total < bytes {
+        let to_write = min(bytes - total, CHUNK);
+        progress.begin_call();
+        match stream.write(&buffer[..to_write]) {
+            Ok(n) => {
+                progress.end_call(n);
+                total += n;
+                if n < to_write {
+                    println!("Partial write: {} of {} bytes, total written: {}",
+                        n, to_write, total);
+                } else {
+                    println!("Wrote {} bytes, total written: {}", n, total);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                progress.end_call(0);
+                println!("Write would block at {} ms, {} bytes written. Waiting for writable event",
+                    progress.elapsed().as_millis(), total);
+                let start = Instant::now();
+                loop {
+                    poll.poll(&mut events, None)?;
+                    if events.iter().any(|event| event.token() == SOCKET && event.is_writable()) {
+                        break;
+                    }
+                }
+                println!("Writable event after {} ms", start.elapsed().as_millis());
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+
+fn with_timeout(socket: &mut TcpStream, bytes: usize, timeout: Duration, progress: &Progress)
+    -> io::Result<()> {
+    socket.set_write_timeout(Some(timeout))?;
+    println!("Send timeout (SO_SNDTIMEO) set to {} ms", timeout.as_millis());
+
+    let buffer = [b'A'; CHUNK];
+    let mut total: usize = 0;
+    let mut timeouts: usize = 0;
+
+    while total < bytes {
+        let to_write = min(bytes - total, CHUNK);
+        progress.begin_call();
+        match socket.write(&buffer[..to_write]) {
+            Ok(n) => {
+                let took = progress.end_call(n);
+                total += n;
+                // If the timeout expires after some of the data was copied
+                // to the send buffer, write returns the partial count.
+                if n < to_write {
+                    println!("Partial write after {} ms: {} of {} bytes, total written: {}",
+                        took.as_millis(), n, to_write, total);
+                } else {
+                    println!("Wrote {} bytes, total written: {}", n, total);
+                }
+            }
+            // Linux reports the expired timeout as EAGAIN, which Rust maps
+            // to WouldBlock. Other systems may use TimedOut.
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                let took = progress.end_call(0);
+                timeouts += 1;
+                println!("Write timed out after {} ms at {} ms, {} bytes written ({} timeouts)",
+                    took.as_millis(), progress.elapsed().as_millis(), total, timeouts);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
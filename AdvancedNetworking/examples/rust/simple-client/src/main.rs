@@ -1,6 +1,19 @@
 /* Open TCP socket, send some data and receive data back.
+ *
+ * Without framing, the string is written once, and whatever the first read
+ * returns is printed. TCP does not preserve message boundaries, so the reply
+ * may be only part of what the server sent, or contain more than one reply.
+ *
+ * With framing ("length", "newline" or "fixed:<size>"), the string is sent
+ * as one complete message, and exactly one message is read back, no matter
+ * how many reads it takes. The server must understand the same framing;
+ * echo servers work with any of them, because they send the bytes back as
+ * they are. See the framing library for details.
+ *
+ * If the host name has both IPv6 and IPv4 addresses, they are raced using
+ * Happy Eyeballs, and the addresses tried are printed.
  * 
- * Usage: cargo run -- <name/address>:<port> <string>
+ * Usage: cargo run -- <name/address>:<port> <string> [<framing>]
  */
 
 use std::{
@@ -10,19 +23,37 @@ use std::{
     net::TcpStream,
 };
 
+use framing::{blocking::Framed, Codec};
+
 
 fn main() -> Result<(), Box<dyn Error>> {
     // Collect command-line arguments into a vector
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 3 {
-        eprintln!("arguments: <host>:<port> <message>");
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("arguments: <host>:<port> <message> [length|newline|fixed:<size>]");
         return Err("Invalid command".into());
     }
 
     // Connect TCP socket. If connection fails, exit the main function with error.
-    // The connect function does both name resolution and connection establishment.
-    let mut socket = TcpStream::connect(&args[1])?;
+    // The connect function does both name resolution and connection establishment,
+    // trying IPv6 and IPv4 addresses in parallel.
+    let (result, report) = happy_eyeballs::connect(&args[1]);
+    print!("{}", report);
+    let mut socket: TcpStream = result?;
+
+    if let Some(framing) = args.get(3) {
+        // Framed stream takes care of writing everything and reading until
+        // the whole message has arrived.
+        let codec: Codec = framing.parse()?;
+        let mut framed = Framed::new(socket, codec);
+        framed.send(args[2].as_bytes())?;
+        match framed.recv()? {
+            Some(reply) => println!("{}", String::from_utf8_lossy(&reply)),
+            None => println!("Server closed the connection without reply"),
+        }
+        return Ok(());
+    }
 
     // as_bytes() converts the string from command line argument into a u8 byte slice.
     // If write produces an error, we exit the main function immediately.
@@ -34,12 +65,12 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     // Allocate u8 buffer of 160 bytes and read data to it from socket.
-    // Print the output to screen.
+    // Print the bytes that were read to screen, not the whole buffer.
     // Assume it is UTF-8 encoded string, otherwise interrupts with error.
     // If read causes error, exits the main function
     let mut buf: [u8; 160] = [0; 160];
-    socket.read(&mut buf)?;
-    println!("{}", std::str::from_utf8(&buf)?);
+    let n = socket.read(&mut buf)?;
+    println!("{}", std::str::from_utf8(&buf[..n])?);
 
     Ok(())  // Everything successful!
 }
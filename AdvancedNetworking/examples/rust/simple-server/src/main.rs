@@ -2,6 +2,11 @@
  * Handle incoming connections one at the time: read some data from socket,
  * and echo it back. Bind to "0.0.0.0:<port>" if connections are allowed from any
  * interface.
+ *
+ * On SIGINT (Ctrl-C) or SIGTERM the server stops accepting new connections,
+ * lets the connection being served finish within a deadline, prints how many
+ * bytes were echoed to each client, and exits. A second signal exits
+ * immediately.
  * 
  * Usage: cargo run -- <IP>:<port>
  */
@@ -9,10 +14,25 @@
 use std::{
     env,
     error::Error,
-    io::{Read, Write},
-    net::TcpListener,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+use signal_hook::consts::TERM_SIGNALS;
+
+// How long the connection being served may take after shutdown was requested.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often reads from the client wake up to check whether shutdown was
+// requested.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 
 fn main() -> Result<(), Box<dyn Error>> {
     // Collect command-line arguments into a vector
@@ -23,29 +43,121 @@ fn main() -> Result<(), Box<dyn Error>> {
         return Err("Invalid command".into());
     }
 
+    // Set up signal handling. The signal handler sets a flag that we check
+    // while serving a client. If the flag is already set when a signal
+    // arrives, i.e., this is the second signal, the process exits
+    // immediately. The handler also writes a byte to a pipe (the "self-pipe
+    // trick"), so that we can wait for a signal and a new connection at the
+    // same time with poll.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let (signal_pipe, signal_write) = UnixStream::pair()?;
+    for sig in TERM_SIGNALS {
+        signal_hook::flag::register_conditional_shutdown(*sig, 1, Arc::clone(&shutdown))?;
+        signal_hook::flag::register(*sig, Arc::clone(&shutdown))?;
+        signal_hook::low_level::pipe::register(*sig, signal_write.try_clone()?)?;
+    }
+
     // Create a passive server socket and bind to address given as command line argument.
     // If there is an error in bind, exit the main function with error.
     let server = TcpListener::bind(&args[1])?;
 
-    loop {
-        // Wait until new connection request comes in.
-        // accept returns active socket and address of the connecting host as tuple.
-        let (mut socket, address) = server.accept()?;
-        println!("Accepting connection from {}", address.to_string());
+    // Accept only after poll tells there is a connection. The socket is
+    // non-blocking in case the connection is gone by the time we accept it.
+    server.set_nonblocking(true)?;
+
+    // Number of bytes echoed to each client
+    let mut echoed: Vec<(SocketAddr, usize)> = Vec::new();
+
+    while !shutdown.load(Ordering::Relaxed) {
+        // Sleep until there is a new connection request, or a signal.
+        if !wait_for_connection(&server, &signal_pipe)? {
+            break;
+        }
 
-        // Read at most 160 bytes from the established connection
-        // 'readn' will contain the number of bytes actually read.
-        // If read fails, the error causes main function to exit. 
-        let mut buf: [u8; 160] = [0; 160];
-        let readn = socket.read(&mut buf)?;
+        // accept returns active socket and address of the connecting host as tuple.
+        let (socket, address) = match server.accept() {
+            Ok(a) => a,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e.into()),
+        };
+        println!("Accepting connection from {}", address);
 
-        // Write the bytes that were read back to the client.
-        // 'writen' will contain the number of bytes actually written.
-        // If function fails, the error causes main function to exit.
-        let writen = socket.write(&buf[..readn])?;
-        println!("Wrote {} bytes", writen);
+        // A failing client does not stop the server
+        match serve_client(socket, &shutdown) {
+            Ok(n) => echoed.push((address, n)),
+            Err(e) => eprintln!("Error with client {}: {}", address, e),
+        }
 
         // Client socket is implicitly closed as 'socket' goes out of scope
-        // at the end of loop. We are ready to accept the next connection.
+        // in serve_client. We are ready to accept the next connection.
+    }
+
+    println!("Shutting down");
+    println!("Bytes echoed per client:");
+    for (address, n) in echoed {
+        println!("  {}: {}", address, n);
     }
+    Ok(())
+}
+
+
+// Wait until the listening socket has a connection to accept (returns true),
+// or a signal has been written to the pipe (returns false).
+fn wait_for_connection(server: &TcpListener, signal_pipe: &UnixStream) -> io::Result<bool> {
+    let mut fds = [
+        libc::pollfd { fd: server.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: signal_pipe.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+    ];
+    loop {
+        let rc = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if rc >= 0 {
+            return Ok(fds[1].revents == 0);
+        }
+        // The signal interrupts poll; the pipe is readable when we try again
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+
+// Read some data and echo it back. Returns the number of bytes written.
+fn serve_client(mut socket: TcpStream, shutdown: &AtomicBool) -> Result<usize, Box<dyn Error>> {
+    // Use blocking socket, but with read timeout, so that we can check the
+    // shutdown flag while waiting for the client.
+    socket.set_nonblocking(false)?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut deadline: Option<Instant> = None;
+
+    // Read at most 160 bytes from the established connection
+    // 'readn' will contain the number of bytes actually read.
+    // If read fails, the error is returned to main function, which reports it.
+    let mut buf: [u8; 160] = [0; 160];
+    let readn = loop {
+        match socket.read(&mut buf) {
+            Ok(n) => break n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                || e.kind() == io::ErrorKind::TimedOut => {
+                // No data yet. If shutdown has been requested, wait only
+                // until the deadline.
+                if shutdown.load(Ordering::Relaxed) {
+                    let deadline = *deadline.get_or_insert_with(|| Instant::now() + DRAIN_TIMEOUT);
+                    if Instant::now() >= deadline {
+                        println!("Client did not finish in time, closing");
+                        return Ok(0);
+                    }
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    // Write the bytes that were read back to the client.
+    // 'writen' will contain the number of bytes actually written.
+    // If function fails, the error is returned to main function.
+    let writen = socket.write(&buf[..readn])?;
+    println!("Wrote {} bytes", writen);
+
+    Ok(writen)
 }
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use clap::Parser;
 
 /// Command line arguments parser for this application.
@@ -11,13 +13,43 @@ pub struct Args {
     /// Start as server, give address to listen for connections.
     #[arg(short, long, default_value_t = 0)]
     server_port: u16,
+
+    /// As client, how long to wait for response before sending again,
+    /// in milliseconds. Doubled after each retry.
+    #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
+    /// As client, how many times to send again if there is no response.
+    #[arg(short, long, default_value_t = 3)]
+    retries: u32,
+
+    /// As server, join this multicast group and receive datagrams sent to it,
+    /// e.g. 239.0.0.1 or ff15::1.
+    #[arg(short, long)]
+    group: Option<String>,
+
+    /// With --group, receive only datagrams from this source (source-specific
+    /// multicast).
+    #[arg(long)]
+    source: Option<String>,
+
+    /// Network interface used for multicast, e.g. eth0. By default the
+    /// kernel chooses based on the routing table.
+    #[arg(short, long)]
+    interface: Option<String>,
+
+    /// TTL (IPv4) or hop limit (IPv6) of multicast datagrams sent.
+    #[arg(long, default_value_t = 1)]
+    ttl: u32,
+
+    /// Do not loop sent multicast datagrams back to receivers on this host.
+    #[arg(long)]
+    no_loop: bool,
 }
 
 impl Args {
     pub fn new() -> Args {
-        let args = Args::parse();
-
-        args
+        Args::parse()
     }
 
     pub fn connect_addr(&self) -> &String {
@@ -27,4 +59,32 @@ impl Args {
     pub fn server_port(&self) -> u16 {
         self.server_port
     }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout)
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub fn group(&self) -> Option<&String> {
+        self.group.as_ref()
+    }
+
+    pub fn source(&self) -> Option<&String> {
+        self.source.as_ref()
+    }
+
+    pub fn interface(&self) -> Option<&String> {
+        self.interface.as_ref()
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    pub fn no_loop(&self) -> bool {
+        self.no_loop
+    }
 }
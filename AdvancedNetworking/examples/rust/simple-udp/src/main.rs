@@ -1,57 +1,172 @@
 /* Illustrates use of UDP socket, by sending user input to given destination address.
  * Server echoes the datagram content back to client that prints it.
- * 
+ *
+ * Datagrams may be lost, so the client waits for the response only for a
+ * while, and sends the message again if it does not arrive. Datagrams larger
+ * than the receive buffer are detected with MSG_TRUNC. The server socket
+ * accepts both IPv6 and IPv4 clients.
+ *
+ * If the destination is a multicast group, the client sends numbered
+ * datagrams to the group without waiting for responses. With --group, the
+ * server joins the group and reports senders and missing datagrams instead of
+ * echoing. See multicast.rs.
+ *
  * Server usage: cargo run -- -s port
- * Client usage: cargo run -- -c <address:port>
+ * Client usage: cargo run -- -c <address:port> [--timeout <ms>] [--retries <n>]
+ * Multicast receiver: cargo run -- -s port -g <group> [--source <address>] [-i <interface>]
+ * Multicast sender:   cargo run -- -c <group:port> [--ttl <n>] [--no-loop] [-i <interface>]
  */
 
 use std::{
     error::Error,
-    io::stdin,
-    net::UdpSocket,
+    io::{self, stdin},
+    mem::MaybeUninit,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
 };
 
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
 use crate::args::Args;
 
 
 fn client(args: &Args) -> Result<(), Box<dyn Error>> {
+    if let Ok(group) = args.connect_addr().parse::<SocketAddr>() {
+        if group.ip().is_multicast() {
+            return multicast::sender(args, group);
+        }
+    }
+
+    // Resolve destination first, so that we can create a socket of the same
+    // address family (IPv4 or IPv6).
+    let server = args.connect_addr().to_socket_addrs()?.next()
+        .ok_or("could not resolve address")?;
+
     // Create UDP socket, bind to any address, pick a free UDP port
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let socket = match server {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
 
     loop {
         let mut input = String::new();
-        stdin().read_line(&mut input)?;
+        if stdin().read_line(&mut input)? == 0 {
+            return Ok(());  // end of input
+        }
+        let message = input.trim().as_bytes();
+
+        // UDP does not retransmit lost datagrams, so we must do it ourselves:
+        // wait for the response at most for the timeout, then send again.
+        // The timeout is doubled after each attempt (exponential backoff),
+        // to avoid flooding a congested network.
+        let mut timeout = args.timeout();
+        let mut attempt = 0;
+        loop {
+            // Send the line given by user to UDP socket, to given destination.
+            // Because UDP is not connection-oriented, we use send_to function that
+            // provides destination address in addition to data. Unlike with TCP, we
+            // can use the same socket for sending to multiple destinations.
+            socket.send_to(message, server)?;
+
+            // Read response back from UDP socket. Blocks execution until datagram
+            // arrives, or the timeout expires.
+            socket.set_read_timeout(Some(timeout))?;
+            match receive_response(&socket, server, message) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut => {
+                    attempt += 1;
+                    if attempt > args.retries() {
+                        println!("No response after {} retries, giving up", args.retries());
+                        break;
+                    }
+                    println!("No response in {} ms, retrying ({}/{})",
+                        timeout.as_millis(), attempt, args.retries());
+                    timeout *= 2;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
 
-        // Send the line given by user to UDP socket, to given destination.
-        // Because UDP is not connection-oriented, we use send_to function that
-        // provides destination address in addition to data. Unlike with TCP, we
-        // can use the same socket for sending to multiple destinations.
-        socket.send_to(input.trim().as_bytes(), args.connect_addr())?;
 
-        let mut buf = [0; 1024];
-        // Read response back from UDP socket. Blocks execution until datagram
-        // arrives. Recv_from returns number of bytes read, and the address/port
-        // of the sender.
-        let (size, src) = socket.recv_from(&mut buf)?;
+// Wait for the echo of message. Datagrams from other addresses, and late
+// responses to earlier messages (e.g., when the first attempt was delayed,
+// not lost) are ignored, as are datagrams too long for the buffer.
+fn receive_response(socket: &UdpSocket, server: SocketAddr, message: &[u8]) -> io::Result<()> {
+    let mut buf = [0; 1024];
+    loop {
+        let (size, src) = match recv_datagram(socket, &mut buf) {
+            Ok(r) => r,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                println!("Ignoring datagram: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
         let msg = String::from_utf8_lossy(&buf[..size]);
-        println!("Received from {}: {}", src, msg);
+        if src != server {
+            println!("Ignoring datagram from {}: {}", src, msg);
+        } else if &buf[..size] != message {
+            println!("Ignoring unexpected response: {}", msg);
+        } else {
+            println!("Received from {}: {}", src, msg);
+            return Ok(());
+        }
     }
 }
 
 
+// Like recv_from, but tells if the datagram did not fit in the buffer.
+// Normally the rest of a datagram is silently discarded. With MSG_TRUNC flag,
+// Linux returns the real length of the datagram, which is how we notice.
+fn recv_datagram(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    // socket2 takes the buffer as possibly uninitialized bytes. Ours is
+    // initialized, which is fine.
+    let uninit = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+    let (len, addr) = SockRef::from(socket).recv_from_with_flags(uninit, libc::MSG_TRUNC)?;
+    let src = addr.as_socket().ok_or_else(|| io::Error::other("unexpected address family"))?;
+    if len > buf.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("datagram from {} truncated: {} bytes, buffer is {} bytes",
+                src, len, buf.len())));
+    }
+    Ok((len, src))
+}
+
+
 fn server(args: &Args) -> Result<(), Box<dyn Error>> {
-    let socket = UdpSocket::bind(format!("0.0.0.0:{}", args.server_port()))?;
+    if let Some(group) = args.group() {
+        return multicast::receiver(args, group.parse()?);
+    }
+
+    // Bind to IPv6 wildcard address with IPV6_V6ONLY off, so that the socket
+    // receives both IPv6 and IPv4 datagrams. IPv4 senders are shown as
+    // IPv4-mapped IPv6 addresses, e.g., ::ffff:127.0.0.1.
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), args.server_port()).into())?;
+    let socket: UdpSocket = socket.into();
 
     loop {
         let mut buf = [0; 1024];
         // Block until datagram comes to the socket. Returns number of bytes read
         // and the address/port of the sender.
         // Convert the u8 array into UTF8 string.
-        let (size, src) = socket.recv_from(&mut buf)?;
+        let (size, src) = match recv_datagram(&socket, &mut buf) {
+            Ok(r) => r,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                // Echoing only part of the datagram would be wrong, so skip it
+                println!("Not echoed: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
         let msg = String::from_utf8_lossy(&buf[..size]);
         println!("Received from {}: {}", src, msg);
 
-        socket.send_to(&buf, src)?;
+        // Echo exactly the bytes received, not the whole buffer
+        socket.send_to(&buf[..size], src)?;
     }
 }
 
@@ -70,3 +185,4 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 mod args;
+mod multicast;
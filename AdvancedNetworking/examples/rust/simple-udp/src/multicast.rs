@@ -0,0 +1,328 @@
+/* Multicast sender and receiver.
+ *
+ * A multicast datagram is sent to a group address (224.0.0.0/4 in IPv4,
+ * ff00::/8 in IPv6) instead of a single host. Receivers must join the group
+ * on a network interface, which makes the host report its membership to the
+ * local routers (IGMP in IPv4, MLD in IPv6). The sender does not need to
+ * join, but it can choose:
+ * - the interface where datagrams are sent (IP_MULTICAST_IF)
+ * - how many routers the datagrams may cross (IP_MULTICAST_TTL, or hop limit
+ *   in IPv6). The default is 1, i.e., the local network only.
+ * - whether its own datagrams are looped back to receivers on the same host
+ *   (IP_MULTICAST_LOOP). This is on by default.
+ *
+ * With source-specific multicast (SSM, RFC 4607), the receiver joins a
+ * (source, group) pair and receives only datagrams from that source. The
+ * 232.0.0.0/8 and ff3x::/32 ranges are reserved for it.
+ *
+ * Group membership is managed here with the protocol independent
+ * MCAST_JOIN_GROUP and MCAST_JOIN_SOURCE_GROUP options (RFC 3678), which
+ * work the same way for IPv4 and IPv6 and take the interface as an index.
+ *
+ * The sender numbers each datagram, so that the receiver can tell when some
+ * datagrams did not arrive, or arrived late.
+ */
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    ffi::CString,
+    io::{self, stdin},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    os::fd::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::args::Args;
+
+
+pub fn sender(args: &Args, group: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let socket = Socket::new(Domain::for_address(group), Type::DGRAM, Some(Protocol::UDP))?;
+    let ifindex = interface_index(args.interface())?;
+
+    match group {
+        SocketAddr::V4(_) => {
+            socket.set_multicast_ttl_v4(args.ttl())?;
+            socket.set_multicast_loop_v4(!args.no_loop())?;
+            if ifindex != 0 {
+                set_multicast_if_v4(&socket, ifindex)?;
+            }
+        }
+        SocketAddr::V6(_) => {
+            socket.set_multicast_hops_v6(args.ttl())?;
+            socket.set_multicast_loop_v6(!args.no_loop())?;
+            socket.set_multicast_if_v6(ifindex)?;  // 0 lets the kernel choose
+        }
+    }
+    let socket: UdpSocket = socket.into();
+    println!("Sending to group {} with TTL {}, loopback {}. Each input line is one datagram.",
+        group, args.ttl(), if args.no_loop() { "off" } else { "on" });
+
+    // Prefix each line with a sequence number. Nobody answers to multicast,
+    // so unlike the unicast client we do not wait for a response.
+    let mut seq: u64 = 0;
+    for line in stdin().lines() {
+        let line = line?;
+        seq += 1;
+        socket.send_to(format!("{} {}", seq, line.trim()).as_bytes(), group)?;
+    }
+    Ok(())
+}
+
+
+// A jump larger than this is taken as the sender starting over, rather than
+// that many lost datagrams.
+const MAX_GAP: u64 = 10000;
+
+// Sequence number bookkeeping for one sender.
+struct Stream {
+    next: u64,              // sequence number expected next
+    received: u64,
+    missing: BTreeSet<u64>, // skipped sequence numbers not arrived yet
+    late: u64,              // arrived after a later datagram
+    duplicate: u64,         // arrived already earlier
+}
+
+impl Stream {
+    fn new(seq: u64) -> Stream {
+        // We may have joined in the middle of the stream, so the first
+        // datagram we see sets the starting point.
+        Stream { next: seq, received: 0, missing: BTreeSet::new(), late: 0, duplicate: 0 }
+    }
+
+    fn update(&mut self, seq: u64, src: SocketAddr) {
+        self.received += 1;
+        if seq > self.next.saturating_add(MAX_GAP) {
+            println!("Sequence from {} jumped from {} to {}, starting over", src, self.next, seq);
+            self.missing.clear();
+        } else if seq > self.next {
+            self.missing.extend(self.next..seq);
+            println!("Gap from {}: expected {}, got {}, {} missing", src, self.next, seq,
+                seq - self.next);
+        } else if seq < self.next {
+            // A late datagram fills its gap. Anything else we have seen.
+            if self.missing.remove(&seq) {
+                self.late += 1;
+                println!("Late from {}: {}", src, seq);
+            } else {
+                self.duplicate += 1;
+                println!("Duplicate from {}: {}", src, seq);
+            }
+            return;
+        }
+        self.next = seq.saturating_add(1);
+    }
+}
+
+
+pub fn receiver(args: &Args, group: IpAddr) -> Result<(), Box<dyn Error>> {
+    let ifindex = interface_index(args.interface())?;
+    let source = args.source().map(|s| s.parse::<IpAddr>()).transpose()?;
+
+    // Bind to the wildcard address and the port the sender uses. Reuse
+    // address allows running several receivers on the same host.
+    let bind_addr = match group {
+        IpAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), args.server_port()),
+        IpAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), args.server_port()),
+    };
+    let socket = Socket::new(Domain::for_address(bind_addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+
+    // By default Linux delivers to a wildcard-bound socket the datagrams of
+    // every group joined by any socket on the host. We want only our own.
+    match group {
+        IpAddr::V4(_) => socket.set_multicast_all_v4(false)?,
+        IpAddr::V6(_) => socket.set_multicast_all_v6(false)?,
+    }
+    socket.bind(&bind_addr.into())?;
+
+    membership(&socket, true, group, source, ifindex)?;
+    match source {
+        Some(source) => println!("Joined group {} for source {} on port {}",
+            group, source, args.server_port()),
+        None => println!("Joined group {} on port {}", group, args.server_port()),
+    }
+
+    // Stop on Ctrl-C. The read timeout makes recv_from return regularly,
+    // so that we notice the flag.
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGINT, Arc::clone(&stop))?;
+    signal_hook::flag::register(SIGTERM, Arc::clone(&stop))?;
+    let socket: UdpSocket = socket.into();
+    socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+
+    let mut senders: HashMap<SocketAddr, Stream> = HashMap::new();
+    let mut buf = [0; 1024];
+    while !stop.load(Ordering::Relaxed) {
+        let (size, src) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock
+                || e.kind() == io::ErrorKind::TimedOut
+                || e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let msg = String::from_utf8_lossy(&buf[..size]);
+        println!("Received from {}: {}", src, msg);
+
+        let seq = msg.split_once(' ').and_then(|(seq, _)| seq.parse::<u64>().ok());
+        if let Some(seq) = seq {
+            senders.entry(src).or_insert_with(|| Stream::new(seq)).update(seq, src);
+        }
+    }
+
+    // Leaving happens also when the socket is closed, but leaving explicitly
+    // shows that membership is a property of the socket.
+    membership(&socket, false, group, source, ifindex)?;
+    println!("Left group {}", group);
+    for (src, stream) in &senders {
+        println!("{}: {} received, {} missing, {} late, {} duplicate",
+            src, stream.received, stream.missing.len(), stream.late, stream.duplicate);
+    }
+    Ok(())
+}
+
+
+// Returns the index of the named interface, or 0 if no name was given,
+// letting the kernel choose the interface based on the routing table.
+fn interface_index(name: Option<&String>) -> io::Result<u32> {
+    let Some(name) = name else {
+        return Ok(0);
+    };
+    let cname = CString::new(name.as_str())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+    match unsafe { libc::if_nametoindex(cname.as_ptr()) } {
+        0 => Err(io::Error::new(io::ErrorKind::NotFound,
+            format!("unknown interface: {}", name))),
+        index => Ok(index),
+    }
+}
+
+
+// IPv4 multicast interface given by index, with the Linux ip_mreqn structure.
+// (socket2 only supports giving the interface address.)
+fn set_multicast_if_v4(socket: &Socket, ifindex: u32) -> io::Result<()> {
+    let mreqn = libc::ip_mreqn {
+        imr_multiaddr: libc::in_addr { s_addr: 0 },
+        imr_address: libc::in_addr { s_addr: 0 },
+        imr_ifindex: ifindex as libc::c_int,
+    };
+    setsockopt(socket.as_raw_fd(), libc::IPPROTO_IP, libc::IP_MULTICAST_IF, &mreqn)
+}
+
+
+// Join or leave group, optionally for a single source only.
+fn membership(socket: &impl AsRawFd, join: bool, group: IpAddr, source: Option<IpAddr>,
+    ifindex: u32) -> io::Result<()> {
+    let level = match group {
+        IpAddr::V4(_) => libc::IPPROTO_IP,
+        IpAddr::V6(_) => libc::IPPROTO_IPV6,
+    };
+    let group_addr = SockAddr::from(SocketAddr::new(group, 0)).as_storage();
+
+    match source {
+        None => {
+            let req = libc::group_req { gr_interface: ifindex, gr_group: group_addr };
+            let option = if join { libc::MCAST_JOIN_GROUP } else { libc::MCAST_LEAVE_GROUP };
+            setsockopt(socket.as_raw_fd(), level, option, &req)
+        }
+        Some(source) => {
+            if source.is_ipv4() != group.is_ipv4() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    "source and group must be of the same address family"));
+            }
+            let req = libc::group_source_req {
+                gsr_interface: ifindex,
+                gsr_group: group_addr,
+                gsr_source: SockAddr::from(SocketAddr::new(source, 0)).as_storage(),
+            };
+            let option = if join {
+                libc::MCAST_JOIN_SOURCE_GROUP
+            } else {
+                libc::MCAST_LEAVE_SOURCE_GROUP
+            };
+            setsockopt(socket.as_raw_fd(), level, option, &req)
+        }
+    }
+}
+
+
+fn setsockopt<T>(fd: libc::c_int, level: libc::c_int, option: libc::c_int, value: &T)
+    -> io::Result<()> {
+    let rc = unsafe {
+        libc::setsockopt(fd, level, option, value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src() -> SocketAddr {
+        "127.0.0.1:20000".parse().unwrap()
+    }
+
+    #[test]
+    fn late_datagram_fills_gap() {
+        let mut stream = Stream::new(1);
+        for seq in [1, 2, 5] {
+            stream.update(seq, src());
+        }
+        assert_eq!(stream.missing, BTreeSet::from([3, 4]));
+        assert_eq!(stream.next, 6);
+
+        stream.update(4, src());
+        assert_eq!(stream.missing, BTreeSet::from([3]));
+        assert_eq!((stream.received, stream.late, stream.duplicate), (4, 1, 0));
+        assert_eq!(stream.next, 6);
+    }
+
+    #[test]
+    fn duplicate_is_counted_once() {
+        let mut stream = Stream::new(1);
+        for seq in [1, 2, 3, 2] {
+            stream.update(seq, src());
+        }
+        assert!(stream.missing.is_empty());
+        assert_eq!((stream.received, stream.late, stream.duplicate), (4, 0, 1));
+
+        // A late datagram that arrives twice is a duplicate the second time
+        stream.update(5, src());
+        stream.update(4, src());
+        stream.update(4, src());
+        assert_eq!((stream.received, stream.late, stream.duplicate), (7, 1, 2));
+    }
+
+    #[test]
+    fn large_jump_starts_over() {
+        let mut stream = Stream::new(1);
+        for seq in [1, 2, 4] {
+            stream.update(seq, src());
+        }
+        assert_eq!(stream.missing, BTreeSet::from([3]));
+
+        // Far beyond MAX_GAP: the sender restarted, nothing is missing
+        let seq = 5 + MAX_GAP + 1;
+        stream.update(seq, src());
+        assert!(stream.missing.is_empty());
+        assert_eq!(stream.next, seq + 1);
+
+        // A gap of exactly MAX_GAP is still counted as missing datagrams
+        let mut stream = Stream::new(1);
+        stream.update(1 + MAX_GAP, src());
+        assert_eq!(stream.missing.len() as u64, MAX_GAP);
+    }
+}
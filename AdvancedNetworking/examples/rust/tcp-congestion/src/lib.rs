@@ -0,0 +1,79 @@
+/* Selecting the congestion control algorithm of a single socket.
+ *
+ * Linux has a system-wide default algorithm (net.ipv4.tcp_congestion_control
+ * sysctl), but each socket can pick its own with the TCP_CONGESTION socket
+ * option. The algorithm must be loaded in the kernel: the available ones are
+ * listed in /proc/sys/net/ipv4/tcp_available_congestion_control, and others
+ * may be loaded as modules (e.g. "modprobe tcp_bbr"). Processes without
+ * CAP_NET_ADMIN can only use the algorithms listed in
+ * net.ipv4.tcp_allowed_congestion_control.
+ *
+ * The option can be set before or after connecting. Setting it on a
+ * connected socket switches the algorithm for the rest of the connection.
+ *
+ * Used by send-much and task-cli.
+ */
+
+use std::{
+    fs, io,
+    os::fd::RawFd,
+};
+
+// Maximum length of algorithm name in kernel, including the terminating zero.
+const TCP_CA_NAME_MAX: usize = 16;
+
+/// Sets the congestion control algorithm of socket, then reads back the
+/// algorithm in effect and returns its name.
+pub fn set(fd: RawFd, name: &str) -> io::Result<String> {
+    let rc = unsafe {
+        libc::setsockopt(fd, libc::IPPROTO_TCP, libc::TCP_CONGESTION,
+            name.as_ptr() as *const libc::c_void, name.len() as libc::socklen_t)
+    };
+    if rc < 0 {
+        let err = io::Error::last_os_error();
+        return Err(match err.raw_os_error() {
+            Some(libc::ENOENT) => io::Error::new(io::ErrorKind::NotFound,
+                format!("congestion control '{}' is not available, try 'modprobe tcp_{}'",
+                    name, name)),
+            Some(libc::EPERM) => io::Error::new(io::ErrorKind::PermissionDenied,
+                format!("congestion control '{}' is not in net.ipv4.tcp_allowed_congestion_control",
+                    name)),
+            _ => err,
+        });
+    }
+
+    let in_effect = get(fd)?;
+    if in_effect != name {
+        return Err(io::Error::other(
+            format!("requested congestion control '{}', but kernel uses '{}'", name, in_effect)));
+    }
+    Ok(in_effect)
+}
+
+/// Returns the name of congestion control algorithm used by socket.
+pub fn get(fd: RawFd) -> io::Result<String> {
+    let mut name = [0u8; TCP_CA_NAME_MAX];
+    let mut len = name.len() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_CONGESTION,
+            name.as_mut_ptr() as *mut libc::c_void, &mut len)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let name = &name[..len as usize];
+    let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Ok(String::from_utf8_lossy(&name[..end]).into_owned())
+}
+
+/// Whether the error from `set` means that the kernel refused the algorithm,
+/// rather than that something is wrong with the socket.
+pub fn is_refused(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::NotFound || err.kind() == io::ErrorKind::PermissionDenied
+}
+
+/// Returns the algorithms currently loaded in the kernel.
+pub fn available() -> io::Result<Vec<String>> {
+    let list = fs::read_to_string("/proc/sys/net/ipv4/tcp_available_congestion_control")?;
+    Ok(list.split_whitespace().map(String::from).collect())
+}
@@ -0,0 +1,79 @@
+use clap::{Parser, ValueEnum};
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Address to bind, as <IP>:<port>.
+    address: String,
+
+    /// How threads are used for serving clients.
+    #[arg(short, long, value_enum, default_value_t = Mode::PerConnection)]
+    mode: Mode,
+
+    /// Number of worker threads in pool mode.
+    #[arg(short, long, default_value_t = 4,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    workers: usize,
+
+    /// Number of accepted connections that can wait for a free worker.
+    #[arg(short, long, default_value_t = 16)]
+    queue: usize,
+
+    /// What to do with a new connection when the queue is full.
+    #[arg(short, long, value_enum, default_value_t = Reject::Block)]
+    reject: Reject,
+
+    /// Broadcast lines to other clients instead of echoing data back.
+    /// Only with per-connection mode.
+    #[arg(short, long)]
+    chat: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Spawn a new thread for each connection
+    PerConnection,
+    /// Fixed number of worker threads take connections from a queue
+    Pool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Reject {
+    /// Stop accepting until there is room in the queue
+    Block,
+    /// Close the new connection immediately
+    Close,
+    /// Send a busy message to the client, then close
+    Busy,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn address(&self) -> &String {
+        &self.address
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    pub fn queue(&self) -> usize {
+        self.queue
+    }
+
+    pub fn reject(&self) -> Reject {
+        self.reject
+    }
+
+    pub fn chat(&self) -> bool {
+        self.chat
+    }
+}
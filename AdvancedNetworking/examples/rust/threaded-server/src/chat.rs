@@ -0,0 +1,203 @@
+/* Chat mode for the threaded server: each complete line a client sends is
+ * delivered to all other clients, prefixed with the sender's nickname. The
+ * first line from a client is its nickname.
+ *
+ * The members of the chat room are shared by all client threads, so the room
+ * is protected by a mutex and shared with Arc, like the counter in the
+ * thread-mutex example. Writing to the other clients while holding the lock
+ * would let one slow client stop the whole room. Therefore each client has
+ * a writer thread that takes messages from a channel, and broadcasting just
+ * puts the message into the channels of the members. The channels are
+ * bounded: a member that does not read fast enough to keep its channel from
+ * filling up is disconnected, so that it cannot grow the memory of the
+ * server without limit. For the same reason, lines longer than MAX_LINE
+ * are dropped.
+ */
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+const PROMPT: &str = "Welcome to chat! Enter your nickname:\n";
+
+/// Messages waiting for a member's writer thread. A member with a full
+/// channel is disconnected.
+const MAX_QUEUED: usize = 256;
+
+/// Longest line accepted from a client. Longer lines are dropped.
+const MAX_LINE: usize = 1024;
+
+/// Chat room members, shared by all client threads.
+pub struct Room {
+    members: Mutex<HashMap<u64, Member>>,
+}
+
+struct Member {
+    nick: String,
+    address: SocketAddr,
+    tx: SyncSender<Arc<str>>,  // to the member's writer thread
+    socket: TcpStream,         // for disconnecting the member
+}
+
+impl Room {
+    pub fn new() -> Room {
+        Room {
+            members: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn join(&self, id: u64, member: Member) {
+        let mut members = self.members.lock().unwrap();
+        broadcast(&mut members, id, &format!("* {} joined\n", member.nick));
+        members.insert(id, member);
+    }
+
+    fn leave(&self, id: u64) {
+        let mut members = self.members.lock().unwrap();
+        if let Some(member) = members.remove(&id) {
+            broadcast(&mut members, id, &format!("* {} left\n", member.nick));
+        }
+    }
+
+    fn say(&self, id: u64, line: &str) {
+        let mut members = self.members.lock().unwrap();
+        if let Some(member) = members.get(&id) {
+            let message = format!("<{}> {}\n", member.nick, line);
+            broadcast(&mut members, id, &message);
+        }
+    }
+}
+
+// Send message to everyone except `from`. The caller holds the lock. Members
+// whose channel is full are removed from the room and disconnected; their
+// own threads then notice the closed socket and finish.
+fn broadcast(members: &mut HashMap<u64, Member>, from: u64, message: &str) {
+    // One allocation shared by all receivers
+    let message: Arc<str> = Arc::from(message);
+    members.retain(|id, member| {
+        if *id == from {
+            return true;
+        }
+        match member.tx.try_send(Arc::clone(&message)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                println!("Client {} ({}) is not reading, disconnecting", member.address, member.nick);
+                let _ = member.socket.shutdown(Shutdown::Both);
+                false
+            }
+            // The writer thread has exited, the member is about to leave anyway
+            Err(TrySendError::Disconnected(_)) => true,
+        }
+    });
+}
+
+
+/// Serve a chat client until it closes the connection. Returns the number of
+/// bytes sent to the client.
+pub fn process_client(socket: TcpStream, address: SocketAddr, id: u64, room: &Room) -> u64 {
+    let (tx, rx) = mpsc::sync_channel::<Arc<str>>(MAX_QUEUED);
+    let writer_socket = match socket.try_clone() {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Error cloning socket for {}: {}", address, e);
+            return 0;
+        }
+    };
+
+    // Writer thread sends everything that arrives in the channel. It ends
+    // when all senders are dropped, i.e., the client has left the room and
+    // this thread has finished.
+    let writer = thread::spawn(move || {
+        let mut socket = writer_socket;
+        let mut sent: u64 = 0;
+        for message in rx.iter() {
+            if socket.write_all(message.as_bytes()).is_err() {
+                break;
+            }
+            sent += message.len() as u64;
+        }
+        sent
+    });
+    let _ = tx.try_send(Arc::from(PROMPT));
+
+    if let Err(e) = read_lines(socket, address, id, room, &tx) {
+        println!("Error reading from {}: {}", address, e);
+    }
+    room.leave(id);
+
+    drop(tx);
+    writer.join().unwrap_or(0)
+}
+
+// Read lines from the client. The first non-empty line is the nickname.
+fn read_lines(
+    socket: TcpStream,
+    address: SocketAddr,
+    id: u64,
+    room: &Room,
+    tx: &SyncSender<Arc<str>>,
+) -> io::Result<()> {
+    let mut joined = false;
+    let member_socket = socket.try_clone()?;
+    let mut reader = BufReader::new(socket);
+    let mut buf = Vec::new();
+    while let Some(line) = next_line(&mut reader, &mut buf, address)? {
+        if joined {
+            room.say(id, &line);
+            continue;
+        }
+        let nick = line.trim();
+        if nick.is_empty() {
+            let _ = tx.try_send(Arc::from(PROMPT));
+            continue;
+        }
+        println!("Client {} joined chat as {}", address, nick);
+        room.join(id, Member {
+            nick: nick.to_string(),
+            address,
+            tx: tx.clone(),
+            socket: member_socket.try_clone()?,
+        });
+        joined = true;
+    }
+    println!("Client {} closed connection", address);
+    Ok(())
+}
+
+// Read the next line, without the line feed. Returns None when the client
+// has closed the connection. A client that never sends a line feed cannot
+// make us buffer without limit: lines longer than MAX_LINE are dropped.
+// Invalid UTF-8 is replaced rather than treated as an error.
+fn next_line(
+    reader: &mut BufReader<TcpStream>,
+    buf: &mut Vec<u8>,
+    address: SocketAddr,
+) -> io::Result<Option<String>> {
+    loop {
+        buf.clear();
+        if reader.take(MAX_LINE as u64 + 1).read_until(b'\n', buf)? == 0 {
+            return Ok(None);
+        }
+        if buf.len() <= MAX_LINE || buf.ends_with(b"\n") {
+            break;
+        }
+        println!("Line from {} is longer than {} bytes, dropping it", address, MAX_LINE);
+        // Skip to the end of the line
+        while !buf.ends_with(b"\n") {
+            buf.clear();
+            if reader.take(MAX_LINE as u64).read_until(b'\n', buf)? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+    let line = buf.strip_suffix(b"\n").unwrap_or(buf);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Ok(Some(String::from_utf8_lossy(line).into_owned()))
+}
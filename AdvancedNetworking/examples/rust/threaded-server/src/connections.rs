@@ -0,0 +1,95 @@
+/* Book-keeping of connections, shared by all threads, for graceful shutdown.
+ * Each accepted connection is added here with a clone of its socket. When
+ * shutting down, the main thread waits until the client threads have
+ * finished their connections. If that takes too long, the remaining sockets
+ * are shut down through the clones, so that reads in the client threads
+ * return and the threads finish.
+ */
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+pub struct Connections {
+    state: Mutex<State>,
+    changed: Condvar,  // notified when a connection finishes
+}
+
+struct State {
+    next_id: u64,
+    active: HashMap<u64, (SocketAddr, TcpStream)>,
+    finished: Vec<(SocketAddr, u64)>,  // bytes echoed to each finished client
+}
+
+impl Connections {
+    pub fn new() -> Connections {
+        Connections {
+            state: Mutex::new(State {
+                next_id: 0,
+                active: HashMap::new(),
+                finished: Vec::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Add accepted connection. Returns identifier for the connection.
+    pub fn add(&self, socket: &TcpStream, address: SocketAddr) -> io::Result<u64> {
+        let clone = socket.try_clone()?;
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.active.insert(id, (address, clone));
+        Ok(id)
+    }
+
+    /// Connection has finished after echoing `bytes`.
+    pub fn finish(&self, id: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((address, _)) = state.active.remove(&id) {
+            state.finished.push((address, bytes));
+        }
+        self.changed.notify_all();
+    }
+
+    /// Wait until all connections have finished, at most `timeout`. Returns
+    /// true if all connections finished.
+    pub fn wait_all(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        while !state.active.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            // Condvar releases the lock while waiting
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        true
+    }
+
+    /// Shut down all remaining connections.
+    pub fn close_all(&self) {
+        let state = self.state.lock().unwrap();
+        for (address, socket) in state.active.values() {
+            println!("Closing connection to {}", address);
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.state.lock().unwrap().active.len()
+    }
+
+    pub fn print_report(&self) {
+        let state = self.state.lock().unwrap();
+        println!("Bytes echoed per client:");
+        for (address, bytes) in state.finished.iter() {
+            println!("  {}: {}", address, bytes);
+        }
+    }
+}
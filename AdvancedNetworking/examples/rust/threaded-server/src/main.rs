@@ -2,45 +2,219 @@
  * For each incoming connection, a new thread is spawned to handle the connection.
  * For each connection, read incoming data and echo it back, until connection closes.
  * Bind to "0.0.0.0:<port>" if connections are allowed from any interface.
+ *
+ * Alternatively, with "--mode pool", connections are served by a fixed number
+ * of worker threads (see pool.rs), to compare the two designs.
+ *
+ * On SIGINT (Ctrl-C) or SIGTERM the server stops accepting new connections,
+ * waits for the active connections to finish within a deadline, closes those
+ * that did not, prints how many bytes were echoed to each client, and exits.
+ * A second signal exits immediately.
+ *
+ * With "--chat", lines from one client are broadcast to all other clients
+ * instead of echoing (see chat.rs). Try with several netcats.
  * 
  * Usage: cargo run -- <IP>:<port>
+ *        cargo run -- --mode pool --workers 4 --queue 16 --reject busy <IP>:<port>
+ *        cargo run -- --chat <IP>:<port>
  */
 
 use std::{
-    env,
     error::Error,
-    io::{Read, Write},
+    io::{self, Read, Write},
     net::{SocketAddr, TcpListener, TcpStream},
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     thread,
+    time::Duration,
 };
 
+use signal_hook::consts::TERM_SIGNALS;
+
+use crate::{
+    args::{Args, Mode},
+    chat::Room,
+    connections::Connections,
+};
+
+// How long active connections may take after shutdown was requested.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long to wait before accepting again after accept failed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Collect command-line arguments into a vector
-    let args: Vec<String> = env::args().collect();
+    // Parse command line arguments
+    let args = Args::new();
+    if args.chat() && args.mode() == Mode::Pool {
+        // Chat clients stay connected, a few workers would fill up quickly
+        return Err("Chat needs per-connection mode".into());
+    }
 
-    if args.len() != 2 {
-        eprintln!("arguments: <host>:<port>");
-        return Err("Invalid command".into());
+    // Set up signal handling. The signal handler sets a flag that the
+    // accepting loop checks. If the flag is already set when a signal
+    // arrives, i.e., this is the second signal, the process exits immediately.
+    // The handler also writes a byte to a pipe, so that the accepting loop
+    // can wait for a signal and a new connection at the same time with poll,
+    // like in simple-server.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let (signal_pipe, signal_write) = UnixStream::pair()?;
+    for sig in TERM_SIGNALS {
+        signal_hook::flag::register_conditional_shutdown(*sig, 1, Arc::clone(&shutdown))?;
+        signal_hook::flag::register(*sig, Arc::clone(&shutdown))?;
+        signal_hook::low_level::pipe::register(*sig, signal_write.try_clone()?)?;
     }
 
     // Create a passive server socket and bind to address given as command line argument.
     // If there is an error in bind, exit the main function with error.
-    let server = TcpListener::bind(&args[1])?;
+    let server = TcpListener::bind(args.address())?;
+
+    // Accept only after poll tells there is a connection. The socket is
+    // non-blocking in case the connection is gone by the time we accept it.
+    server.set_nonblocking(true)?;
+
+    // Active and finished connections, shared by all threads.
+    let connections = Arc::new(Connections::new());
+
+    // Both modes return when shutdown has been requested.
+    match args.mode() {
+        Mode::PerConnection =>
+            serve_per_connection(&server, &signal_pipe, args.chat(), &shutdown, &connections)?,
+        Mode::Pool => pool::serve(&server, &signal_pipe, &args, &shutdown, &connections)?,
+    }
+    drop(server);  // stop accepting, new connection attempts are refused
+
+    println!("Shutting down, waiting up to {} seconds for {} active connections",
+        DRAIN_TIMEOUT.as_secs(), connections.active());
+    if !connections.wait_all(DRAIN_TIMEOUT) {
+        // Threads notice the closed sockets, and finish quickly.
+        connections.close_all();
+        connections.wait_all(Duration::from_secs(1));
+    }
+    connections.print_report();
+    Ok(())
+}
+
+
+// Wait for the next connection. Returns None when shutdown is requested.
+// Failing accepts are reported, and do not stop the server.
+fn accept_next(
+    server: &TcpListener,
+    signal_pipe: &UnixStream,
+    shutdown: &AtomicBool,
+) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+    while !shutdown.load(Ordering::Relaxed) {
+        // Sleep until there is a new connection request, or a signal.
+        if !wait_for_connection(server, signal_pipe)? {
+            break;
+        }
 
-    loop {
-        // Wait until new connection request comes in.
         // accept returns active socket and address of the connecting host as tuple.
-        let (socket, address) = server.accept()?;
-        println!("Accepting connection from {}", address.to_string());
+        match server.accept() {
+            Ok((socket, address)) => {
+                // Client threads use blocking I/O
+                match socket.set_nonblocking(false) {
+                    Ok(()) => return Ok(Some((socket, address))),
+                    Err(e) => println!("Error with connection from {}: {}", address, e),
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => {
+                // For example out of file descriptors. The connection stays
+                // in the listen queue, so poll would return right away.
+                // Wait for a moment for other clients to finish.
+                println!("Error accepting connection: {}", e);
+                thread::sleep(ACCEPT_BACKOFF);
+            }
+        }
+    }
+    Ok(None)
+}
+
+
+// Wait until the listening socket has a connection to accept (returns true),
+// or a signal has been written to the pipe (returns false).
+fn wait_for_connection(server: &TcpListener, signal_pipe: &UnixStream) -> io::Result<bool> {
+    let mut fds = [
+        libc::pollfd { fd: server.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: signal_pipe.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+    ];
+    loop {
+        let rc = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if rc >= 0 {
+            return Ok(fds[1].revents == 0);
+        }
+        // The signal interrupts poll; the pipe is readable when we try again
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+
+fn serve_per_connection(
+    server: &TcpListener,
+    signal_pipe: &UnixStream,
+    chat: bool,
+    shutdown: &AtomicBool,
+    connections: &Arc<Connections>,
+) -> Result<(), Box<dyn Error>> {
+    // Number of threads currently running, and the highest number seen.
+    // Shared between threads, so protected by mutex.
+    let threads = Arc::new(Mutex::new((0, 0)));
+
+    // Chat room, used only in chat mode
+    let room = Arc::new(Room::new());
+
+    // Wait until new connection request comes in, or shutdown is requested.
+    while let Some((socket, address)) = accept_next(server, signal_pipe, shutdown)? {
+        println!("Accepting connection from {}", address);
+        let id = match connections.add(&socket, address) {
+            Ok(id) => id,
+            Err(e) => {
+                // The socket is closed when it goes out of scope
+                println!("Error with connection from {}: {}", address, e);
+                continue;
+            }
+        };
+
+        {
+            let mut threads = threads.lock().unwrap();
+            threads.0 += 1;
+            threads.1 = threads.1.max(threads.0);
+            println!("Active threads: {} (peak {})", threads.0, threads.1);
+        }
 
         // Spawn a new thread to handle all communication with the client.
-        thread::spawn(move || process_client(socket, address));
+        let threads = Arc::clone(&threads);
+        let connections = Arc::clone(connections);
+        let room = Arc::clone(&room);
+        thread::spawn(move || {
+            let bytes = if chat {
+                chat::process_client(socket, address, id, &room)
+            } else {
+                process_client(socket, address)
+            };
+            connections.finish(id, bytes);
+            let mut threads = threads.lock().unwrap();
+            threads.0 -= 1;
+            println!("Thread for {} echoed {} bytes, active threads: {}",
+                address, bytes, threads.0);
+        });
     }
+    Ok(())
 }
 
 
-fn process_client(mut socket: TcpStream, address: SocketAddr) {
+// Echo data until the client closes the connection. Returns the number of
+// bytes echoed.
+fn process_client(mut socket: TcpStream, address: SocketAddr) -> u64 {
+    let mut echoed: u64 = 0;
     loop {
         let mut buf: [u8; 160] = [0; 160];
         // Test whether read call returns Ok or Err result variant
@@ -49,23 +223,32 @@ fn process_client(mut socket: TcpStream, address: SocketAddr) {
                 // return value of 0 bytes means that socket is closed by
                 // the other end.
                 if n == 0 {
-                    println!("Client {} closed connection", address.to_string());
-                    break;  // exit the function and terminate the thread
+                    println!("Client {} closed connection", address);
+                    break;  // done with this client
                 }
-                println!("read {} bytes from client {}", n, address.to_string());
+                println!("read {} bytes from client {}", n, address);
 
                 // Echo the same bytes back to client. 
-                if let Err(e) = socket.write(&buf[..n]) {
-                    // If there is an error, terminate thread
-                    println!("Error writing to client: {}", e);
-                    break;
+                match socket.write(&buf[..n]) {
+                    Ok(n) => echoed += n as u64,
+                    Err(e) => {
+                        // If there is an error, stop serving this client
+                        println!("Error writing to client: {}", e);
+                        break;
+                    }
                 }
             },
             Err(e) => {
                 println!("Error reading from {}: {}",
-                    address.to_string(), e);
-                break;  // bye bye, thread!
+                    address, e);
+                break;  // bye bye, client!
             }
         }
     }
-}
\ No newline at end of file
+    echoed
+}
+
+mod args;
+mod chat;
+mod connections;
+mod pool;
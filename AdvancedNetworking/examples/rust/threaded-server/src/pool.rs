@@ -0,0 +1,159 @@
+/* Worker pool for the threaded server.
+ * A fixed number of worker threads is started at the beginning. The accepting
+ * thread places new connections to a bounded queue (a sync_channel), from
+ * which a free worker picks the next one. When all workers are busy and the
+ * queue is full, the rejection policy decides what happens to new
+ * connections. This way a connection flood cannot exhaust threads.
+ * On shutdown the queue is closed: workers finish the connections already
+ * in the queue, and then exit.
+ */
+
+use std::{
+    error::Error,
+    io::Write,
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    accept_next,
+    args::{Args, Reject},
+    connections::Connections,
+    process_client,
+};
+
+// Connection waiting in the queue, with its identifier in Connections
+type Job = (u64, TcpStream, SocketAddr);
+
+const BUSY_MESSAGE: &[u8] = b"Server busy, try again later\n";
+
+// How often a full queue is retried with the blocking policy.
+const QUEUE_RETRY: Duration = Duration::from_millis(10);
+
+// Statistics of one worker thread
+#[derive(Default)]
+struct WorkerStats {
+    connections: u64,
+    bytes: u64,
+}
+
+
+pub fn serve(
+    server: &TcpListener,
+    signal_pipe: &UnixStream,
+    args: &Args,
+    shutdown: &AtomicBool,
+    connections: &Arc<Connections>,
+) -> Result<(), Box<dyn Error>> {
+    // Bounded queue of accepted connections. The receiving end is shared by
+    // all workers, protected by a mutex, like in the thread-mutex example.
+    let (tx, rx) = mpsc::sync_channel::<Job>(args.queue());
+    let rx = Arc::new(Mutex::new(rx));
+
+    // Each worker has its own statistics entry in a shared vector.
+    let stats = Arc::new(Mutex::new(Vec::new()));
+    stats.lock().unwrap().resize_with(args.workers(), WorkerStats::default);
+
+    for id in 0..args.workers() {
+        let rx = Arc::clone(&rx);
+        let stats = Arc::clone(&stats);
+        let connections = Arc::clone(connections);
+        thread::spawn(move || worker(id, rx, stats, connections));
+    }
+    println!("Started {} workers, queue length {}, rejection policy {:?}",
+        args.workers(), args.queue(), args.reject());
+
+    let mut rejected: u64 = 0;
+
+    // Wait until new connection request comes in, or shutdown is requested.
+    while let Some((socket, address)) = accept_next(server, signal_pipe, shutdown)? {
+        println!("Accepting connection from {}", address);
+        let id = match connections.add(&socket, address) {
+            Ok(id) => id,
+            Err(e) => {
+                println!("Error with connection from {}: {}", address, e);
+                continue;
+            }
+        };
+
+        if args.reject() == Reject::Block {
+            // When the queue is full, we do not accept new connections until
+            // a worker becomes free. They wait in the kernel listen queue
+            // instead. A blocking send would not notice a signal, so retry
+            // after a short sleep, and give up when shutdown is requested.
+            let mut job = (id, socket, address);
+            loop {
+                match tx.try_send(job) {
+                    Ok(()) => break,
+                    Err(TrySendError::Full(returned)) => job = returned,
+                    Err(TrySendError::Disconnected(_)) => return Err("Workers have stopped".into()),
+                }
+                if shutdown.load(Ordering::Relaxed) {
+                    println!("Shutting down, closing {}", job.2);
+                    connections.finish(job.0, 0);
+                    break;
+                }
+                thread::sleep(QUEUE_RETRY);
+            }
+            continue;
+        }
+
+        // try_send returns the connection back if the queue is full.
+        match tx.try_send((id, socket, address)) {
+            Ok(()) => {}
+            Err(TrySendError::Full((id, mut socket, address))) => {
+                rejected += 1;
+                println!("All workers busy, rejecting {} ({} rejected in total)",
+                    address, rejected);
+                if args.reject() == Reject::Busy {
+                    let _ = socket.write_all(BUSY_MESSAGE);
+                }
+                connections.finish(id, 0);
+                // socket is closed when it goes out of scope
+            }
+            Err(TrySendError::Disconnected(_)) => return Err("Workers have stopped".into()),
+        }
+    }
+
+    // Dropping the sending end closes the queue. Workers exit after the
+    // queue is empty.
+    drop(tx);
+    Ok(())
+}
+
+
+fn worker(
+    id: usize,
+    rx: Arc<Mutex<Receiver<Job>>>,
+    stats: Arc<Mutex<Vec<WorkerStats>>>,
+    connections: Arc<Connections>,
+) {
+    loop {
+        // Lock the queue only for taking the next connection. The lock is
+        // released at the end of the statement, so other workers can wait
+        // for connections while this one is serving.
+        let next = rx.lock().unwrap().recv();
+        let (connection_id, socket, address) = match next {
+            Ok(job) => job,
+            Err(_) => break,  // queue has been closed
+        };
+        println!("Worker {} serving {}", id, address);
+
+        let bytes = process_client(socket, address);
+        connections.finish(connection_id, bytes);
+
+        let mut stats = stats.lock().unwrap();
+        let own = &mut stats[id];
+        own.connections += 1;
+        own.bytes += bytes;
+        println!("Worker {}: {} connections, {} bytes echoed in total",
+            id, own.connections, own.bytes);
+    }
+}
@@ -0,0 +1,216 @@
+/* Timers for an event loop, kept in a min-heap ordered by deadline.
+ *
+ * MIO's poll can wait for a timeout in addition to I/O events. Before each
+ * poll the loop asks for the time until the next deadline, and after poll it
+ * handles the timers that have expired. The value stored with a timer tells
+ * the loop what to do when the timer expires.
+ *
+ * Cancelling a timer only marks it cancelled; the entry stays in the heap
+ * until its deadline comes, and is then skipped. Often it is simpler still
+ * not to cancel at all, but to check when the timer expires whether it is
+ * still relevant. For example, an idle timer can check the time of last
+ * activity, and set a new timer if the client was active.
+ *
+ * The crate only depends on the standard library. It is used by the
+ * iterative-server example, and fits other event loops such as the MIO
+ * server of task-srv or the task-tun tunnel.
+ */
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+    time::{Duration, Instant},
+};
+
+/// Identifies a timer for cancelling it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+pub struct Timers<T> {
+    heap: BinaryHeap<Reverse<Entry<T>>>,
+    pending: HashSet<u64>,  // sequence numbers of timers not cancelled
+    seq: u64,
+}
+
+/// Timer entry in the heap. Sequence number keeps timers with equal deadline
+/// in insertion order, and means that `T` itself does not need to be ordered.
+struct Entry<T> {
+    deadline: Instant,
+    seq: u64,
+    value: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.deadline, self.seq) == (other.deadline, other.seq)
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+impl<T> Default for Timers<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Timers<T> {
+    pub fn new() -> Timers<T> {
+        Timers {
+            heap: BinaryHeap::new(),
+            pending: HashSet::new(),
+            seq: 0,
+        }
+    }
+
+    /// Add timer that expires at `deadline`.
+    pub fn add(&mut self, deadline: Instant, value: T) -> TimerId {
+        self.seq += 1;
+        self.heap.push(Reverse(Entry {
+            deadline,
+            seq: self.seq,
+            value,
+        }));
+        self.pending.insert(self.seq);
+        TimerId(self.seq)
+    }
+
+    /// Add timer that expires `delay` from now.
+    pub fn add_after(&mut self, delay: Duration, value: T) -> TimerId {
+        self.add(Instant::now() + delay, value)
+    }
+
+    /// Cancel a timer. Returns false if it has already expired or been
+    /// cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        self.pending.remove(&id.0)
+    }
+
+    /// Number of timers that have not expired or been cancelled.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Time until the next timer expires, to be used as poll timeout. None if
+    /// there are no timers, i.e., poll can wait forever.
+    pub fn next_timeout(&mut self, now: Instant) -> Option<Duration> {
+        self.skip_cancelled();
+        self.heap
+            .peek()
+            .map(|Reverse(e)| e.deadline.saturating_duration_since(now))
+    }
+
+    /// Remove and return the value of the next timer expired by `now`.
+    /// Call repeatedly until None is returned.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<T> {
+        self.skip_cancelled();
+        match self.heap.peek() {
+            Some(Reverse(e)) if e.deadline <= now => {
+                let Reverse(e) = self.heap.pop()?;
+                self.pending.remove(&e.seq);
+                Some(e.value)
+            }
+            _ => None,
+        }
+    }
+
+    // Drop cancelled timers from the top of the heap.
+    fn skip_cancelled(&mut self) {
+        while let Some(Reverse(e)) = self.heap.peek() {
+            if self.pending.contains(&e.seq) {
+                break;
+            }
+            self.heap.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expired(timers: &mut Timers<&'static str>, now: Instant) -> Vec<&'static str> {
+        std::iter::from_fn(|| timers.pop_expired(now)).collect()
+    }
+
+    #[test]
+    fn expire_in_deadline_order() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        timers.add(start + Duration::from_millis(30), "c");
+        timers.add(start + Duration::from_millis(10), "a");
+        timers.add(start + Duration::from_millis(20), "b");
+
+        assert_eq!(timers.next_timeout(start), Some(Duration::from_millis(10)));
+        assert_eq!(expired(&mut timers, start), Vec::<&str>::new());
+        assert_eq!(expired(&mut timers, start + Duration::from_millis(20)), vec!["a", "b"]);
+        assert_eq!(timers.next_timeout(start + Duration::from_millis(25)), Some(Duration::from_millis(5)));
+        assert_eq!(expired(&mut timers, start + Duration::from_millis(40)), vec!["c"]);
+        assert_eq!(timers.next_timeout(start), None);
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn overdue_timer_has_zero_timeout() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        timers.add(start, "a");
+        assert_eq!(timers.next_timeout(start + Duration::from_secs(1)), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn equal_deadlines_expire_in_insertion_order() {
+        let deadline = Instant::now();
+        let mut timers = Timers::new();
+        for value in ["a", "b", "c", "d"] {
+            timers.add(deadline, value);
+        }
+        assert_eq!(expired(&mut timers, deadline), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn cancelled_timer_does_not_expire() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        let first = timers.add(start + Duration::from_millis(10), "a");
+        timers.add(start + Duration::from_millis(20), "b");
+        let last = timers.add(start + Duration::from_millis(30), "c");
+
+        assert!(timers.cancel(first));
+        assert!(!timers.cancel(first));
+        assert_eq!(timers.len(), 2);
+        // The next deadline is that of the first timer not cancelled
+        assert_eq!(timers.next_timeout(start), Some(Duration::from_millis(20)));
+        assert_eq!(expired(&mut timers, start + Duration::from_millis(30)), vec!["b", "c"]);
+        // Expired timers cannot be cancelled
+        assert!(!timers.cancel(last));
+    }
+
+    #[test]
+    fn cancel_among_equal_deadlines() {
+        let deadline = Instant::now();
+        let mut timers = Timers::new();
+        timers.add(deadline, "a");
+        let b = timers.add(deadline, "b");
+        timers.add(deadline, "c");
+        timers.cancel(b);
+        assert_eq!(expired(&mut timers, deadline), vec!["a", "c"]);
+        assert!(timers.is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use clap::Parser;
+
+use udp_transport::{batch::Batching, congestion::Algorithm};
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Keyword given in the MyCourses assignment.
+    keyword: String,
+
+    /// Address of adnet-agent. Data is sent to UDP port 20000 at the same host.
+    #[arg(short, long, default_value = "10.0.0.3:12345")]
+    server_addr: String,
+
+    /// How datagrams are passed to the kernel.
+    #[arg(short, long, value_enum, default_value_t = Batching::Mmsg)]
+    batching: Batching,
+
+    /// How congestion control reacts to ECN marks.
+    #[arg(long, value_enum, default_value_t = Algorithm::Reno)]
+    cc: Algorithm,
+
+    /// Do not mark datagrams ECN-capable.
+    #[arg(long)]
+    no_ecn: bool,
+
+    /// Send an XOR repair datagram after every FEC datagrams, so that the
+    /// receiver can rebuild one lost datagram per block. Only the agent
+    /// stand-in understands repair datagrams.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(2..))]
+    fec: Option<u8>,
+
+    /// Measure RTT from kernel send and receive timestamps, and compare with
+    /// RTT measured in user space.
+    #[arg(long)]
+    timestamps: bool,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn keyword(&self) -> &String {
+        &self.keyword
+    }
+
+    pub fn server_addr(&self) -> &String {
+        &self.server_addr
+    }
+
+    pub fn batching(&self) -> Batching {
+        self.batching
+    }
+
+    pub fn cc(&self) -> Algorithm {
+        self.cc
+    }
+
+    pub fn ecn(&self) -> bool {
+        !self.no_ecn
+    }
+
+    pub fn fec(&self) -> Option<u8> {
+        self.fec
+    }
+
+    pub fn timestamps(&self) -> bool {
+        self.timestamps
+    }
+}
@@ -0,0 +1,511 @@
+/*  Batched datagram I/O.
+
+    Sending or receiving one datagram per system call costs a transition
+    between user space and kernel for every datagram, which dominates at high
+    packet rates. Linux has a few ways to move more data per call:
+
+    - sendmmsg / recvmmsg: one system call carries several datagrams, each
+      with its own header (struct mmsghdr). The kernel still processes the
+      datagrams one by one.
+    - UDP GSO (generic segmentation offload): the application gives one large
+      buffer and a segment size in UDP_SEGMENT control message. The buffer
+      travels through the stack as one unit, and is split into datagrams at
+      the last moment (by the NIC, if it supports it).
+    - UDP GRO (generic receive offload): the receiving side merges
+      consecutive datagrams of a flow into one buffer, and tells the segment
+      size in UDP_GRO control message, so that the application can split them.
+
+    When the kernel does not support a mode, we fall back to the next simpler
+    one: GSO to sendmmsg, and sendmmsg to one send_to per datagram.
+
+    Note that a GSO buffer may pass through traffic control (e.g. netem in
+    Mininet) as one packet, so queue limits and loss are not applied per
+    datagram.
+
+    With kernel timestamps enabled (timestamp.rs), the socket also keeps
+    track of which datagrams each send call carried, so that a transmit
+    timestamp can be given for every datagram. The datagrams of one GSO
+    buffer share the timestamp of the buffer.
+*/
+
+use std::{
+    collections::VecDeque,
+    io,
+    mem,
+    net::{SocketAddr, UdpSocket},
+    os::fd::AsRawFd,
+    ptr,
+    time::{Duration, SystemTime},
+};
+
+use clap::ValueEnum;
+use socket2::SockAddr;
+
+use crate::{ecn, timestamp};
+
+/// Maximum number of datagrams in one system call.
+pub const MAX_BATCH: usize = 64;
+
+/// Maximum number of segments in one GSO buffer (UDP_MAX_SEGMENTS in kernel).
+const MAX_GSO_SEGMENTS: usize = 64;
+
+/// Maximum size of one GSO buffer: it must fit in a single UDP datagram.
+const MAX_GSO_BYTES: usize = 65507;
+
+/// Receive buffer size for one datagram, when GRO is not used.
+const RECV_BUF: usize = 2048;
+
+/// Receive buffer size for one coalesced GRO buffer.
+const GRO_BUF: usize = 65536;
+
+/// Number of GRO buffers received in one recvmmsg call.
+const GRO_BATCH: usize = 8;
+
+/// Space for control messages per received datagram: GRO segment size, TOS
+/// and receive timestamp.
+const CONTROL_LEN: usize = 128;
+
+/// Send calls remembered while waiting for their transmit timestamps. If
+/// the kernel does not give timestamps, the oldest are forgotten.
+const MAX_PENDING_TIMESTAMPS: usize = 4096;
+
+/// How datagrams are moved between application and kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Batching {
+    /// One send_to or recv_from call per datagram
+    Single,
+    /// Several datagrams per sendmmsg or recvmmsg call
+    Mmsg,
+    /// UDP GSO for sending and GRO for receiving
+    Gso,
+}
+
+/// Where a received datagram came from, and how it was marked.
+pub struct RecvInfo {
+    pub src: SocketAddr,
+    /// ECN bits of the datagram, if enabled with ecn::enable_receive.
+    pub ecn: u8,
+    /// When the kernel received the datagram, if enabled with
+    /// BatchSocket::enable_timestamps.
+    pub timestamp: Option<SystemTime>,
+}
+
+/// When the kernel sent a datagram.
+pub struct TxTimestamp {
+    /// Index of the datagram among all datagrams sent from the socket,
+    /// counted from 0 (see BatchSocket::datagrams_sent).
+    pub index: u64,
+    pub time: SystemTime,
+}
+
+/// UDP socket that sends and receives datagrams in batches.
+pub struct BatchSocket {
+    socket: UdpSocket,
+    mode: Batching,
+    gso_buf: Vec<u8>,
+    sent: u64,              // datagrams sent so far
+
+    // Kernel timestamps: the send calls waiting for their transmit timestamp,
+    // as (number of the call, index of its first datagram, datagram count)
+    timestamps: bool,
+    next_key: u32,
+    pending_tx: VecDeque<(u32, u64, usize)>,
+    tx_timestamps: Vec<TxTimestamp>,
+
+    // Receive buffers and the structures pointing to them for recvmmsg
+    bufs: Vec<Vec<u8>>,
+    addrs: Vec<libc::sockaddr_storage>,
+    controls: Vec<[u64; CONTROL_LEN / 8]>,   // u64 for cmsghdr alignment
+    iovs: Vec<libc::iovec>,
+    msgs: Vec<libc::mmsghdr>,
+}
+
+// The raw pointers in iovs and msgs point only into buffers owned by the
+// same BatchSocket, and are set up again before every use.
+unsafe impl Send for BatchSocket {}
+
+impl BatchSocket {
+    /// Prepare socket for the given batching mode. If the kernel does not
+    /// support the mode, a simpler one is used.
+    pub fn new(socket: UdpSocket, mode: Batching) -> BatchSocket {
+        let mut mode = mode;
+        if mode == Batching::Gso {
+            if let Err(e) = enable_gso(&socket) {
+                println!("UDP GSO/GRO not available ({}), using sendmmsg/recvmmsg", e);
+                mode = Batching::Mmsg;
+            }
+        }
+        let (count, size) = match mode {
+            Batching::Single => (1, RECV_BUF),
+            Batching::Mmsg => (MAX_BATCH, RECV_BUF),
+            Batching::Gso => (GRO_BATCH, GRO_BUF),
+        };
+        BatchSocket {
+            socket,
+            mode,
+            gso_buf: Vec::with_capacity(MAX_GSO_BYTES),
+            sent: 0,
+            timestamps: false,
+            next_key: 0,
+            pending_tx: VecDeque::new(),
+            tx_timestamps: Vec::new(),
+            bufs: vec![vec![0; size]; count],
+            addrs: vec![unsafe { mem::zeroed() }; count],
+            controls: vec![[0; CONTROL_LEN / 8]; count],
+            iovs: vec![libc::iovec { iov_base: ptr::null_mut(), iov_len: 0 }; count],
+            msgs: vec![unsafe { mem::zeroed() }; count],
+        }
+    }
+
+    /// The mode in effect, after possible fallback.
+    pub fn mode(&self) -> Batching {
+        self.mode
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Take send and receive times from the kernel (timestamp.rs). Must be
+    /// called before anything is sent.
+    pub fn enable_timestamps(&mut self) -> io::Result<()> {
+        timestamp::enable(&self.socket)?;
+        self.timestamps = true;
+        Ok(())
+    }
+
+    /// Number of datagrams sent so far, which is also the index of the next
+    /// datagram in transmit timestamps.
+    pub fn datagrams_sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Transmit timestamps that have arrived during recv_batch calls.
+    pub fn tx_timestamps(&mut self) -> std::vec::Drain<'_, TxTimestamp> {
+        self.tx_timestamps.drain(..)
+    }
+
+    /// Send all datagrams to dest.
+    pub fn send_batch(&mut self, datagrams: &[&[u8]], dest: SocketAddr) -> io::Result<()> {
+        let address = SockAddr::from(dest);
+        let mut sent = 0;
+        while sent < datagrams.len() {
+            let mode = self.mode;
+            let result = match mode {
+                Batching::Single => self.socket.send_to(datagrams[sent], dest).map(|_| 1),
+                Batching::Mmsg => self.send_mmsg(&datagrams[sent..], &address),
+                Batching::Gso => self.send_gso(&datagrams[sent..], &address),
+            };
+            match result {
+                Ok(n) => {
+                    if self.timestamps {
+                        // One send call per GSO buffer, otherwise per datagram
+                        match mode {
+                            Batching::Gso => self.add_pending_tx(self.sent, n),
+                            _ => for i in 0..n {
+                                self.add_pending_tx(self.sent + i as u64, 1);
+                            }
+                        }
+                    }
+                    self.sent += n as u64;
+                    sent += n;
+                }
+                // Kernel without sendmmsg
+                Err(e) if self.mode == Batching::Mmsg && e.raw_os_error() == Some(libc::ENOSYS) => {
+                    println!("sendmmsg not available, sending one datagram at a time");
+                    self.mode = Batching::Single;
+                }
+                // Segmentation offload not possible on the outgoing interface
+                Err(e) if self.mode == Batching::Gso && e.raw_os_error() == Some(libc::EIO) => {
+                    println!("UDP GSO failed on this route, using sendmmsg");
+                    self.mode = Batching::Mmsg;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Remember the datagrams of the next send call, until its transmit
+    // timestamp arrives.
+    fn add_pending_tx(&mut self, first: u64, count: usize) {
+        if self.pending_tx.len() == MAX_PENDING_TIMESTAMPS {
+            self.pending_tx.pop_front();
+        }
+        self.pending_tx.push_back((self.next_key, first, count));
+        self.next_key = self.next_key.wrapping_add(1);
+    }
+
+    // Send as many datagrams as fit in one sendmmsg call. Returns the number
+    // of datagrams sent.
+    fn send_mmsg(&mut self, datagrams: &[&[u8]], dest: &SockAddr) -> io::Result<usize> {
+        let count = datagrams.len().min(MAX_BATCH);
+        let mut iovs: [libc::iovec; MAX_BATCH] = unsafe { mem::zeroed() };
+        let mut msgs: [libc::mmsghdr; MAX_BATCH] = unsafe { mem::zeroed() };
+        for i in 0..count {
+            iovs[i] = libc::iovec {
+                iov_base: datagrams[i].as_ptr() as *mut libc::c_void,
+                iov_len: datagrams[i].len(),
+            };
+            msgs[i].msg_hdr.msg_name = dest.as_ptr() as *mut libc::c_void;
+            msgs[i].msg_hdr.msg_namelen = dest.len();
+            msgs[i].msg_hdr.msg_iov = &mut iovs[i];
+            msgs[i].msg_hdr.msg_iovlen = 1;
+        }
+        let rc = unsafe {
+            libc::sendmmsg(self.socket.as_raw_fd(), msgs.as_mut_ptr(), count as libc::c_uint, 0)
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(rc as usize)
+    }
+
+    // Copy datagrams of equal size into one buffer and send it with
+    // UDP_SEGMENT. Only the last segment may be shorter. Returns the number
+    // of datagrams sent.
+    //
+    // A zero-length datagram cannot be a segment: it would add nothing to
+    // the buffer, but would still be counted as sent.
+    fn send_gso(&mut self, datagrams: &[&[u8]], dest: &SockAddr) -> io::Result<usize> {
+        let segment = datagrams[0].len();
+        if segment == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "zero-length datagram cannot be sent with UDP GSO"));
+        }
+        self.gso_buf.clear();
+        let mut count = 0;
+        for datagram in datagrams {
+            if count == MAX_GSO_SEGMENTS
+                || datagram.is_empty()
+                || datagram.len() > segment
+                || self.gso_buf.len() + datagram.len() > MAX_GSO_BYTES {
+                break;
+            }
+            self.gso_buf.extend_from_slice(datagram);
+            count += 1;
+            if datagram.len() < segment {
+                break;
+            }
+        }
+        // The first datagram alone is too large for one UDP datagram
+        if count == 0 {
+            return Err(io::Error::from_raw_os_error(libc::EMSGSIZE));
+        }
+
+        let mut iov = libc::iovec {
+            iov_base: self.gso_buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: self.gso_buf.len(),
+        };
+        let mut control = [0u64; CONTROL_LEN / 8];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = dest.as_ptr() as *mut libc::c_void;
+        msg.msg_namelen = dest.len();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if count > 1 {
+            // Control message telling the segment size
+            let space = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as u32) } as usize;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = space as _;
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_UDP;
+                (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment as u16);
+            }
+        }
+        let rc = unsafe { libc::sendmsg(self.socket.as_raw_fd(), &msg, 0) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(count)
+    }
+
+    /// Wait at most `timeout` for datagrams, and call `handle` for each
+    /// received datagram. Returns the number of datagrams received, 0 if
+    /// none arrived in time. With timestamps enabled, also returns early
+    /// when transmit timestamps arrive (see tx_timestamps).
+    ///
+    /// The wait is done with ppoll rather than the socket receive timeout,
+    /// because the latter is counted in kernel ticks (several milliseconds),
+    /// which is too coarse for pacing.
+    pub fn recv_batch(&mut self, timeout: Duration, mut handle: impl FnMut(&[u8], &RecvInfo))
+        -> io::Result<usize> {
+        let events = self.wait_readable(timeout)?;
+        if events == 0 {
+            return Ok(0);
+        }
+        // Transmit timestamps make the socket report an error condition
+        if self.timestamps && events & libc::POLLERR != 0 {
+            self.read_tx_timestamps()?;
+            if events & libc::POLLIN == 0 {
+                return Ok(0);
+            }
+        }
+
+        // The kernel overwrites the lengths, so set up the headers every time
+        for i in 0..self.bufs.len() {
+            self.iovs[i] = libc::iovec {
+                iov_base: self.bufs[i].as_mut_ptr() as *mut libc::c_void,
+                iov_len: self.bufs[i].len(),
+            };
+            let hdr = &mut self.msgs[i].msg_hdr;
+            hdr.msg_name = &mut self.addrs[i] as *mut _ as *mut libc::c_void;
+            hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            hdr.msg_iov = &mut self.iovs[i];
+            hdr.msg_iovlen = 1;
+            hdr.msg_control = self.controls[i].as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = CONTROL_LEN as _;
+        }
+
+        // Take the datagrams that are already waiting, without blocking.
+        // In single mode recvmsg rather than recv_from, to get the control
+        // messages.
+        let fd = self.socket.as_raw_fd();
+        let rc = if self.mode == Batching::Single {
+            // recvmsg returns the length, which may be 0 for an empty
+            // datagram, but it is always one message
+            let rc = unsafe { libc::recvmsg(fd, &mut self.msgs[0].msg_hdr, libc::MSG_DONTWAIT) };
+            if rc >= 0 {
+                self.msgs[0].msg_len = rc as libc::c_uint;
+                1
+            } else {
+                -1
+            }
+        } else {
+            unsafe {
+                libc::recvmmsg(fd, self.msgs.as_mut_ptr(), self.msgs.len() as libc::c_uint,
+                    libc::MSG_DONTWAIT, ptr::null_mut())
+            }
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut count = 0;
+        for i in 0..rc as usize {
+            let hdr = &self.msgs[i].msg_hdr;
+            let src = match unsafe { SockAddr::new(self.addrs[i], hdr.msg_namelen) }.as_socket() {
+                Some(src) => src,
+                None => continue,
+            };
+            let control = Control::parse(hdr);
+            let info = RecvInfo { src, ecn: control.tos & ecn::MASK, timestamp: control.timestamp };
+            let data = &self.bufs[i][..self.msgs[i].msg_len as usize];
+            match control.segment {
+                Some(segment) => {
+                    for datagram in data.chunks(segment) {
+                        handle(datagram, &info);
+                        count += 1;
+                    }
+                }
+                None => {
+                    handle(data, &info);
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    // Returns the poll events of the socket, 0 on timeout.
+    fn wait_readable(&self, timeout: Duration) -> io::Result<libc::c_short> {
+        let mut fds = libc::pollfd { fd: self.socket.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ts = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        let rc = unsafe { libc::ppoll(&mut fds, 1, &ts, ptr::null()) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(if rc > 0 { fds.revents } else { 0 })
+    }
+
+    // Give each datagram of a send call the time from its transmit timestamp.
+    fn read_tx_timestamps(&mut self) -> io::Result<()> {
+        let pending = &mut self.pending_tx;
+        let timestamps = &mut self.tx_timestamps;
+        timestamp::read_transmit(&self.socket, |key, time| {
+            // Calls before this one whose timestamps were lost are skipped
+            while let Some(&(pending_key, first, count)) = pending.front() {
+                let diff = key.wrapping_sub(pending_key) as i32;
+                if diff < 0 {
+                    break;  // already forgotten
+                }
+                pending.pop_front();
+                if diff == 0 {
+                    timestamps.extend((first..first + count as u64)
+                        .map(|index| TxTimestamp { index, time }));
+                    break;
+                }
+            }
+        })?;
+        Ok(())
+    }
+}
+
+
+// Check that the kernel knows UDP_SEGMENT, and ask for GRO on receive.
+fn enable_gso(socket: &UdpSocket) -> io::Result<()> {
+    let fd = socket.as_raw_fd();
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(fd, libc::SOL_UDP, libc::UDP_SEGMENT,
+            &mut value as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let on: libc::c_int = 1;
+    let rc = unsafe {
+        libc::setsockopt(fd, libc::SOL_UDP, libc::UDP_GRO,
+            &on as *const _ as *const libc::c_void, mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+
+// Information from the control messages of a received buffer.
+#[derive(Default)]
+struct Control {
+    segment: Option<usize>,   // UDP_GRO: several coalesced datagrams of this size
+    tos: u8,                  // IP_TOS or IPV6_TCLASS, if enabled (ecn.rs)
+    timestamp: Option<SystemTime>,  // SCM_TIMESTAMPNS, if enabled (timestamp.rs)
+}
+
+impl Control {
+    fn parse(hdr: &libc::msghdr) -> Control {
+        let mut control = Control::default();
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::SOL_UDP, libc::UDP_GRO) => {
+                        let segment = ptr::read_unaligned(data as *const libc::c_int);
+                        control.segment = Some(segment as usize);
+                    }
+                    // IPv4 gives the TOS as one byte, IPv6 the traffic class as int
+                    (libc::IPPROTO_IP, libc::IP_TOS) => control.tos = *data,
+                    (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                        control.tos = ptr::read_unaligned(data as *const libc::c_int) as u8;
+                    }
+                    (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                        let ts = ptr::read_unaligned(data as *const libc::timespec);
+                        control.timestamp = timestamp::from_timespec(&ts);
+                    }
+                    _ => (),
+                }
+                cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+            }
+        }
+        control
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use clap::Parser;
+
+use udp_transport::batch::Batching;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Address for control connections. Data is received at the same IP
+    /// address, in the UDP port given with --udp-port.
+    #[arg(short, long, default_value = "127.0.0.1:12345")]
+    listen_addr: String,
+
+    /// UDP port for data.
+    #[arg(short, long, default_value_t = 20000)]
+    udp_port: u16,
+
+    /// Number of bytes the client is asked to send.
+    #[arg(short = 'n', long, default_value_t = 100000)]
+    bytes: usize,
+
+    /// Character the client is asked to fill payload with.
+    #[arg(short, long, default_value_t = 'A')]
+    character: char,
+
+    /// One-way delay of the emulated link, in milliseconds.
+    #[arg(long, default_value_t = 0)]
+    delay: u64,
+
+    /// Bandwidth of the emulated link from client to agent, in Mbit/s.
+    /// 0 means unlimited.
+    #[arg(long, default_value_t = 0.0)]
+    bw: f64,
+
+    /// Packet loss rate of the emulated link, in percent. Applied in both
+    /// directions.
+    #[arg(long, default_value_t = 0.0)]
+    loss: f64,
+
+    /// Number of datagrams that fit in the queue before the bandwidth limit.
+    #[arg(long, default_value_t = 20)]
+    queue: usize,
+
+    /// Mark ECN-capable datagrams with CE when at least this many datagrams
+    /// are in the queue. No marking if not given. Needs --bw.
+    #[arg(long)]
+    ecn_threshold: Option<usize>,
+
+    /// Seed for the random loss, to repeat the same loss pattern.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How datagrams are received from the kernel.
+    #[arg(short, long, value_enum, default_value_t = Batching::Mmsg)]
+    batching: Batching,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn listen_addr(&self) -> &String {
+        &self.listen_addr
+    }
+
+    pub fn udp_port(&self) -> u16 {
+        self.udp_port
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn character(&self) -> u8 {
+        self.character as u8
+    }
+
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay)
+    }
+
+    /// Bandwidth in bits per second, 0 if unlimited.
+    pub fn bandwidth(&self) -> f64 {
+        self.bw * 1e6
+    }
+
+    /// Loss probability between 0 and 1.
+    pub fn loss(&self) -> f64 {
+        self.loss / 100.0
+    }
+
+    pub fn queue(&self) -> usize {
+        self.queue
+    }
+
+    pub fn ecn_threshold(&self) -> Option<usize> {
+        self.ecn_threshold
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn batching(&self) -> Batching {
+        self.batching
+    }
+}
@@ -0,0 +1,122 @@
+/*  Emulated bottleneck link between the client and the agent, similar to
+    what simple_topo.py sets up with tc in Mininet:
+    - datagrams wait in a queue of limited size, and are transmitted at the
+      link bandwidth. When the queue is full, arriving datagrams are dropped.
+    - every datagram is delayed by the one-way propagation delay.
+    - datagrams are dropped at random with the given probability.
+    Acknowledgments on the way back are only delayed and dropped at random.
+
+    Optionally the queue marks ECN-capable datagrams with CE when it holds
+    more than a threshold of datagrams, like a datacenter switch configured
+    for DCTCP. Other datagrams are only dropped when the queue is full.
+*/
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use udp_transport::ecn;
+
+// IPv4 and UDP headers, counted in the transmission time.
+const HEADERS: usize = 28;
+
+pub struct Link {
+    delay: Duration,
+    bandwidth: f64,              // bits per second, 0 if unlimited
+    loss: f64,                   // probability
+    queue: usize,                // datagrams
+    mark_threshold: Option<usize>,  // datagrams in queue before CE marking
+    departures: VecDeque<Instant>,  // when queued datagrams finish transmission
+    rng: Rng,
+
+    pub dropped_loss: u64,
+    pub dropped_queue: u64,
+    pub marked: u64,
+}
+
+impl Link {
+    pub fn new(delay: Duration, bandwidth: f64, loss: f64, queue: usize,
+        mark_threshold: Option<usize>, seed: Option<u64>) -> Link {
+        Link {
+            delay,
+            bandwidth,
+            loss,
+            queue,
+            mark_threshold,
+            departures: VecDeque::new(),
+            rng: Rng::new(seed),
+            dropped_loss: 0,
+            dropped_queue: 0,
+            marked: 0,
+        }
+    }
+
+    /// Datagram of `len` bytes with ECN bits `ecn` from the client enters
+    /// the link at `now`. Returns the time it reaches the agent and its ECN
+    /// bits after the link, or None if it was dropped.
+    pub fn forward(&mut self, len: usize, ecn: u8, now: Instant) -> Option<(Instant, u8)> {
+        if self.lost() {
+            self.dropped_loss += 1;
+            return None;
+        }
+        if self.bandwidth == 0.0 {
+            return Some((now + self.delay, ecn));
+        }
+
+        // Datagrams that have been transmitted have left the queue
+        while self.departures.front().is_some_and(|&t| t <= now) {
+            self.departures.pop_front();
+        }
+        if self.departures.len() >= self.queue {
+            self.dropped_queue += 1;
+            return None;
+        }
+        let mut ecn = ecn;
+        if self.mark_threshold.is_some_and(|k| self.departures.len() >= k)
+            && ecn::is_capable(ecn) {
+            ecn = ecn::CE;
+            self.marked += 1;
+        }
+        let start = self.departures.back().map_or(now, |&t| t.max(now));
+        let departure = start
+            + Duration::from_secs_f64(((len + HEADERS) * 8) as f64 / self.bandwidth);
+        self.departures.push_back(departure);
+        Some((departure + self.delay, ecn))
+    }
+
+    /// Acknowledgment from the agent enters the link back to the client at
+    /// `now`. Returns the time it reaches the client, or None if dropped.
+    pub fn reverse(&mut self, now: Instant) -> Option<Instant> {
+        if self.lost() {
+            self.dropped_loss += 1;
+            return None;
+        }
+        Some(now + self.delay)
+    }
+
+    fn lost(&mut self) -> bool {
+        self.loss > 0.0 && self.rng.next_f64() < self.loss
+    }
+}
+
+
+// Small xorshift pseudo-random number generator. Good enough for dropping
+// packets, and avoids a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: Option<u64>) -> Rng {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64
+        });
+        Rng(seed | 1)  // state must not be zero
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
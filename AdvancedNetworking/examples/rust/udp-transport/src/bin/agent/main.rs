@@ -0,0 +1,203 @@
+/*  Stand-in for adnet-agent, to test UDP transfers without Mininet.
+
+    Answers the "TASK-UDP <keyword>" control message over TCP with the number
+    of bytes and the character to send, and then receives the datagrams over
+    UDP and acknowledges them like adnet-agent does. Between the client and
+    the receiver there is an emulated bottleneck link (link.rs), so that
+    retransmissions and congestion control can be tried out on loopback.
+
+    The link can also mark ECN-capable datagrams when its queue grows
+    (--ecn-threshold), and the acknowledgments then echo the number of
+    marked datagrams.
+
+    Only one transfer is active at a time: a new control connection starts a
+    new transfer.
+
+    Usage: cargo run --bin agent -- [-l <address:port>] [--delay <ms>] [--bw <Mbit/s>]
+                                    [--loss <%>] [--queue <n>] [--ecn-threshold <n>] [--seed <n>]
+*/
+
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    error::Error,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, UdpSocket},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use udp_transport::{batch::BatchSocket, ecn, protocol::datagram_count};
+
+use crate::{args::Args, link::Link, receiver::Transfer};
+
+// How long to wait for datagrams when nothing else is scheduled.
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+// Something that happens at a later time on the emulated link.
+enum Kind {
+    Arrive(Vec<u8>, u8),        // datagram and its ECN bits reach the receiver
+    Ack(Vec<u8>),               // acknowledgment reaches the client
+}
+
+struct Event {
+    at: Instant,
+    order: u64,                 // keeps events at the same time in order
+    peer: SocketAddr,
+    kind: Kind,
+}
+
+// BinaryHeap is a max-heap, so the ordering is reversed to get the earliest
+// event first.
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.order).cmp(&(self.at, self.order))
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::new();
+    if args.ecn_threshold().is_some() && args.bandwidth() == 0.0 {
+        // Without a bandwidth limit no queue builds up, so nothing is marked
+        return Err("--ecn-threshold needs a bandwidth limit (--bw)".into());
+    }
+
+    let listener = TcpListener::bind(args.listen_addr())?;
+    let udp_addr = SocketAddr::new(listener.local_addr()?.ip(), args.udp_port());
+    let socket = UdpSocket::bind(udp_addr)?;
+    ecn::enable_receive(&socket)?;
+    let mut socket = BatchSocket::new(socket, args.batching());
+    println!("Listening for control connections at {}, data at {} ({:?} receive)",
+        listener.local_addr()?, udp_addr, socket.mode());
+
+    let (tx, rx) = mpsc::channel();
+    let (bytes, character) = (args.bytes(), args.character());
+    let total = datagram_count(bytes as u64).ok_or("too many bytes for 32-bit sequence numbers")?;
+    thread::spawn(move || control(listener, bytes, total, character, tx));
+
+    let mut link = Link::new(args.delay(), args.bandwidth(), args.loss(), args.queue(),
+        args.ecn_threshold(), args.seed());
+    receive(&mut socket, &mut link, rx)
+}
+
+
+// Serve control connections one at a time. Each connection starts a new
+// transfer, handed over to the receiving loop.
+fn control(listener: TcpListener, bytes: usize, total: u32, character: u8, tx: Sender<Transfer>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Accept failed: {}", e);
+                continue;
+            }
+        };
+        let mut buf = [0; 100];
+        let request = match stream.read(&mut buf) {
+            Ok(n) => String::from_utf8_lossy(&buf[..n]).to_string(),
+            Err(e) => {
+                println!("Reading control message failed: {}", e);
+                continue;
+            }
+        };
+        if !request.starts_with("TASK-UDP ") {
+            println!("Unknown control message: {}", request.trim());
+            continue;
+        }
+        println!("{} from {}: sending {} bytes of {}",
+            request.trim(), stream.peer_addr().map_or("?".to_string(), |a| a.to_string()),
+            bytes, character as char);
+        // The receiver must know about the transfer before the client gets
+        // the response and starts sending.
+        if tx.send(Transfer::new(bytes, total, character)).is_err() {
+            return;
+        }
+        if let Err(e) = stream.write_all(format!("{} {}", bytes, character as char).as_bytes()) {
+            println!("Writing response failed: {}", e);
+        }
+    }
+}
+
+
+// Receive datagrams, pass them through the emulated link, and send the
+// acknowledgments back through the link.
+fn receive(socket: &mut BatchSocket, link: &mut Link, rx: Receiver<Transfer>)
+    -> Result<(), Box<dyn Error>> {
+    let mut transfer: Option<Transfer> = None;
+    let mut events = BinaryHeap::new();
+    let mut order = 0;
+
+    loop {
+        if let Some(new) = rx.try_iter().last() {
+            if transfer.as_ref().is_some_and(|t| !t.is_complete()) {
+                println!("Previous transfer was not completed");
+            }
+            transfer = Some(new);
+            link.dropped_loss = 0;
+            link.dropped_queue = 0;
+            link.marked = 0;
+        }
+
+        // Handle everything that is due on the link
+        let now = Instant::now();
+        while events.peek().is_some_and(|e: &Event| e.at <= now) {
+            let event = events.pop().unwrap();
+            match event.kind {
+                Kind::Arrive(data, ecn) => {
+                    let Some(transfer) = transfer.as_mut() else { continue };
+                    let was_complete = transfer.is_complete();
+                    let Some(ack) = transfer.on_datagram(&data, ecn, now) else { continue };
+                    if !was_complete && transfer.is_complete() {
+                        println!("Dropped on the link: {} by random loss, {} by full queue; {} marked CE",
+                            link.dropped_loss, link.dropped_queue, link.marked);
+                    }
+                    if let Some(at) = link.reverse(now) {
+                        order += 1;
+                        events.push(Event { at, order, peer: event.peer, kind: Kind::Ack(ack.to_bytes()) });
+                    }
+                }
+                Kind::Ack(ack) => {
+                    socket.socket().send_to(&ack, event.peer)?;
+                }
+            }
+        }
+
+        // Wait for datagrams until the next event is due
+        let wait = events.peek().map_or(IDLE_WAIT, |e| e.at.saturating_duration_since(now))
+            .min(IDLE_WAIT);
+        let mut arrived = Vec::new();
+        match socket.recv_batch(wait, |data, info| arrived.push((data.to_vec(), info.src, info.ecn))) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        let now = Instant::now();
+        for (data, peer, ecn) in arrived {
+            if let Some((at, ecn)) = link.forward(data.len(), ecn, now) {
+                order += 1;
+                events.push(Event { at, order, peer, kind: Kind::Arrive(data, ecn) });
+            }
+        }
+    }
+}
+
+mod args;
+mod link;
+mod receiver;
@@ -0,0 +1,159 @@
+/*  Receiving side of one transfer: keeps track of which datagrams have
+    arrived, and builds the cumulative acknowledgment for each datagram.
+
+    The real adnet-agent computes its check byte in its own way. The stand-in
+    folds in each sequence number as the cumulative acknowledgment advances,
+    so that the final value depends on every datagram having arrived.
+
+    When datagrams are ECN-capable, the acknowledgments also carry the number
+    of datagrams that arrived with the CE mark (protocol.rs).
+
+    Repair datagrams (fec.rs) are passed to the FEC decoder, and a datagram
+    it rebuilds is handled as if it had arrived. Repair datagrams are
+    acknowledged only if they rebuild something, so that they do not look
+    like duplicate acknowledgments to the sender. As the cumulative
+    acknowledgment advances, the decoder drops the blocks it has passed.
+*/
+
+use std::time::Instant;
+
+use udp_transport::{
+    ecn,
+    fec::{Decoder, Repair},
+    protocol::{payload_range, Ack, Header, HEADER_LEN},
+};
+
+pub struct Transfer {
+    size: usize,
+    character: u8,
+    total: u32,
+    received: Vec<bool>,   // indexed by sequence number
+    cumulative: u32,
+    check: u8,
+    start: Option<Instant>,
+    ecn_capable: bool,      // sender has marked datagrams ECN-capable
+    ce: u32,                // datagrams received with CE
+    decoder: Decoder,
+
+    datagrams: u64,
+    duplicates: u64,
+    invalid: u64,
+    repairs: u64,           // repair datagrams received
+    repaired: u64,          // lost datagrams rebuilt from them
+}
+
+impl Transfer {
+    /// Transfer of `size` bytes in `total` datagrams (datagram_count).
+    pub fn new(size: usize, total: u32, character: u8) -> Transfer {
+        Transfer {
+            size,
+            character,
+            total,
+            received: vec![false; total as usize + 1],
+            cumulative: 0,
+            check: 0,
+            start: None,
+            ecn_capable: false,
+            ce: 0,
+            decoder: Decoder::new(),
+            datagrams: 0,
+            duplicates: 0,
+            invalid: 0,
+            repairs: 0,
+            repaired: 0,
+        }
+    }
+
+    /// Handle a datagram with ECN bits `ecn` that arrived at `now`. Returns
+    /// the acknowledgment to send, or None if there is nothing to
+    /// acknowledge.
+    pub fn on_datagram(&mut self, data: &[u8], ecn: u8, now: Instant) -> Option<Ack> {
+        let rebuilt = match Repair::parse(data) {
+            Some(repair) => {
+                self.repairs += 1;
+                self.count_ecn(ecn);
+                Some(self.decoder.on_repair(repair)?)
+            }
+            None => {
+                let header = Header::parse(data)?;
+                if !self.is_valid(&header, &data[HEADER_LEN..]) {
+                    self.invalid += 1;
+                    return None;
+                }
+                self.start.get_or_insert(now);
+                self.datagrams += 1;
+                self.count_ecn(ecn);
+                if self.received[header.seq as usize] {
+                    self.duplicates += 1;
+                }
+                self.received[header.seq as usize] = true;
+                self.decoder.on_data(data)
+            }
+        };
+
+        if let Some(datagram) = rebuilt {
+            match Header::parse(&datagram) {
+                Some(header) if self.is_valid(&header, &datagram[HEADER_LEN..]) => {
+                    if !self.received[header.seq as usize] {
+                        self.received[header.seq as usize] = true;
+                        self.repaired += 1;
+                    }
+                }
+                _ => self.invalid += 1,
+            }
+        }
+
+        let before = self.cumulative;
+        while self.cumulative < self.total && self.received[self.cumulative as usize + 1] {
+            self.cumulative += 1;
+            self.check = self.check.rotate_left(3) ^ (self.cumulative as u8) ^ self.character;
+        }
+        self.decoder.forget_through(self.cumulative);
+        if before < self.total && self.is_complete() {
+            self.print_summary(now);
+        }
+        Some(Ack {
+            seq: self.cumulative,
+            check: self.check,
+            ce: self.ecn_capable.then_some(self.ce),
+        })
+    }
+
+    fn count_ecn(&mut self, ecn: u8) {
+        self.ecn_capable |= ecn::is_capable(ecn);
+        if ecn == ecn::CE {
+            self.ce += 1;
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.cumulative == self.total
+    }
+
+    // Sequence number in range, length as expected for that position, and
+    // payload filled with the right character.
+    fn is_valid(&self, header: &Header, payload: &[u8]) -> bool {
+        if header.seq == 0 || header.seq > self.total {
+            return false;
+        }
+        let expected = payload_range(header.seq, self.size).len();
+        header.len as usize == expected
+            && payload.len() == expected
+            && payload.iter().all(|&c| c == self.character)
+    }
+
+    fn print_summary(&self, now: Instant) {
+        let duration = now - self.start.unwrap_or(now);
+        println!("Transfer complete: {} bytes in {} datagrams, {:?} from first datagram",
+            self.size, self.total, duration);
+        println!("Datagrams received: {}, duplicates: {}, invalid: {}, check byte: {}",
+            self.datagrams, self.duplicates, self.invalid, self.check);
+        if self.ecn_capable {
+            println!("Datagrams received with CE: {}", self.ce);
+        }
+        if self.repairs > 0 {
+            println!("FEC repair datagrams received: {}, lost datagrams repaired: {}",
+                self.repairs, self.repaired);
+        }
+    }
+}
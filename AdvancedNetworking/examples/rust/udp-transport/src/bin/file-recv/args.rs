@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use udp_transport::batch::Batching;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Address to receive files at.
+    #[arg(short, long, default_value = "0.0.0.0:20001")]
+    listen_addr: String,
+
+    /// Directory where received files are stored.
+    #[arg(short, long, default_value = ".")]
+    dir: PathBuf,
+
+    /// How datagrams are received from the kernel.
+    #[arg(short, long, value_enum, default_value_t = Batching::Mmsg)]
+    batching: Batching,
+
+    /// Largest file accepted, in bytes.
+    #[arg(short, long, default_value_t = 1 << 30)]
+    max_size: u64,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn listen_addr(&self) -> &String {
+        &self.listen_addr
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    pub fn batching(&self) -> Batching {
+        self.batching
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+}
@@ -0,0 +1,212 @@
+/*  One file being received.
+
+    Data is written to "<name>.part" at the offset of each datagram, and
+    the number of consecutive datagrams received so far is saved in
+    "<name>.part.state" together with the size and digest of the file. If
+    the transfer is interrupted, a new transfer of the same file continues
+    from that point. When the whole file has arrived, its digest is
+    checked, and the file is renamed to its final name, or discarded if the
+    digest does not match.
+*/
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    net::SocketAddr,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use udp_transport::{
+    file::{Metadata, STATUS_CORRUPT, STATUS_PENDING, STATUS_VERIFIED},
+    protocol::{datagram_count, payload_range, Ack, Header, HEADER_LEN},
+    sha256,
+};
+
+/// How often the resume point is saved during transfer.
+const SAVE_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Incoming {
+    metadata: Metadata,
+    path: PathBuf,          // final name
+    part: PathBuf,          // data while receiving
+    state: PathBuf,         // resume point
+    file: Option<File>,     // None when finished
+    total: u32,
+    received: Vec<bool>,    // indexed by sequence number
+    cumulative: u32,
+    status: u8,
+    saved: Instant,
+    last_active: Instant,
+
+    start: Instant,
+    resumed: u32,
+    datagrams: u64,
+    duplicates: u64,
+}
+
+impl Incoming {
+    /// Prepare to receive a file into `dir`. Continues an interrupted
+    /// transfer of the same file, if there is one.
+    pub fn open(dir: &Path, metadata: Metadata, peer: SocketAddr) -> io::Result<Incoming> {
+        // Only the last component of the name, so that the sender cannot
+        // write outside the directory
+        let name = Path::new(&metadata.name).file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"))?;
+        let path = dir.join(name);
+        let part = dir.join(format!("{}.part", name.to_string_lossy()));
+        let state = dir.join(format!("{}.part.state", name.to_string_lossy()));
+        let total = datagram_count(metadata.size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+
+        let now = Instant::now();
+        let mut incoming = Incoming {
+            metadata,
+            path,
+            part,
+            state,
+            file: None,
+            total,
+            received: vec![false; total as usize + 1],
+            cumulative: 0,
+            status: STATUS_PENDING,
+            saved: now,
+            last_active: now,
+            start: now,
+            resumed: 0,
+            datagrams: 0,
+            duplicates: 0,
+        };
+
+        // Received completely earlier
+        if incoming.path.exists() && sha256::file_digest(&incoming.path)? == incoming.metadata.digest {
+            incoming.cumulative = total;
+            incoming.status = STATUS_VERIFIED;
+            println!("{}: already received", incoming.path.display());
+            return Ok(incoming);
+        }
+
+        let resume = incoming.load_state().filter(|_| incoming.part.exists()).unwrap_or(0);
+        let file = OpenOptions::new().create(true).write(true).truncate(resume == 0)
+            .open(&incoming.part)?;
+        file.set_len(incoming.metadata.size)?;
+        incoming.file = Some(file);
+        incoming.cumulative = resume;
+        incoming.resumed = resume;
+        incoming.received[..=resume as usize].fill(true);
+        println!("{}: receiving {} bytes from {}{}", incoming.path.display(),
+            incoming.metadata.size, peer,
+            if resume > 0 { format!(", resuming after datagram {}", resume) } else { String::new() });
+
+        if incoming.cumulative == total {
+            incoming.finish()?;
+        }
+        Ok(incoming)
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.status == STATUS_PENDING
+    }
+
+    pub fn last_active(&self) -> Instant {
+        self.last_active
+    }
+
+    /// Acknowledgment for the current state of the transfer.
+    pub fn ack(&self) -> Ack {
+        Ack { seq: self.cumulative, check: self.status, ce: None }
+    }
+
+    /// Handle a data datagram. Returns the acknowledgment to send, or None
+    /// if the datagram does not belong to this file.
+    pub fn on_data(&mut self, data: &[u8]) -> io::Result<Option<Ack>> {
+        let Some(header) = Header::parse(data) else { return Ok(None) };
+        let payload = &data[HEADER_LEN..];
+        if header.seq == 0 || header.seq > self.total {
+            return Ok(None);
+        }
+        let range = payload_range(header.seq, self.metadata.size as usize);
+        if header.len as usize != range.len() || payload.len() != range.len() {
+            return Ok(None);
+        }
+        let now = Instant::now();
+        self.last_active = now;
+        self.datagrams += 1;
+
+        let seq = header.seq as usize;
+        match &self.file {
+            Some(file) if !self.received[seq] => {
+                file.write_all_at(payload, range.start as u64)?;
+                self.received[seq] = true;
+            }
+            _ => self.duplicates += 1,
+        }
+
+        let before = self.cumulative;
+        while self.cumulative < self.total && self.received[self.cumulative as usize + 1] {
+            self.cumulative += 1;
+        }
+        if self.cumulative == self.total && before < self.total {
+            self.finish()?;
+        } else if self.cumulative > before && now - self.saved >= SAVE_INTERVAL {
+            self.save_state()?;
+            self.saved = now;
+        }
+        Ok(Some(self.ack()))
+    }
+
+    /// Save the resume point, when a transfer is abandoned.
+    pub fn close(&mut self) -> io::Result<()> {
+        if self.is_pending() {
+            self.save_state()?;
+            println!("{}: transfer stopped after {} of {} datagrams", self.path.display(),
+                self.cumulative, self.total);
+        }
+        Ok(())
+    }
+
+    // All data has arrived: check the digest.
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+        }
+        let digest = sha256::file_digest(&self.part)?;
+        let _ = fs::remove_file(&self.state);
+        let duration = self.start.elapsed();
+        if digest == self.metadata.digest {
+            fs::rename(&self.part, &self.path)?;
+            self.status = STATUS_VERIFIED;
+            println!("{}: complete, SHA-256 verified", self.path.display());
+        } else {
+            fs::remove_file(&self.part)?;
+            self.status = STATUS_CORRUPT;
+            println!("{}: SHA-256 mismatch, got {}, data discarded", self.path.display(),
+                sha256::to_hex(&digest));
+        }
+        println!("Received {} datagrams ({} duplicates) in {:?}, resumed after datagram {}",
+            self.datagrams, self.duplicates, duration, self.resumed);
+        Ok(())
+    }
+
+    // State file: "<size> <digest> <consecutive datagrams received>"
+    fn save_state(&self) -> io::Result<()> {
+        fs::write(&self.state, format!("{} {} {}\n", self.metadata.size,
+            sha256::to_hex(&self.metadata.digest), self.cumulative))
+    }
+
+    // Resume point from the state file, if it is for the same file.
+    fn load_state(&self) -> Option<u32> {
+        let state = fs::read_to_string(&self.state).ok()?;
+        let mut words = state.split_whitespace();
+        let size: u64 = words.next()?.parse().ok()?;
+        let digest = words.next()?;
+        let cumulative: u32 = words.next()?.parse().ok()?;
+        (size == self.metadata.size && digest == sha256::to_hex(&self.metadata.digest))
+            .then_some(cumulative.min(self.total))
+    }
+}
@@ -0,0 +1,135 @@
+/*  Receives files sent with file-send, and stores them in a directory.
+
+    Each sender address has its own transfer, so several files can arrive
+    at the same time. A transfer is dropped when nothing has been heard
+    from the sender for a while; its resume point is saved, and the sender
+    can continue later by sending the same file again.
+
+    The size in the metadata comes from the network, so files larger than
+    --max-size are refused before anything is allocated for them.
+
+    Usage: cargo run --release --bin file-recv -- [-l <address:port>] [-d <directory>]
+                                                  [-m <bytes>]
+*/
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use udp_transport::{
+    batch::BatchSocket,
+    file::{Metadata, STATUS_REFUSED},
+    protocol::{Ack, Header},
+};
+
+use crate::{args::Args, incoming::Incoming};
+
+// Transfers without datagrams for this long are dropped.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::new();
+    let mut socket = BatchSocket::new(UdpSocket::bind(args.listen_addr())?, args.batching());
+    println!("Receiving files at {} into {}", socket.socket().local_addr()?, args.dir().display());
+
+    let mut transfers: HashMap<SocketAddr, Incoming> = HashMap::new();
+    loop {
+        let mut received = Vec::new();
+        match socket.recv_batch(Duration::from_secs(1), |data, info| {
+            received.push((data.to_vec(), info.src));
+        }) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        for (data, peer) in received {
+            let Some(header) = Header::parse(&data) else { continue };
+            let ack = if header.seq == 0 {
+                let Some(metadata) = Metadata::parse(&data) else { continue };
+                on_metadata(&mut transfers, &args, metadata, peer)
+            } else {
+                let Some(incoming) = transfers.get_mut(&peer) else { continue };
+                match incoming.on_data(&data) {
+                    Ok(ack) => ack,
+                    Err(e) => {
+                        // For example disk full: give up. The sender times out and asks
+                        // again, or gives up after a few attempts.
+                        println!("{}: {}", incoming.metadata().name, e);
+                        transfers.remove(&peer);
+                        None
+                    }
+                }
+            };
+            if let Some(ack) = ack {
+                socket.socket().send_to(&ack.to_bytes(), peer)?;
+            }
+        }
+
+        // Forget senders that have gone away
+        transfers.retain(|_, incoming| {
+            if incoming.last_active().elapsed() < IDLE_TIMEOUT {
+                return true;
+            }
+            if let Err(e) = incoming.close() {
+                println!("{}: saving state failed: {}", incoming.metadata().name, e);
+            }
+            false
+        });
+    }
+}
+
+
+// Start a new transfer, or answer again to a sender whose previous answer
+// was lost.
+fn on_metadata(transfers: &mut HashMap<SocketAddr, Incoming>, args: &Args,
+    metadata: Metadata, peer: SocketAddr) -> Option<Ack> {
+    if let Some(incoming) = transfers.get(&peer) {
+        if *incoming.metadata() == metadata {
+            return Some(incoming.ack());
+        }
+    }
+    if let Some(mut old) = transfers.remove(&peer) {
+        let _ = old.close();
+    }
+    if metadata.size > args.max_size() {
+        println!("{}: {} bytes is more than the limit of {}", metadata.name, metadata.size,
+            args.max_size());
+        return Some(Ack { seq: 0, check: STATUS_REFUSED, ce: None });
+    }
+    // Two senders must not write the same file at the same time. A sender
+    // that was restarted comes from a new port: it takes over the transfer
+    // of the same file, and continues from where the old one got.
+    let same_name: Vec<SocketAddr> = transfers.iter()
+        .filter(|(_, t)| t.metadata().name == metadata.name)
+        .map(|(addr, _)| *addr)
+        .collect();
+    for addr in same_name {
+        if transfers[&addr].is_pending() && *transfers[&addr].metadata() != metadata {
+            println!("{}: already being received from another sender", metadata.name);
+            return Some(Ack { seq: 0, check: STATUS_REFUSED, ce: None });
+        }
+        if let Some(mut old) = transfers.remove(&addr) {
+            let _ = old.close();
+        }
+    }
+
+    match Incoming::open(args.dir(), metadata.clone(), peer) {
+        Ok(incoming) => {
+            let ack = incoming.ack();
+            transfers.insert(peer, incoming);
+            Some(ack)
+        }
+        Err(e) => {
+            println!("{}: cannot receive: {}", metadata.name, e);
+            Some(Ack { seq: 0, check: STATUS_REFUSED, ce: None })
+        }
+    }
+}
+
+mod args;
+mod incoming;
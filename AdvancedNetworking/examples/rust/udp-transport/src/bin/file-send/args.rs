@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use udp_transport::{batch::Batching, congestion::Algorithm};
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// File to send.
+    file: PathBuf,
+
+    /// Address of the receiver (file-recv).
+    #[arg(short, long, default_value = "127.0.0.1:20001")]
+    server_addr: String,
+
+    /// Name to store the file with at the receiver. Defaults to the name of
+    /// the file being sent.
+    #[arg(short, long)]
+    name: Option<String>,
+
+    /// How datagrams are passed to the kernel.
+    #[arg(short, long, value_enum, default_value_t = Batching::Mmsg)]
+    batching: Batching,
+
+    /// How congestion control reacts to ECN marks.
+    #[arg(long, value_enum, default_value_t = Algorithm::Reno)]
+    cc: Algorithm,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args::parse()
+    }
+
+    pub fn file(&self) -> &PathBuf {
+        &self.file
+    }
+
+    pub fn server_addr(&self) -> &String {
+        &self.server_addr
+    }
+
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
+    pub fn batching(&self) -> Batching {
+        self.batching
+    }
+
+    pub fn cc(&self) -> Algorithm {
+        self.cc
+    }
+}
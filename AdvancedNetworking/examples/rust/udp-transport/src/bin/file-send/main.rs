@@ -0,0 +1,139 @@
+/*  Sends a file to file-recv using the task-udp datagram format, with the
+    same retransmissions and congestion control as the client (transport.rs).
+
+    The transfer starts with the file name, size and SHA-256 digest
+    (file.rs). If an earlier transfer of the same file was interrupted, the
+    receiver tells how far it got, and the transfer continues from there.
+    At the end the receiver tells whether the digest of what it received
+    matches.
+
+    If the receiver stops acknowledging in the middle (e.g. it was
+    restarted, or dropped the transfer), the sender asks it again how far
+    it got and continues from there. After MAX_RESUMES attempts in a row
+    that do not get further, it gives up and tells how much was
+    acknowledged; sending the file again later continues from there.
+
+    Usage: cargo run --release --bin file-send -- [-s <address:port>] [-n <name>]
+                                                  [-b single|mmsg|gso] [--cc reno|dctcp] <file>
+*/
+
+use std::{
+    error::Error,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    io,
+    time::Instant,
+};
+
+use udp_transport::{
+    batch::BatchSocket,
+    file::{handshake, FileSource, Metadata, STATUS_CORRUPT, STATUS_REFUSED, STATUS_VERIFIED},
+    protocol::{datagram_count, MAX_PAYLOAD},
+    sha256,
+    transport::Sender,
+};
+
+use crate::args::Args;
+
+/// Handshakes in a row after a timed out transfer, without getting further.
+const MAX_RESUMES: u32 = 3;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::new();
+
+    let mut source = FileSource::open(args.file())?;
+    let name = match args.name() {
+        Some(name) => name.clone(),
+        None => args.file().file_name().ok_or("not a file name")?.to_string_lossy().to_string(),
+    };
+    if name.len() > Metadata::max_name_len() {
+        return Err("file name is too long".into());
+    }
+    let metadata = Metadata {
+        name,
+        size: std::fs::metadata(args.file())?.len(),
+        digest: sha256::file_digest(args.file())?,
+    };
+    println!("Sending {} as {}: {} bytes, SHA-256 {}", args.file().display(), metadata.name,
+        metadata.size, sha256::to_hex(&metadata.digest));
+    let total = datagram_count(metadata.size).ok_or("file is too large to send")?;
+
+    let dest = args.server_addr().to_socket_addrs()?.next()
+        .ok_or("could not resolve receiver address")?;
+    let socket = match dest {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
+    let mut socket = BatchSocket::new(socket, args.batching());
+
+    let start = Instant::now();
+    let bytes_in = |datagrams: u32| metadata.size.min(datagrams as u64 * MAX_PAYLOAD as u64);
+    let mut remaining = None;   // bytes that needed to be sent at the first answer
+    let mut acked = 0;          // bytes acknowledged when the previous attempt timed out
+    let mut stalled = 0;
+    let status = loop {
+        let answer = match handshake(&mut socket, dest, &metadata) {
+            Ok(answer) => answer,
+            Err(e) => {
+                if remaining.is_some() {
+                    report_resume_point(acked, metadata.size);
+                }
+                return Err(e.into());
+            }
+        };
+        if answer.check == STATUS_REFUSED {
+            return Err("receiver refused the file".into());
+        }
+        if remaining.is_none() {
+            remaining = Some(metadata.size - bytes_in(answer.seq));
+            if answer.seq >= total && total > 0 {
+                println!("Receiver already has the file");
+            }
+        }
+        if answer.seq >= total {
+            break answer.check;
+        }
+        if answer.seq > 0 {
+            println!("Resuming: receiver already has {} bytes", bytes_in(answer.seq));
+        }
+
+        let mut sender = Sender::new(socket, dest, source, args.cc())?.resume_from(answer.seq);
+        let result = sender.run();
+        let stats = sender.stats();
+        println!("Datagrams sent: {}, retransmitted: {} ({} fast retransmits, {} timeouts), acks: {}",
+            stats.sent, stats.retransmitted, stats.fast_retransmits, stats.timeouts, stats.acks);
+        match result {
+            Ok(status) => break status,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                acked = bytes_in(sender.acked());
+                stalled = if sender.acked() > answer.seq { 0 } else { stalled + 1 };
+                if stalled == MAX_RESUMES {
+                    report_resume_point(acked, metadata.size);
+                    return Err(e.into());
+                }
+                println!("{}: {} of {} bytes acknowledged, asking the receiver where to continue",
+                    e, acked, metadata.size);
+                (socket, source) = sender.into_parts();
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    let remaining = remaining.unwrap_or(0);
+    let duration = start.elapsed();
+
+    match status {
+        STATUS_VERIFIED => {
+            println!("Receiver verified the SHA-256 digest. Duration: {:?}, {:.2} Mbit/s",
+                duration, remaining as f64 * 8.0 / duration.as_secs_f64() / 1e6);
+            Ok(())
+        }
+        STATUS_CORRUPT => Err("digest did not match at receiver, data was discarded".into()),
+        other => Err(format!("unexpected status from receiver: {}", other).into()),
+    }
+}
+
+fn report_resume_point(acked: u64, size: u64) {
+    println!("Receiver acknowledged {} of {} bytes. Sending the file again continues from \
+        where the receiver got.", acked, size);
+}
+
+mod args;
@@ -0,0 +1,133 @@
+/*  Benchmark of the batching modes in batch.rs: how many datagrams per
+    second can be sent and received on loopback, with one datagram per
+    system call, with sendmmsg/recvmmsg, and with UDP GSO/GRO.
+
+    For each mode, a receiver thread counts arriving datagrams while the
+    sender sends full-size datagrams as fast as it can. The sender may be
+    faster than the receiver, in which case the socket receive buffer fills
+    up and the excess datagrams are dropped, shown as loss.
+
+    Run in release mode, debug builds are too slow to tell the difference.
+
+    Usage: cargo run --release --bin udp-bench -- [-m single|mmsg|gso] [-d <seconds>] [--size <bytes>]
+*/
+
+use std::{
+    error::Error,
+    io,
+    net::{Ipv4Addr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use socket2::SockRef;
+
+use udp_transport::{
+    batch::{BatchSocket, Batching, MAX_BATCH},
+    protocol::MAX_DATAGRAM,
+};
+
+// Large receive buffer, so that the receiver is not limited by short
+// scheduling delays.
+const RCVBUF: usize = 8 * 1024 * 1024;
+
+/// Command line arguments parser for this application.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Mode to test. All modes are tested if not given.
+    #[arg(short, long, value_enum)]
+    mode: Option<Batching>,
+
+    /// Duration of each test, in seconds.
+    #[arg(short, long, default_value_t = 2.0)]
+    duration: f64,
+
+    /// Datagram size in bytes.
+    #[arg(long, default_value_t = MAX_DATAGRAM)]
+    size: usize,
+}
+
+// Results of one test.
+struct Result {
+    mode: Batching,
+    sent: u64,
+    received: u64,
+    bytes: u64,
+    duration: Duration,
+}
+
+
+fn main() -> std::result::Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let modes = match args.mode {
+        Some(mode) => vec![mode],
+        None => vec![Batching::Single, Batching::Mmsg, Batching::Gso],
+    };
+
+    let mut results = Vec::new();
+    for mode in modes {
+        results.push(run(mode, Duration::from_secs_f64(args.duration), args.size)?);
+    }
+
+    println!("{:>8} {:>12} {:>12} {:>8} {:>10}", "mode", "sent/s", "received/s", "loss", "Mbit/s");
+    for r in results {
+        let secs = r.duration.as_secs_f64();
+        let loss = if r.sent > 0 { 100.0 * (1.0 - r.received as f64 / r.sent as f64) } else { 0.0 };
+        println!("{:>8} {:>12.0} {:>12.0} {:>7.1}% {:>10.1}",
+            format!("{:?}", r.mode).to_lowercase(),
+            r.sent as f64 / secs,
+            r.received as f64 / secs,
+            loss,
+            r.bytes as f64 * 8.0 / secs / 1e6);
+    }
+    Ok(())
+}
+
+
+fn run(mode: Batching, duration: Duration, size: usize) -> io::Result<Result> {
+    let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    SockRef::from(&receiver).set_recv_buffer_size(RCVBUF)?;
+    let dest = receiver.local_addr()?;
+    let mut receiver = BatchSocket::new(receiver, mode);
+    let mut sender = BatchSocket::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?, mode);
+    // Report the mode actually used, after possible fallback
+    let mode = sender.mode();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let receiving = {
+        let done = done.clone();
+        thread::spawn(move || -> io::Result<(u64, u64)> {
+            let (mut count, mut bytes) = (0, 0);
+            loop {
+                let received = receiver.recv_batch(Duration::from_millis(100), |data, _| {
+                    count += 1;
+                    bytes += data.len() as u64;
+                })?;
+                // Sender has finished and the socket is drained
+                if received == 0 && done.load(Ordering::Relaxed) {
+                    return Ok((count, bytes));
+                }
+            }
+        })
+    };
+
+    let buf = vec![b'A'; size];
+    let datagrams: Vec<&[u8]> = vec![&buf; MAX_BATCH];
+    let mut sent = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        sender.send_batch(&datagrams, dest)?;
+        sent += datagrams.len() as u64;
+    }
+    let elapsed = start.elapsed();
+    done.store(true, Ordering::Relaxed);
+
+    let (received, bytes) = receiving.join().expect("receiver thread panicked")?;
+    Ok(Result { mode, sent, received, bytes, duration: elapsed })
+}
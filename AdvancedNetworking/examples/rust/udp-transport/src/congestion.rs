@@ -0,0 +1,213 @@
+/*  Congestion control for the UDP sender, similar to TCP Reno.
+
+    The window tells how many datagrams may be unacknowledged at a time.
+    - Slow start: the window grows by one datagram for each acknowledged
+      datagram, i.e., doubles every round trip, until it reaches the slow
+      start threshold.
+    - Congestion avoidance: after that the window grows by one datagram per
+      round trip.
+    - Loss detected by duplicate acknowledgments halves the window.
+    - Retransmission timeout drops the window to one datagram and starts slow
+      start again, because acknowledgments have stopped coming altogether.
+
+    When the acknowledgments echo ECN congestion marks (ecn.rs), the window
+    is reduced at most once per round trip:
+    - Reno: halved, as if a datagram had been lost (RFC 3168).
+    - DCTCP: reduced by alpha / 2, where alpha is a moving average of the
+      fraction of marked datagrams per round trip (RFC 8257). A few marks
+      cause only a small reduction, so that the queue can be kept short
+      without losing throughput. This requires that the bottleneck marks
+      datagrams as soon as the queue exceeds a small threshold.
+*/
+
+use clap::ValueEnum;
+
+/// Initial window, in datagrams (like TCP, RFC 6928).
+const INITIAL_WINDOW: f64 = 10.0;
+
+/// The window is never reduced below this after a loss.
+const MIN_WINDOW: f64 = 2.0;
+
+/// Weight of the latest round trip in DCTCP alpha (RFC 8257 suggests 1/16).
+const DCTCP_GAIN: f64 = 1.0 / 16.0;
+
+/// How the window reacts to ECN marks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    /// Halve the window when datagrams are marked
+    Reno,
+    /// Reduce the window in proportion to the fraction of marked datagrams
+    Dctcp,
+}
+
+pub struct Congestion {
+    algorithm: Algorithm,
+    cwnd: f64,
+    ssthresh: f64,
+
+    // ECN reaction
+    alpha: f64,             // estimated fraction of marked datagrams
+    window_end: u32,        // alpha is updated when this datagram is acknowledged
+    window_acked: u32,      // datagrams acknowledged in the current round trip
+    window_marked: u32,     // of which marked
+    reduced_until: u32,     // no new reduction until this datagram is acknowledged
+}
+
+impl Congestion {
+    pub fn new(algorithm: Algorithm) -> Congestion {
+        Congestion {
+            algorithm,
+            cwnd: INITIAL_WINDOW,
+            ssthresh: f64::INFINITY,
+            alpha: 1.0,     // start cautiously, like Linux
+            window_end: 0,
+            window_acked: 0,
+            window_marked: 0,
+            reduced_until: 0,
+        }
+    }
+
+    /// `acked` new datagrams were acknowledged.
+    pub fn on_ack(&mut self, acked: u32) {
+        if self.in_slow_start() {
+            self.cwnd += acked as f64;
+        } else {
+            self.cwnd += acked as f64 / self.cwnd;
+        }
+    }
+
+    /// Loss detected from duplicate acknowledgments, when `in_flight`
+    /// datagrams were unacknowledged.
+    pub fn on_loss(&mut self, in_flight: u32) {
+        self.ssthresh = (in_flight as f64 / 2.0).max(MIN_WINDOW);
+        self.cwnd = self.ssthresh;
+    }
+
+    /// Retransmission timeout.
+    pub fn on_timeout(&mut self, in_flight: u32) {
+        self.ssthresh = (in_flight as f64 / 2.0).max(MIN_WINDOW);
+        self.cwnd = 1.0;
+    }
+
+    /// Acknowledgment up to `acked_seq` covered `acked` new datagrams, and
+    /// echoed `marked` new CE marks. `last_sent` is the highest datagram
+    /// sent so far.
+    pub fn on_ecn(&mut self, acked_seq: u32, acked: u32, marked: u32, last_sent: u32) {
+        self.window_acked += acked;
+        self.window_marked += marked;
+
+        if marked > 0 && acked_seq >= self.reduced_until {
+            let factor = match self.algorithm {
+                Algorithm::Reno => 0.5,
+                Algorithm::Dctcp => 1.0 - self.alpha / 2.0,
+            };
+            self.ssthresh = (self.cwnd * factor).max(MIN_WINDOW);
+            self.cwnd = self.ssthresh;
+            // Datagrams already in flight may carry marks of the same congestion
+            self.reduced_until = last_sent + 1;
+        }
+
+        // One round trip of data acknowledged
+        if acked_seq >= self.window_end && self.window_acked > 0 {
+            let fraction = self.window_marked as f64 / self.window_acked as f64;
+            self.alpha = (1.0 - DCTCP_GAIN) * self.alpha + DCTCP_GAIN * fraction.min(1.0);
+            self.window_acked = 0;
+            self.window_marked = 0;
+            self.window_end = last_sent + 1;
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// DCTCP estimate of the fraction of marked datagrams.
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+
+    /// Window in whole datagrams.
+    pub fn window(&self) -> u32 {
+        self.cwnd as u32
+    }
+
+    pub fn cwnd(&self) -> f64 {
+        self.cwnd
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn dctcp_reduction_follows_marked_fraction() {
+        let mut dctcp = Congestion::new(Algorithm::Dctcp);
+
+        // Round trips of one datagram without marks let alpha decay
+        for seq in 1..=16 {
+            dctcp.on_ecn(seq, 1, 0, seq);
+        }
+        let alpha = dctcp.alpha();
+        assert_close(alpha, (1.0 - DCTCP_GAIN).powi(16));
+        assert_close(dctcp.cwnd(), INITIAL_WINDOW);
+
+        // A mark reduces the window by alpha / 2, less than Reno would
+        dctcp.on_ecn(17, 1, 1, 30);
+        assert_close(dctcp.cwnd(), INITIAL_WINDOW * (1.0 - alpha / 2.0));
+        assert!(dctcp.cwnd() > INITIAL_WINDOW / 2.0);
+
+        let mut reno = Congestion::new(Algorithm::Reno);
+        reno.on_ecn(17, 1, 1, 30);
+        assert_close(reno.cwnd(), INITIAL_WINDOW / 2.0);
+    }
+
+    #[test]
+    fn one_reduction_per_round_trip() {
+        let mut cc = Congestion::new(Algorithm::Reno);
+        cc.on_ecn(1, 1, 1, 20);
+        assert_close(cc.cwnd(), INITIAL_WINDOW / 2.0);
+
+        // Marks on datagrams sent before the reduction are of the same
+        // congestion
+        cc.on_ecn(10, 9, 3, 25);
+        cc.on_ecn(20, 10, 5, 30);
+        assert_close(cc.cwnd(), INITIAL_WINDOW / 2.0);
+
+        // Datagram 21 was sent after the reduction
+        cc.on_ecn(21, 1, 1, 30);
+        assert_close(cc.cwnd(), INITIAL_WINDOW / 4.0);
+    }
+
+    #[test]
+    fn alpha_updated_at_window_end() {
+        let mut cc = Congestion::new(Algorithm::Dctcp);
+
+        // The first acknowledgment ends the initial round trip, which has
+        // no marks. The next round trip ends when datagram 11 is acknowledged.
+        cc.on_ecn(1, 1, 0, 10);
+        let alpha = 1.0 - DCTCP_GAIN;  // from the initial 1.0
+        assert_close(cc.alpha(), alpha);
+
+        cc.on_ecn(6, 5, 2, 15);
+        assert_close(cc.alpha(), alpha);
+
+        // 5 of the 10 datagrams in the round trip were marked
+        cc.on_ecn(11, 5, 3, 20);
+        let alpha = (1.0 - DCTCP_GAIN) * alpha + DCTCP_GAIN * 0.5;
+        assert_close(cc.alpha(), alpha);
+
+        // The counters start over: an unmarked round trip up to 21
+        cc.on_ecn(21, 10, 0, 30);
+        assert_close(cc.alpha(), (1.0 - DCTCP_GAIN) * alpha);
+    }
+}
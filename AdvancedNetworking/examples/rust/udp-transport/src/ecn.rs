@@ -0,0 +1,73 @@
+/*  Explicit Congestion Notification (RFC 3168).
+
+    The two lowest bits of the IPv4 TOS byte (IPv6 traffic class) tell
+    whether the sender understands ECN. A router whose queue is building up
+    can then set the bits to CE (congestion experienced) instead of dropping
+    the packet. The receiver echoes the marks back to the sender in its
+    acknowledgments, and the sender slows down as it would after a loss, but
+    without having to retransmit anything.
+
+    A UDP application has to do both ends itself: set ECT on outgoing
+    datagrams with IP_TOS, and read the bits of received datagrams from
+    IP_TOS control messages after enabling IP_RECVTOS.
+*/
+
+use std::{
+    io,
+    mem,
+    net::UdpSocket,
+    os::fd::AsRawFd,
+};
+
+/// Not ECN-capable transport.
+pub const NOT_ECT: u8 = 0b00;
+
+/// ECN-capable transport, codepoint 1 (used by L4S).
+pub const ECT1: u8 = 0b01;
+
+/// ECN-capable transport, codepoint 0 (the classic one).
+pub const ECT0: u8 = 0b10;
+
+/// Congestion experienced.
+pub const CE: u8 = 0b11;
+
+/// Mask of the ECN bits in the TOS byte.
+pub const MASK: u8 = 0b11;
+
+/// Whether the sender of a datagram with these ECN bits understands ECN.
+pub fn is_capable(ecn: u8) -> bool {
+    ecn & MASK != NOT_ECT
+}
+
+/// Mark datagrams sent from this socket with `ecn` (ECT0 or NOT_ECT).
+pub fn set_codepoint(socket: &UdpSocket, ecn: u8) -> io::Result<()> {
+    let (level, name) = if socket.local_addr()?.is_ipv4() {
+        (libc::IPPROTO_IP, libc::IP_TOS)
+    } else {
+        (libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+    };
+    setsockopt_int(socket, level, name, (ecn & MASK) as libc::c_int)
+}
+
+/// Ask for the TOS byte (traffic class) of each received datagram in a
+/// control message. See batch.rs for reading it.
+pub fn enable_receive(socket: &UdpSocket) -> io::Result<()> {
+    if socket.local_addr()?.is_ipv4() {
+        setsockopt_int(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1)
+    } else {
+        setsockopt_int(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)
+    }
+}
+
+fn setsockopt_int(socket: &UdpSocket, level: libc::c_int, name: libc::c_int, value: libc::c_int)
+    -> io::Result<()> {
+    let rc = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), level, name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,343 @@
+/*  Forward error correction with XOR parity.
+
+    After every block of k data datagrams the sender sends one repair
+    datagram, that is the XOR of the whole data datagrams of the block
+    (headers included, shorter ones padded with zeros). If one datagram of
+    the block is lost, XORing the repair with the others that did arrive
+    gives the lost datagram back, without waiting a round trip for a
+    retransmission. If two or more are lost, the block cannot be repaired,
+    and the normal retransmissions take care of it.
+
+    XOR repairs one loss per block, at the cost of 1/k extra datagrams.
+    Reed-Solomon codes could repair as many losses as there are repair
+    datagrams, but that is left out for simplicity.
+
+    Repair datagram format, so that it cannot be mistaken for data:
+    - 4-byte sequence number with the highest bit set, the rest telling the
+      first sequence number of the block
+    - 2-byte length of the rest of the datagram
+    - 1 byte: number of datagrams in the block
+    - the XOR parity
+
+    adnet-agent does not understand repair datagrams: this works only with
+    the agent stand-in (src/bin/agent).
+*/
+
+use std::collections::BTreeMap;
+
+use crate::protocol::{Header, HEADER_LEN};
+
+/// Bit set in the sequence number of repair datagrams.
+pub const REPAIR_FLAG: u32 = 1 << 31;
+
+/// Repair datagram of one block.
+pub struct Repair {
+    pub first: u32,
+    pub count: u8,
+    pub parity: Vec<u8>,
+}
+
+impl Repair {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0; HEADER_LEN];
+        Header { seq: REPAIR_FLAG | self.first, len: (self.parity.len() + 1) as u16 }
+            .write(&mut buf);
+        buf.push(self.count);
+        buf.extend_from_slice(&self.parity);
+        buf
+    }
+
+    /// Returns None if this is not a repair datagram.
+    pub fn parse(buf: &[u8]) -> Option<Repair> {
+        let header = Header::parse(buf)?;
+        if header.seq & REPAIR_FLAG == 0 || buf.len() < HEADER_LEN + 1 {
+            return None;
+        }
+        Some(Repair {
+            first: header.seq & !REPAIR_FLAG,
+            count: buf[HEADER_LEN],
+            parity: buf[HEADER_LEN + 1..].to_vec(),
+        })
+    }
+}
+
+
+/// Builds repair datagrams at the sender.
+pub struct Encoder {
+    block: u8,
+    first: u32,
+    count: u8,
+    parity: Vec<u8>,
+}
+
+impl Encoder {
+    /// One repair datagram after every `block` data datagrams.
+    pub fn new(block: u8) -> Encoder {
+        Encoder { block, first: 0, count: 0, parity: Vec::new() }
+    }
+
+    pub fn block(&self) -> u8 {
+        self.block
+    }
+
+    /// Add the next new data datagram. Returns the repair datagram when the
+    /// block is full, or when this is the `last` datagram of the transfer.
+    /// Retransmissions must not be added.
+    pub fn add(&mut self, datagram: &[u8], last: bool) -> Option<Vec<u8>> {
+        if self.count == 0 {
+            self.first = Header::parse(datagram)?.seq;
+        }
+        xor_into(&mut self.parity, datagram);
+        self.count += 1;
+        if self.count < self.block && !last {
+            return None;
+        }
+        let repair = Repair {
+            first: self.first,
+            count: self.count,
+            parity: std::mem::take(&mut self.parity),
+        };
+        self.count = 0;
+        Some(repair.to_bytes())
+    }
+}
+
+
+/// Recovers lost datagrams at the receiver.
+///
+/// The receiver does not know whether the sender uses FEC, or the block
+/// size, before the first repair datagram arrives. Until then nothing is
+/// kept, so the blocks before the first repair cannot be repaired. After
+/// that, data datagrams are kept until the repair of their block has
+/// arrived and the block is either complete or repaired, or until
+/// `forget_through` tells that the block is no longer needed, e.g. because
+/// its repair was lost and retransmissions filled the gap.
+#[derive(Default)]
+pub struct Decoder {
+    datagrams: BTreeMap<u32, Vec<u8>>,  // data datagrams of unresolved blocks
+    repairs: BTreeMap<u32, Repair>,     // repairs of unresolved blocks by first sequence
+    block: u32,                         // largest block seen, 0 before the first repair
+    forgotten: u32,                     // blocks ending at or below this are not needed
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Data datagram arrived. Returns a datagram recovered with its help.
+    pub fn on_data(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let seq = Header::parse(datagram)?.seq;
+        if self.block == 0 || seq <= self.forgotten {
+            return None;
+        }
+        self.datagrams.entry(seq).or_insert_with(|| datagram.to_vec());
+        let (&first, repair) = self.repairs.range(..=seq).next_back()?;
+        if seq >= first + repair.count as u32 {
+            return None;
+        }
+        self.try_recover(first)
+    }
+
+    /// Repair datagram arrived. Returns the datagram it recovered, if
+    /// exactly one of its block was missing.
+    pub fn on_repair(&mut self, repair: Repair) -> Option<Vec<u8>> {
+        let first = repair.first;
+        let count = repair.count as u32;
+        if count == 0 || first + count - 1 <= self.forgotten {
+            return None;
+        }
+        self.block = self.block.max(count);
+        self.repairs.insert(first, repair);
+        self.try_recover(first)
+    }
+
+    /// All datagrams up to `seq` have arrived (the cumulative
+    /// acknowledgment). Drops the blocks that end at or below it, and the
+    /// datagrams that cannot belong to a block that is still open.
+    pub fn forget_through(&mut self, seq: u32) {
+        if seq <= self.forgotten {
+            return;
+        }
+        self.forgotten = seq;
+        self.repairs.retain(|&first, repair| first + repair.count as u32 - 1 > seq);
+        // A block that is still open contains seq + 1, so it cannot start
+        // before seq + 2 - block
+        let keep_from = (seq + 2).saturating_sub(self.block);
+        self.datagrams = self.datagrams.split_off(&keep_from);
+    }
+
+    fn try_recover(&mut self, first: u32) -> Option<Vec<u8>> {
+        let repair = &self.repairs[&first];
+        let block = first..first + repair.count as u32;
+        let mut missing = block.clone().filter(|seq| !self.datagrams.contains_key(seq));
+        let lost = match (missing.next(), missing.next()) {
+            (Some(seq), None) => seq,
+            (Some(_), Some(_)) => return None,  // wait for more datagrams or retransmissions
+            (None, _) => {
+                // Nothing lost, the block is no longer needed
+                for seq in block {
+                    self.datagrams.remove(&seq);
+                }
+                self.repairs.remove(&first);
+                return None;
+            }
+        };
+
+        let mut datagram = repair.parity.clone();
+        for seq in block.clone() {
+            if let Some(data) = self.datagrams.remove(&seq) {
+                xor_into(&mut datagram, &data);
+            }
+        }
+        self.repairs.remove(&first);
+
+        // What remains is the lost datagram, padded with zeros
+        let header = Header::parse(&datagram)?;
+        if header.seq != lost || HEADER_LEN + header.len as usize > datagram.len() {
+            return None;
+        }
+        datagram.truncate(HEADER_LEN + header.len as usize);
+        Some(datagram)
+    }
+}
+
+
+fn xor_into(parity: &mut Vec<u8>, data: &[u8]) {
+    if parity.len() < data.len() {
+        parity.resize(data.len(), 0);
+    }
+    for (p, d) in parity.iter_mut().zip(data) {
+        *p ^= d;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: u8 = 3;
+
+    // Data datagram `seq` with `len` bytes of payload. The payload differs
+    // between datagrams, so that XOR of the wrong datagrams is noticed.
+    fn data(seq: u32, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; HEADER_LEN];
+        Header { seq, len: len as u16 }.write(&mut buf);
+        buf.extend((0..len).map(|i| (seq as usize * 7 + i) as u8));
+        buf
+    }
+
+    // Transfer of `count` full datagrams and a short last one, with the
+    // repair datagram of each block after the block. Returns the datagrams
+    // in sending order, with the sequence number of data or None for repair.
+    fn transfer(count: u32) -> Vec<(Option<u32>, Vec<u8>)> {
+        let mut encoder = Encoder::new(BLOCK);
+        let mut sent = Vec::new();
+        for seq in 1..=count {
+            let datagram = data(seq, if seq == count { 500 } else { 1200 });
+            let repair = encoder.add(&datagram, seq == count);
+            sent.push((Some(seq), datagram));
+            if let Some(repair) = repair {
+                sent.push((None, repair));
+            }
+        }
+        sent
+    }
+
+    // Deliver the datagrams except the lost ones, and return what was
+    // rebuilt. The lost repairs are given with the first sequence number of
+    // their block.
+    fn deliver(decoder: &mut Decoder, sent: &[(Option<u32>, Vec<u8>)], lost: &[u32],
+        lost_repairs: &[u32]) -> Vec<Vec<u8>> {
+        let mut rebuilt = Vec::new();
+        for (seq, datagram) in sent {
+            let result = match seq {
+                Some(seq) if lost.contains(seq) => continue,
+                Some(_) => decoder.on_data(datagram),
+                None => {
+                    let repair = Repair::parse(datagram).unwrap();
+                    if lost_repairs.contains(&repair.first) {
+                        continue;
+                    }
+                    decoder.on_repair(repair)
+                }
+            };
+            rebuilt.extend(result);
+        }
+        rebuilt
+    }
+
+    #[test]
+    fn repair_roundtrip() {
+        let repair = Repair { first: 4, count: 3, parity: vec![1, 2, 3] };
+        let parsed = Repair::parse(&repair.to_bytes()).unwrap();
+        assert_eq!((parsed.first, parsed.count, parsed.parity), (4, 3, vec![1, 2, 3]));
+        assert!(Repair::parse(&data(4, 10)).is_none());
+    }
+
+    #[test]
+    fn one_loss_per_block_is_rebuilt() {
+        // The first block only tells the decoder that FEC is in use. The
+        // last datagram, 9, is shorter than the others.
+        let sent = transfer(9);
+        let mut decoder = Decoder::new();
+        let rebuilt = deliver(&mut decoder, &sent, &[5, 9], &[]);
+        assert_eq!(rebuilt, vec![data(5, 1200), data(9, 500)]);
+    }
+
+    #[test]
+    fn two_losses_are_not_rebuilt() {
+        let sent = transfer(9);
+        let mut decoder = Decoder::new();
+        assert!(deliver(&mut decoder, &sent, &[4, 6], &[]).is_empty());
+    }
+
+    #[test]
+    fn lost_repair_leaves_block_unrepaired() {
+        let sent = transfer(9);
+        let mut decoder = Decoder::new();
+        let rebuilt = deliver(&mut decoder, &sent, &[5, 8], &[4]);
+        assert_eq!(rebuilt, vec![data(8, 1200)]);
+    }
+
+    #[test]
+    fn retransmission_completes_block() {
+        let sent = transfer(9);
+        let mut decoder = Decoder::new();
+        assert!(deliver(&mut decoder, &sent, &[4, 6], &[]).is_empty());
+        assert_eq!(decoder.on_data(&data(4, 1200)), Some(data(6, 1200)));
+    }
+
+    #[test]
+    fn nothing_kept_without_repairs() {
+        let mut decoder = Decoder::new();
+        for seq in 1..=100 {
+            assert!(decoder.on_data(&data(seq, 1200)).is_none());
+        }
+        assert!(decoder.datagrams.is_empty());
+    }
+
+    #[test]
+    fn forget_through_drops_finished_blocks() {
+        // Repair of the second block lost, so its datagrams stay until the
+        // cumulative acknowledgment passes the block
+        let sent = transfer(9);
+        let mut decoder = Decoder::new();
+        deliver(&mut decoder, &sent[..9], &[5], &[4]);
+        assert_eq!(decoder.datagrams.keys().copied().collect::<Vec<_>>(), vec![4, 6, 7]);
+
+        // 5 retransmitted, acknowledged up to 7. A block of three with 8
+        // in it may start from 6, so 6 and 7 are kept.
+        decoder.on_data(&data(5, 1200));
+        decoder.forget_through(7);
+        assert_eq!(decoder.datagrams.keys().copied().collect::<Vec<_>>(), vec![6, 7]);
+        assert!(decoder.repairs.is_empty());
+
+        // A late repair of a finished block is ignored
+        let late = Repair::parse(&sent[7].1).unwrap();
+        assert_eq!(late.first, 4);
+        assert!(decoder.on_repair(late).is_none());
+        assert!(decoder.repairs.is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+/*  File transfer on top of the task-udp datagram format (protocol.rs).
+
+    Sequence numbers of data start from 1, so number 0 is free for the
+    metadata datagram that starts a transfer. Its payload has:
+    - 8-byte file size, in network byte order
+    - 32-byte SHA-256 digest of the file content
+    - file name in UTF-8, the rest of the datagram
+
+    The receiver answers with an acknowledgment telling how many datagrams
+    it already has from an earlier, interrupted transfer of the same file,
+    so that the sender can resume from there. The check byte of the
+    acknowledgments tells the state of the transfer (STATUS_*): when the
+    last datagram has arrived, the receiver computes the digest of what it
+    got, and the final acknowledgment tells whether it matched.
+
+    Data datagrams, acknowledgments, retransmissions and congestion control
+    are the same as in the client (transport.rs).
+*/
+
+use std::{
+    fs::File,
+    io,
+    net::SocketAddr,
+    os::unix::fs::FileExt,
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    batch::BatchSocket,
+    protocol::{Ack, Header, HEADER_LEN, MAX_PAYLOAD},
+    sha256::{Digest, DIGEST_LEN},
+    transport::Source,
+};
+
+/// Transfer in progress.
+pub const STATUS_PENDING: u8 = 0;
+
+/// Whole file received, and the digest matches.
+pub const STATUS_VERIFIED: u8 = 1;
+
+/// Whole file received, but the digest does not match. The receiver has
+/// discarded the data.
+pub const STATUS_CORRUPT: u8 = 2;
+
+/// Receiver cannot take the file.
+pub const STATUS_REFUSED: u8 = 3;
+
+/// Default UDP port of the file receiver.
+pub const FILE_PORT: u16 = 20001;
+
+/// Number of times the metadata is sent before giving up.
+const HANDSHAKE_TRIES: u32 = 4;
+
+/// Time to wait for the first answer to the metadata, doubled every try.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Description of the file, sent before the data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: String,
+    pub size: u64,
+    pub digest: Digest,
+}
+
+impl Metadata {
+    pub fn to_datagram(&self) -> Vec<u8> {
+        let name = self.name.as_bytes();
+        let len = 8 + DIGEST_LEN + name.len();
+        let mut buf = vec![0; HEADER_LEN];
+        Header { seq: 0, len: len as u16 }.write(&mut buf);
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf.extend_from_slice(&self.digest);
+        buf.extend_from_slice(name);
+        buf
+    }
+
+    /// Returns None if this is not a valid metadata datagram.
+    pub fn parse(buf: &[u8]) -> Option<Metadata> {
+        let header = Header::parse(buf)?;
+        let payload = &buf[HEADER_LEN..];
+        if header.seq != 0 || payload.len() != header.len as usize || payload.len() < 8 + DIGEST_LEN {
+            return None;
+        }
+        Some(Metadata {
+            size: u64::from_be_bytes(payload[..8].try_into().ok()?),
+            digest: payload[8..8 + DIGEST_LEN].try_into().ok()?,
+            name: String::from_utf8(payload[8 + DIGEST_LEN..].to_vec()).ok()?,
+        })
+    }
+
+    /// Longest name that fits in the metadata datagram.
+    pub fn max_name_len() -> usize {
+        MAX_PAYLOAD - 8 - DIGEST_LEN
+    }
+}
+
+
+/// Payload read from a file.
+pub struct FileSource {
+    file: File,
+    size: usize,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> io::Result<FileSource> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len() as usize;
+        Ok(FileSource { file, size })
+    }
+}
+
+impl Source for FileSource {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        self.file.read_exact_at(buf, offset as u64)
+    }
+}
+
+
+/// Send the metadata until the receiver answers. Returns the answer: the
+/// number of datagrams the receiver already has, and the status.
+pub fn handshake(socket: &mut BatchSocket, dest: SocketAddr, metadata: &Metadata)
+    -> io::Result<Ack> {
+    let datagram = metadata.to_datagram();
+    let mut timeout = HANDSHAKE_TIMEOUT;
+    for _ in 0..HANDSHAKE_TRIES {
+        socket.send_batch(&[&datagram], dest)?;
+        let mut answer = None;
+        while answer.is_none() {
+            let received = socket.recv_batch(timeout, |data, info| {
+                if info.src == dest {
+                    answer = answer.or(Ack::parse(data));
+                }
+            })?;
+            if received == 0 {
+                break;
+            }
+        }
+        if let Some(ack) = answer {
+            return Ok(ack);
+        }
+        timeout *= 2;
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no answer from receiver"))
+}
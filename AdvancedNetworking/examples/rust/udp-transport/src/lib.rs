@@ -0,0 +1,15 @@
+/*  Building blocks of the UDP transfer, shared by the client (src/main.rs)
+    and the tools in src/bin: the adnet-agent stand-in (agent), the batching
+    benchmark (udp-bench), and the file transfer pair (file-send, file-recv).
+*/
+
+pub mod batch;
+pub mod congestion;
+pub mod ecn;
+pub mod fec;
+pub mod file;
+pub mod protocol;
+pub mod rtt;
+pub mod sha256;
+pub mod timestamp;
+pub mod transport;
@@ -0,0 +1,141 @@
+/*  UDP data transfer using the datagram format of the task-udp assignment.
+    Sends "TASK-UDP <keyword>" to adnet-agent over TCP, and gets back the
+    number of bytes to send and the character to fill the payload with.
+    Then transfers the data over UDP (protocol.rs), with retransmissions and
+    congestion control (transport.rs), and reports the check byte of the
+    last acknowledgment.
+
+    For local testing without Mininet, src/bin/agent is a stand-in for
+    adnet-agent that can also delay, drop and rate-limit datagrams like the
+    simple_topo bottleneck link.
+
+    Datagrams are marked ECN-capable, and if the receiver echoes congestion
+    marks, the sender reduces its rate like TCP Reno or DCTCP (--cc).
+    Optionally repair datagrams are sent for forward error correction
+    (--fec), for the agent stand-in to rebuild lost datagrams. With
+    --timestamps, RTT is measured from kernel timestamps, and compared with
+    the RTT measured in user space at the end.
+
+    Usage: cargo run --bin udp-transport -- [-s <address:port>] [-b single|mmsg|gso]
+                                            [--cc reno|dctcp] [--no-ecn] [--fec <k>] [--timestamps]
+                                            <keyword>
+           cargo run --bin agent -- [--delay <ms>] [--bw <Mbit/s>] [--loss <%>] [--queue <n>]
+                                    [--ecn-threshold <n>]
+           cargo run --release --bin udp-bench
+*/
+
+use std::{
+    error::Error,
+    io::{Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use udp_transport::{
+    batch::BatchSocket,
+    congestion::Algorithm,
+    ecn,
+    protocol::{control_message, parse_response, AGENT_UDP_PORT},
+    rtt::Summary,
+    transport::{Fill, Sender},
+};
+
+use crate::args::Args;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    println!("UDP transfer starting");
+    let args = Args::new();
+
+    // Open TCP connection to adnet-agent, send the control message and read
+    // the response that tells how many bytes of which character to send.
+    let server = args.server_addr().to_socket_addrs()?.next()
+        .ok_or("could not resolve server address")?;
+    let mut control = TcpStream::connect(server)?;
+    control.write_all(control_message(args.keyword()).as_bytes())?;
+    let mut buf = [0; 100];
+    let n = control.read(&mut buf)?;
+    let (size, character) = parse_response(std::str::from_utf8(&buf[..n])?)
+        .ok_or("invalid response from server")?;
+    println!("Starting to transmit {} bytes of {}.", size, character as char);
+
+    // Start clock to measure the time it takes do finish transmission
+    let start = Instant::now();
+    let checknum = transmit(&args, server, size, character)?;
+    let duration = start.elapsed();
+
+    println!("Size: {} -- Checknum: {} -- Duration: {:?}", size, checknum, duration);
+    Ok(())
+}
+
+
+// Transfer the data over UDP. Returns the check byte of the last
+// acknowledgment.
+fn transmit(args: &Args, server: SocketAddr, size: usize, character: u8)
+    -> Result<u8, Box<dyn Error>> {
+    let dest = SocketAddr::new(server.ip(), AGENT_UDP_PORT);
+    let socket = match dest {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
+    if args.ecn() {
+        ecn::set_codepoint(&socket, ecn::ECT0)?;
+    }
+    let mut socket = BatchSocket::new(socket, args.batching());
+    if args.timestamps() {
+        socket.enable_timestamps()?;
+    }
+
+    let mut sender = Sender::new(socket, dest, Fill { size, character }, args.cc())?;
+    if let Some(block) = args.fec() {
+        sender = sender.with_fec(block)?;
+    }
+    let checknum = sender.run()?;
+
+    let stats = sender.stats();
+    println!("Datagrams sent: {}, retransmitted: {} ({} fast retransmits, {} timeouts), acks: {}",
+        stats.sent, stats.retransmitted, stats.fast_retransmits, stats.timeouts, stats.acks);
+    if let (Some(srtt), Some(min_rtt)) = (sender.rtt().srtt(), sender.rtt().min_rtt()) {
+        println!("Smoothed RTT: {:?}, minimum RTT: {:?}, final window: {:.1} datagrams",
+            srtt, min_rtt, sender.congestion().cwnd());
+    }
+    if stats.repairs > 0 {
+        println!("FEC repair datagrams sent: {}", stats.repairs);
+    }
+    if let Some(marked) = stats.marked {
+        print!("Datagrams marked CE: {}", marked);
+        if sender.congestion().algorithm() == Algorithm::Dctcp {
+            print!(", DCTCP alpha: {:.3}", sender.congestion().alpha());
+        }
+        println!();
+    }
+    if args.timestamps() {
+        print_rtt_comparison(sender.rtt_samples());
+    }
+    Ok(checknum)
+}
+
+
+// Distributions of the RTT of the same datagrams, measured in user space and
+// from kernel timestamps.
+fn print_rtt_comparison(samples: &[(Duration, Duration)]) {
+    let user: Vec<Duration> = samples.iter().map(|&(user, _)| user).collect();
+    let kernel: Vec<Duration> = samples.iter().map(|&(_, kernel)| kernel).collect();
+    let (Some(user), Some(kernel)) = (Summary::new(&user), Summary::new(&kernel)) else {
+        println!("No RTT samples with kernel timestamps");
+        return;
+    };
+    println!("RTT of {} datagrams with kernel timestamps:", user.count);
+    println!("{:<26}{:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "(µs)", "min", "median", "p90", "p99", "max", "mean");
+    for (name, summary) in [("user space", &user), ("kernel timestamps", &kernel)] {
+        println!("{:<26}{:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>9.1}", name,
+            micros(summary.min), micros(summary.median), micros(summary.p90),
+            micros(summary.p99), micros(summary.max), micros(summary.mean));
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
+
+mod args;
@@ -0,0 +1,120 @@
+/*  Messages between task-udp and adnet-agent.
+
+    Control message over TCP: "TASK-UDP <keyword>", answered with
+    "<number of bytes> <character>".
+
+    Data datagrams over UDP: 4-byte sequence number and 2-byte payload length,
+    both in network byte order, followed by at most 1200 bytes of payload.
+    Sequence numbers start from 1, and a retransmission uses the original
+    number.
+
+    Acknowledgments: 4-byte highest consecutive sequence number received,
+    followed by one check byte. The agent stand-in (src/bin/agent) may
+    append a 4-byte count of datagrams received with the CE mark (ecn.rs),
+    when the datagrams are ECN-capable. adnet-agent does not do this, so the
+    extension is optional.
+*/
+
+use std::ops::Range;
+
+/// Length of the data datagram header.
+pub const HEADER_LEN: usize = 6;
+
+/// Maximum payload in one datagram.
+pub const MAX_PAYLOAD: usize = 1200;
+
+/// Maximum length of a data datagram.
+pub const MAX_DATAGRAM: usize = HEADER_LEN + MAX_PAYLOAD;
+
+/// Length of an acknowledgment.
+pub const ACK_LEN: usize = 5;
+
+/// Length of an acknowledgment with the CE count.
+pub const ECN_ACK_LEN: usize = ACK_LEN + 4;
+
+/// UDP port where adnet-agent receives data.
+pub const AGENT_UDP_PORT: u16 = 20000;
+
+/// Control message that starts the task.
+pub fn control_message(keyword: &str) -> String {
+    format!("TASK-UDP {}", keyword)
+}
+
+/// Parse response to the control message: number of bytes and the
+/// character to repeat in payload.
+pub fn parse_response(response: &str) -> Option<(usize, u8)> {
+    let mut words = response.split_whitespace();
+    let size = words.next()?.parse().ok()?;
+    let character = *words.next()?.as_bytes().first()?;
+    Some((size, character))
+}
+
+/// Number of datagrams needed to send `size` bytes, or None if they cannot
+/// all be numbered with 32-bit sequence numbers.
+pub fn datagram_count(size: u64) -> Option<u32> {
+    u32::try_from(size.div_ceil(MAX_PAYLOAD as u64)).ok()
+}
+
+/// Header of a data datagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub seq: u32,
+    pub len: u16,
+}
+
+impl Header {
+    pub fn write(&self, buf: &mut [u8]) {
+        buf[0..4].copy_from_slice(&self.seq.to_be_bytes());
+        buf[4..6].copy_from_slice(&self.len.to_be_bytes());
+    }
+
+    pub fn parse(buf: &[u8]) -> Option<Header> {
+        if buf.len() < HEADER_LEN {
+            return None;
+        }
+        Some(Header {
+            seq: u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            len: u16::from_be_bytes([buf[4], buf[5]]),
+        })
+    }
+}
+
+/// Bytes of a transfer of `size` bytes carried in datagram number `seq`.
+pub fn payload_range(seq: u32, size: usize) -> Range<usize> {
+    let offset = (seq as usize - 1) * MAX_PAYLOAD;
+    offset..size.min(offset + MAX_PAYLOAD)
+}
+
+/// Cumulative acknowledgment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ack {
+    pub seq: u32,
+    pub check: u8,
+    /// Number of CE-marked datagrams received so far, if echoed.
+    pub ce: Option<u32>,
+}
+
+impl Ack {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(ECN_ACK_LEN);
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.push(self.check);
+        if let Some(ce) = self.ce {
+            buf.extend_from_slice(&ce.to_be_bytes());
+        }
+        buf
+    }
+
+    pub fn parse(buf: &[u8]) -> Option<Ack> {
+        if buf.len() < ACK_LEN {
+            return None;
+        }
+        let ce = (buf.len() >= ECN_ACK_LEN)
+            .then(|| u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]));
+        Some(Ack {
+            seq: u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            check: buf[4],
+            ce,
+        })
+    }
+}
@@ -0,0 +1,115 @@
+/*  Round-trip time estimation and retransmission timeout, following
+    RFC 6298 (the same algorithm TCP uses).
+
+    Each sample updates a smoothed RTT and its mean deviation, and the
+    retransmission timeout is the smoothed RTT plus four deviations. Samples
+    are not taken from retransmitted datagrams, because we could not know
+    which transmission the acknowledgment belongs to (Karn's algorithm).
+
+    Summary describes a set of samples by its percentiles, to compare RTTs
+    measured in different ways (timestamp.rs).
+*/
+
+use std::time::Duration;
+
+/// Timeout before the first RTT sample.
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+
+/// Lower bound for the timeout. RFC 6298 suggests 1 second, Linux uses 200 ms.
+const MIN_RTO: Duration = Duration::from_millis(200);
+
+/// Upper bound for the timeout, also after backing off.
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    min_rtt: Option<Duration>,
+    rto: Duration,
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RttEstimator {
+    pub fn new() -> RttEstimator {
+        RttEstimator {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            min_rtt: None,
+            rto: INITIAL_RTO,
+        }
+    }
+
+    /// Update the estimate with a new RTT measurement.
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                // rttvar = 3/4 rttvar + 1/4 |srtt - rtt|, srtt = 7/8 srtt + 1/8 rtt
+                let deviation = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + deviation) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min| min.min(rtt)));
+        self.rto = (self.srtt.unwrap() + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Double the timeout after it has expired.
+    pub fn backoff(&mut self) {
+        self.rto = (self.rto * 2).min(MAX_RTO);
+    }
+
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt
+    }
+}
+
+
+/// Distribution of RTT samples.
+pub struct Summary {
+    pub count: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+impl Summary {
+    /// None if there are no samples.
+    pub fn new(samples: &[Duration]) -> Option<Summary> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+        // Nearest-rank percentile
+        let percentile = |p: usize| sorted[((sorted.len() * p).div_ceil(100)).max(1) - 1];
+        Some(Summary {
+            count: sorted.len(),
+            min: sorted[0],
+            median: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+        })
+    }
+}
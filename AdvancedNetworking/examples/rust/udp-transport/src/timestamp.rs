@@ -0,0 +1,125 @@
+/*  Kernel timestamps of sent and received datagrams.
+
+    Measuring the RTT with Instant::now() around send and receive calls also
+    measures the time the process spends in system calls, building
+    datagrams, and waiting to be scheduled. The kernel can instead tell when
+    a datagram actually left through the network interface and when it
+    arrived from it:
+
+    - SO_TIMESTAMPNS: every received datagram comes with an SCM_TIMESTAMPNS
+      control message, the time the kernel received it (struct timespec).
+    - SO_TIMESTAMPING with SOF_TIMESTAMPING_TX_SOFTWARE: when the interface
+      driver hands a sent datagram to the device, the kernel queues a message
+      with the time in the socket error queue, to be read with
+      recvmsg(MSG_ERRQUEUE). With SOF_TIMESTAMPING_OPT_ID the message tells
+      which send call it belongs to: the calls are numbered from 0 in the
+      order they were made. With SOF_TIMESTAMPING_OPT_TSONLY the message does
+      not carry a copy of the datagram.
+
+    Both are CLOCK_REALTIME, so they are returned as SystemTime. Note that a
+    datagram gets its transmit timestamp after any queueing discipline on
+    the sending host (e.g. netem or tbf configured with tc), so that delay
+    does not show up in the RTT.
+*/
+
+use std::{
+    io,
+    mem,
+    net::UdpSocket,
+    os::fd::AsRawFd,
+    ptr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Timestamp of a datagram handed to the device (SCM_TSTAMP_SND in
+/// linux/errqueue.h, not in the libc crate).
+const SCM_TSTAMP_SND: u32 = 0;
+
+/// Space for the control messages of one error queue message.
+const ERR_CONTROL_LEN: usize = 256;
+
+/// Ask for receive timestamps of each datagram, and transmit timestamps of
+/// each send call in the error queue. Must be done before anything is sent,
+/// so that the send calls are numbered from the first one.
+pub fn enable(socket: &UdpSocket) -> io::Result<()> {
+    setsockopt_int(socket, libc::SO_TIMESTAMPNS, 1)?;
+    let flags = libc::SOF_TIMESTAMPING_TX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_SOFTWARE
+        | libc::SOF_TIMESTAMPING_OPT_ID
+        | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+    setsockopt_int(socket, libc::SO_TIMESTAMPING, flags as libc::c_int)
+}
+
+/// Read the transmit timestamps waiting in the error queue, without
+/// blocking, and call `handle` with the number of the send call and its
+/// time. Returns the number of timestamps read.
+pub fn read_transmit(socket: &UdpSocket, mut handle: impl FnMut(u32, SystemTime))
+    -> io::Result<usize> {
+    let mut count = 0;
+    loop {
+        let mut control = [0u64; ERR_CONTROL_LEN / 8];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = ERR_CONTROL_LEN as _;
+        let rc = unsafe {
+            libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT)
+        };
+        if rc < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Ok(count);
+            }
+            return Err(e);
+        }
+
+        // The time and the number come in separate control messages
+        let mut time = None;
+        let mut key = None;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    // struct scm_timestamping: software time first, then two
+                    // unused (legacy and hardware)
+                    (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING) => {
+                        time = from_timespec(&ptr::read_unaligned(data as *const libc::timespec));
+                    }
+                    (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+                        let err = ptr::read_unaligned(data as *const libc::sock_extended_err);
+                        if err.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING
+                            && err.ee_info == SCM_TSTAMP_SND {
+                            key = Some(err.ee_data);
+                        }
+                    }
+                    _ => (),
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        if let (Some(key), Some(time)) = (key, time) {
+            handle(key, time);
+            count += 1;
+        }
+    }
+}
+
+/// Convert a kernel timestamp. All zeros means the kernel had no timestamp.
+pub(crate) fn from_timespec(ts: &libc::timespec) -> Option<SystemTime> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+fn setsockopt_int(socket: &UdpSocket, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    let rc = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,444 @@
+/*  Reliable transfer over UDP using the adnet-agent datagram format.
+
+    The sender keeps a window of unacknowledged datagrams (congestion.rs) and
+    spreads their transmission evenly over the round-trip time (pacing),
+    instead of sending the whole window back-to-back into the bottleneck
+    queue. Datagrams that are due at the same moment are handed to the kernel
+    in one batch (batch.rs).
+
+    Acknowledgments are cumulative, so the only signs of a lost datagram are:
+    - three duplicate acknowledgments: later datagrams arrive, but the
+      acknowledged number does not advance. The first unacknowledged
+      datagram is retransmitted (fast retransmit).
+    - an acknowledgment that advances, but not past the datagrams that were
+      in flight when the loss was detected: the next datagram was lost too,
+      and is retransmitted right away (like TCP NewReno).
+    - no acknowledgments at all until the retransmission timeout (rtt.rs).
+      The timeout doubles each time, and after MAX_TIMEOUTS in a row without
+      progress the receiver is taken to be gone.
+
+    If the acknowledgments echo a count of CE-marked datagrams (ecn.rs), the
+    growth of the count is passed to congestion control, which can then slow
+    down before the bottleneck queue overflows.
+
+    With forward error correction (fec.rs), a repair datagram follows every
+    block of new datagrams, and the receiver can rebuild one lost datagram
+    per block from it. The repair arrives at most a block later than the
+    lost datagram, so fast retransmit waits for that many duplicate
+    acknowledgments before assuming the loss was not repaired.
+
+    If the socket has kernel timestamps enabled (timestamp.rs), RTT samples
+    are the time from the transmit timestamp of a datagram to the receive
+    timestamp of its acknowledgment. They leave out the time the sender
+    spends between the clock readings and the system calls. When either
+    timestamp is missing, the sample comes from Instant::now() as without
+    timestamps. Both samples of each datagram are kept for comparison.
+*/
+
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    batch::{BatchSocket, MAX_BATCH},
+    congestion::{Algorithm, Congestion},
+    fec::{Encoder, REPAIR_FLAG},
+    protocol::{datagram_count, payload_range, Ack, Header, HEADER_LEN, MAX_DATAGRAM},
+    rtt::RttEstimator,
+};
+
+/// Minimum time to wait for acknowledgments at a time. At high rates several
+/// datagrams become due during this time and are sent in one batch.
+const MIN_WAIT: Duration = Duration::from_micros(200);
+
+/// Duplicate acknowledgments that trigger fast retransmit, without FEC.
+const DUPACK_THRESHOLD: u32 = 3;
+
+/// Retransmission timeouts in a row, without the acknowledgments advancing,
+/// after which the transfer fails.
+const MAX_TIMEOUTS: u32 = 6;
+
+/// Where the payload of the datagrams comes from.
+pub trait Source {
+    /// Total number of bytes to send.
+    fn size(&self) -> usize;
+
+    /// Fill `buf` with the bytes starting at `offset`.
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// Payload of the task-udp assignment: one character repeated.
+pub struct Fill {
+    pub size: usize,
+    pub character: u8,
+}
+
+impl Source for Fill {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read_at(&mut self, _offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        buf.fill(self.character);
+        Ok(())
+    }
+}
+
+/// Counters reported at the end of transfer.
+#[derive(Default)]
+pub struct Stats {
+    pub sent: u64,
+    pub retransmitted: u64,
+    pub fast_retransmits: u64,
+    pub timeouts: u64,
+    pub acks: u64,
+    pub repairs: u64,
+    /// Datagrams marked CE, if the receiver echoed the marks
+    pub marked: Option<u32>,
+}
+
+// Send time of an unacknowledged datagram.
+struct Sent {
+    time: Instant,
+    retransmitted: bool,
+    index: u64,                     // first transmission among datagrams of the socket
+    kernel_time: Option<SystemTime>,  // transmit timestamp of the first transmission
+}
+
+pub struct Sender<S: Source> {
+    socket: BatchSocket,
+    dest: SocketAddr,
+    source: S,
+    total: u32,             // number of datagrams in the transfer
+
+    next_seq: u32,          // next new datagram to send
+    acked: u32,             // highest cumulative acknowledgment
+    check: u8,              // check byte of the latest advancing acknowledgment
+    in_flight: VecDeque<Sent>,  // datagrams acked + 1 .. next_seq - 1
+    retransmit: Vec<u32>,   // datagrams to retransmit as soon as possible
+
+    rtt: RttEstimator,
+    cc: Congestion,
+    dupacks: u32,
+    recovery: Option<u32>,  // in loss recovery until this datagram is acked
+    rto_deadline: Option<Instant>,
+    timeouts: u32,          // timeouts since the acknowledgments last advanced
+    next_send: Instant,     // pacing: when the next new datagram may be sent
+    fec: Option<Encoder>,
+
+    stats: Stats,
+    rtt_samples: Vec<(Duration, Duration)>,  // user-space and kernel RTT of the same datagram
+}
+
+impl<S: Source> Sender<S> {
+    /// Fails if the data does not fit in the sequence number space.
+    pub fn new(socket: BatchSocket, dest: SocketAddr, source: S, algorithm: Algorithm)
+        -> io::Result<Sender<S>> {
+        let total = datagram_count(source.size() as u64).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput, format!("{} bytes is too much to send", source.size())))?;
+        Ok(Sender {
+            socket,
+            dest,
+            total,
+            source,
+            next_seq: 1,
+            acked: 0,
+            check: 0,
+            in_flight: VecDeque::new(),
+            retransmit: Vec::new(),
+            rtt: RttEstimator::new(),
+            cc: Congestion::new(algorithm),
+            dupacks: 0,
+            recovery: None,
+            rto_deadline: None,
+            timeouts: 0,
+            next_send: Instant::now(),
+            fec: None,
+            stats: Stats::default(),
+            rtt_samples: Vec::new(),
+        })
+    }
+
+    /// Send a repair datagram after every `block` new datagrams (fec.rs).
+    /// Fails if data sequence numbers would reach the repair flag bit.
+    pub fn with_fec(mut self, block: u8) -> io::Result<Sender<S>> {
+        if self.total >= REPAIR_FLAG {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "{} datagrams is too many with FEC, the limit is {}", self.total, REPAIR_FLAG - 1)));
+        }
+        self.fec = Some(Encoder::new(block));
+        Ok(self)
+    }
+
+    /// Continue an interrupted transfer: datagrams up to `acked` are already
+    /// at the receiver.
+    pub fn resume_from(mut self, acked: u32) -> Sender<S> {
+        self.acked = acked.min(self.total);
+        self.next_seq = self.acked + 1;
+        self
+    }
+
+    /// Transfer all data. Returns the check byte of the acknowledgment that
+    /// completed the transfer, or a TimedOut error if the receiver stops
+    /// acknowledging. `acked` then tells how far the transfer got.
+    pub fn run(&mut self) -> io::Result<u8> {
+        while self.acked < self.total {
+            let now = Instant::now();
+            if self.rto_deadline.is_some_and(|deadline| now >= deadline) {
+                if self.timeouts == MAX_TIMEOUTS {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, format!(
+                        "no acknowledgments after {} retransmission timeouts", MAX_TIMEOUTS)));
+                }
+                self.on_timeout(now);
+            }
+            self.send_due(now)?;
+            self.receive_acks()?;
+        }
+        Ok(self.check)
+    }
+
+    /// Highest datagram acknowledged cumulatively.
+    pub fn acked(&self) -> u32 {
+        self.acked
+    }
+
+    /// The socket and the data source back, e.g. for asking the receiver
+    /// where to continue after a failed transfer.
+    pub fn into_parts(self) -> (BatchSocket, S) {
+        (self.socket, self.source)
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+
+    pub fn congestion(&self) -> &Congestion {
+        &self.cc
+    }
+
+    /// RTT samples of datagrams that got both kernel timestamps, as
+    /// (measured in user space, measured from kernel timestamps).
+    pub fn rtt_samples(&self) -> &[(Duration, Duration)] {
+        &self.rtt_samples
+    }
+
+    fn in_flight(&self) -> u32 {
+        self.next_seq - 1 - self.acked
+    }
+
+    // Time between new datagrams: the window is spread over the smoothed
+    // RTT. In slow start we pace faster, so that pacing does not limit the
+    // growth of the window (Linux uses the same factors for TCP).
+    fn pacing_interval(&self) -> Duration {
+        match self.rtt.srtt() {
+            None => Duration::ZERO,  // no RTT estimate yet: send the initial window at once
+            Some(srtt) => {
+                let factor = if self.cc.in_slow_start() { 2.0 } else { 1.25 };
+                srtt.div_f64(self.cc.cwnd() * factor)
+            }
+        }
+    }
+
+    fn build_datagram(&mut self, seq: u32) -> io::Result<Vec<u8>> {
+        let range = payload_range(seq, self.source.size());
+        let mut buf = vec![0; MAX_DATAGRAM];
+        Header { seq, len: range.len() as u16 }.write(&mut buf);
+        buf.truncate(HEADER_LEN + range.len());
+        self.source.read_at(range.start, &mut buf[HEADER_LEN..])?;
+        Ok(buf)
+    }
+
+    fn dupack_threshold(&self) -> u32 {
+        match &self.fec {
+            Some(fec) => DUPACK_THRESHOLD.max(fec.block() as u32),
+            None => DUPACK_THRESHOLD,
+        }
+    }
+
+    // Send retransmissions, and new datagrams allowed by the window and
+    // pacing, in one batch. Repair datagrams go right after the last new
+    // datagram of their block.
+    fn send_due(&mut self, now: Instant) -> io::Result<()> {
+        let mut seqs: Vec<u32> = self.retransmit.drain(..).filter(|&seq| seq > self.acked).collect();
+        let first_new = self.next_seq;
+        let interval = self.pacing_interval();
+
+        // Do not accumulate sending credit while the window was full
+        if self.next_send + interval < now {
+            self.next_send = now;
+        }
+        while seqs.len() < MAX_BATCH
+            && self.next_seq <= self.total
+            && self.in_flight() < self.cc.window().max(1)
+            && self.next_send <= now {
+            seqs.push(self.next_seq);
+            self.in_flight.push_back(Sent { time: now, retransmitted: false, index: 0, kernel_time: None });
+            self.next_seq += 1;
+            self.next_send += interval;
+        }
+        if seqs.is_empty() {
+            return Ok(());
+        }
+
+        // Index of each new datagram for matching transmit timestamps
+        let first_index = self.socket.datagrams_sent();
+        let mut buffers: Vec<Vec<u8>> = Vec::with_capacity(seqs.len());
+        for &seq in &seqs {
+            if seq >= first_new {
+                let sent = &mut self.in_flight[(seq - self.acked - 1) as usize];
+                sent.index = first_index + buffers.len() as u64;
+            }
+            let buf = self.build_datagram(seq)?;
+            let repair = match &mut self.fec {
+                Some(fec) if seq >= first_new => fec.add(&buf, seq == self.total),
+                _ => None,
+            };
+            buffers.push(buf);
+            if let Some(repair) = repair {
+                buffers.push(repair);
+                self.stats.repairs += 1;
+            }
+        }
+        let datagrams: Vec<&[u8]> = buffers.iter().map(|buf| buf.as_slice()).collect();
+        self.socket.send_batch(&datagrams, self.dest)?;
+        self.stats.sent += seqs.len() as u64;
+
+        if self.rto_deadline.is_none() {
+            self.rto_deadline = Some(now + self.rtt.rto());
+        }
+        Ok(())
+    }
+
+    // Wait for acknowledgments until it is time to send the next datagram,
+    // or the retransmission timer expires.
+    fn receive_acks(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let mut wake = self.rto_deadline.unwrap_or(now + self.rtt.rto());
+        if self.next_seq <= self.total && self.in_flight() < self.cc.window() {
+            wake = wake.min(self.next_send);
+        }
+        let wait = wake.saturating_duration_since(now).max(MIN_WAIT);
+
+        let mut acks = Vec::new();
+        match self.socket.recv_batch(wait, |data, info| {
+            if info.src.ip() == self.dest.ip() {
+                if let Some(ack) = Ack::parse(data) {
+                    acks.push((ack, info.timestamp));
+                }
+            }
+        }) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+
+        // Transmit timestamps of datagrams in flight. The indices grow with
+        // the sequence numbers, because retransmissions keep the index of
+        // the first transmission.
+        for timestamp in self.socket.tx_timestamps() {
+            if let Ok(i) = self.in_flight.binary_search_by_key(&timestamp.index, |sent| sent.index) {
+                self.in_flight[i].kernel_time = Some(timestamp.time);
+            }
+        }
+
+        let now = Instant::now();
+        for (ack, received) in acks {
+            self.on_ack(ack, received, now);
+        }
+        Ok(())
+    }
+
+    fn on_ack(&mut self, ack: Ack, received: Option<SystemTime>, now: Instant) {
+        self.stats.acks += 1;
+        let marked = self.new_marks(&ack);
+        if ack.seq > self.acked && ack.seq < self.next_seq {
+            let newly_acked = ack.seq - self.acked;
+            self.cc.on_ecn(ack.seq, newly_acked, marked, self.next_seq - 1);
+
+            // RTT from the latest datagram this acknowledgment covers
+            let sent = &self.in_flight[newly_acked as usize - 1];
+            if !sent.retransmitted {
+                let user = now - sent.time;
+                let kernel = match (sent.kernel_time, received) {
+                    (Some(sent), Some(received)) => received.duration_since(sent).ok(),
+                    _ => None,
+                };
+                if let Some(kernel) = kernel {
+                    self.rtt_samples.push((user, kernel));
+                }
+                self.rtt.sample(kernel.unwrap_or(user));
+            }
+            self.in_flight.drain(..newly_acked as usize);
+            self.acked = ack.seq;
+            self.check = ack.check;
+            self.dupacks = 0;
+            self.timeouts = 0;
+
+            match self.recovery {
+                Some(end) if self.acked < end => {
+                    // Partial acknowledgment: the next datagram was lost too
+                    self.retransmit(self.acked + 1, now);
+                }
+                _ => {
+                    self.recovery = None;
+                    self.cc.on_ack(newly_acked);
+                }
+            }
+
+            // Restart the timer for the remaining datagrams
+            self.rto_deadline = if self.in_flight() > 0 {
+                Some(now + self.rtt.rto())
+            } else {
+                None
+            };
+        } else if ack.seq == self.acked && self.in_flight() > 0 {
+            self.cc.on_ecn(ack.seq, 0, marked, self.next_seq - 1);
+            self.dupacks += 1;
+            if self.dupacks == self.dupack_threshold() && self.recovery.is_none() {
+                self.stats.fast_retransmits += 1;
+                self.cc.on_loss(self.in_flight());
+                self.recovery = Some(self.next_seq - 1);
+                self.retransmit(self.acked + 1, now);
+            }
+        }
+        // Older acknowledgments carry no new information
+    }
+
+    // Number of CE marks echoed since the previous acknowledgment. The count
+    // is cumulative, and acknowledgments may be reordered.
+    fn new_marks(&mut self, ack: &Ack) -> u32 {
+        let ce = match ack.ce {
+            Some(ce) => ce,
+            None => return 0,
+        };
+        let previous = self.stats.marked.unwrap_or(0);
+        self.stats.marked = Some(ce.max(previous));
+        ce.saturating_sub(previous)
+    }
+
+    fn on_timeout(&mut self, now: Instant) {
+        self.stats.timeouts += 1;
+        self.timeouts += 1;
+        self.cc.on_timeout(self.in_flight());
+        self.rtt.backoff();
+        self.recovery = Some(self.next_seq - 1);
+        self.dupacks = 0;
+        self.retransmit(self.acked + 1, now);
+        self.rto_deadline = Some(now + self.rtt.rto());
+    }
+
+    fn retransmit(&mut self, seq: u32, now: Instant) {
+        let sent = &mut self.in_flight[(seq - self.acked - 1) as usize];
+        sent.time = now;
+        sent.retransmitted = true;
+        self.retransmit.push(seq);
+        self.stats.retransmitted += 1;
+    }
+}
@@ -0,0 +1,319 @@
+/* Open TCP server socket, bind it to given address, and echo back all data
+ * received from the connections, like iterative-server. The difference is
+ * that this server uses io_uring instead of MIO (epoll), to compare
+ * completion-based I/O with readiness-based I/O.
+ *
+ * With MIO, the kernel tells when a socket is ready, and then we make the
+ * read or write system call ourselves. With io_uring, we place the operation
+ * itself into a submission queue shared with the kernel, and later pick its
+ * result from a completion queue. Many operations can be submitted, and many
+ * results collected, with one system call.
+ *
+ * - Multishot accept: one submitted accept operation produces a completion
+ *   for every new connection, until it is cancelled or fails.
+ * - Registered buffers: a fixed set of buffers is registered with the kernel
+ *   at startup, so that the kernel does not need to map the user pages for
+ *   every operation. Each client owns one buffer while it is connected, so
+ *   the number of buffers limits the number of concurrent clients.
+ *
+ * Each client has exactly one operation in flight: either a read into its
+ * buffer, or a write of the data just read. Therefore there cannot be
+ * completions for a client that has already been closed.
+ *
+ * On SIGINT (Ctrl-C) or SIGTERM the server stops accepting new connections,
+ * waits for the active clients to close within a deadline, closes those that
+ * did not, prints how many bytes were echoed to each client, and exits.
+ *
+ * Requires Linux 5.19 or newer for multishot accept.
+ *
+ * Usage: cargo run -- <IP>:<port>
+ */
+
+use std::{
+    env,
+    error::Error,
+    io,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    os::fd::{AsRawFd, FromRawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use signal_hook::consts::TERM_SIGNALS;
+use slab::Slab;
+
+// Number of registered buffers, i.e., maximum number of concurrent clients.
+const BUF_COUNT: usize = 256;
+
+// Size of each registered buffer.
+const BUF_SIZE: usize = 4096;
+
+// Size of submission queue. Completion queue is twice as large by default.
+const RING_SIZE: u32 = 256;
+
+// User data of the multishot accept operation and its cancellation. Client
+// operations use the client's slab key, which is always below BUF_COUNT.
+const ACCEPT: u64 = u64::MAX;
+const CANCEL: u64 = u64::MAX - 1;
+
+// How long active connections may take after shutdown was requested.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often we wake up to check whether shutdown was requested.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Operation that a client currently has in flight.
+enum State {
+    Reading,
+    Writing { pos: usize, len: usize },  // echoing buf[pos..len]
+}
+
+struct Client {
+    socket: TcpStream,  // socket is closed when client is dropped
+    address: SocketAddr,
+    state: State,
+    echoed: u64,
+}
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Collect command-line arguments into a vector
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() != 2 {
+        eprintln!("arguments: <host>:<port>");
+        return Err("Invalid command".into());
+    }
+
+    // Signal handler sets a flag that the event loop checks. A second signal
+    // exits immediately.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for sig in TERM_SIGNALS {
+        signal_hook::flag::register_conditional_shutdown(*sig, 1, Arc::clone(&shutdown))?;
+        signal_hook::flag::register(*sig, Arc::clone(&shutdown))?;
+    }
+
+    // Listening socket is created as usual. io_uring only needs its descriptor.
+    let mut server = Some(TcpListener::bind(&args[1])?);
+    let listen_fd = server.as_ref().unwrap().as_raw_fd();
+
+    // One contiguous memory area for all buffers. It must not move or be
+    // freed while registered, so it is allocated once and never resized.
+    // It is declared before the ring, so that it is dropped after the ring,
+    // also when main returns early with an error: local variables are
+    // dropped in reverse order of declaration.
+    let mut memory = vec![0u8; BUF_COUNT * BUF_SIZE];
+    let iovecs: Vec<libc::iovec> = memory
+        .chunks_mut(BUF_SIZE)
+        .map(|chunk| libc::iovec {
+            iov_base: chunk.as_mut_ptr() as *mut libc::c_void,
+            iov_len: BUF_SIZE,
+        })
+        .collect();
+
+    let mut ring = IoUring::new(RING_SIZE)?;
+    // Safety: the memory stays valid until the ring is dropped, which
+    // happens before `memory` is dropped on every return from main.
+    unsafe { ring.submitter().register_buffers(&iovecs)? };
+    // From now on the memory is accessed only through this pointer, by the
+    // kernel, while operations are in flight.
+    let buffers = memory.as_mut_ptr();
+
+    // Start accepting connections
+    push(&mut ring, &opcode::AcceptMulti::new(types::Fd(listen_fd)).build().user_data(ACCEPT))?;
+
+    let mut clients: Slab<Client> = Slab::with_capacity(BUF_COUNT);
+    let mut finished: Vec<(SocketAddr, u64)> = Vec::new();
+    let mut deadline: Option<Instant> = None;  // set when shutting down
+    let mut closed_all = false;
+
+    while deadline.is_none() || !clients.is_empty() {
+        // Submit the queued operations and wait until at least one has
+        // completed, but at most for POLL_INTERVAL. Timeout and a signal
+        // arriving show as errors, and are not a problem.
+        let ts = types::Timespec::from(POLL_INTERVAL);
+        let submit_args = types::SubmitArgs::new().timespec(&ts);
+        if let Err(e) = ring.submitter().submit_with_args(1, &submit_args) {
+            if e.raw_os_error() != Some(libc::ETIME) && e.kind() != io::ErrorKind::Interrupted {
+                return Err(e.into());
+            }
+        }
+
+        if shutdown.load(Ordering::Relaxed) && deadline.is_none() {
+            // Cancel the accept operation, and close the listening socket.
+            // New connection attempts are refused.
+            println!("Shutting down, waiting up to {} seconds for {} active clients",
+                DRAIN_TIMEOUT.as_secs(), clients.len());
+            push(&mut ring, &opcode::AsyncCancel::new(ACCEPT).build().user_data(CANCEL))?;
+            server = None;
+            deadline = Some(Instant::now() + DRAIN_TIMEOUT);
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) && !closed_all {
+            // Shutting down the sockets completes the pending reads with 0
+            // bytes, and the clients are removed as if they had closed.
+            for (_, c) in clients.iter() {
+                println!("Closing connection to {}", c.address);
+                let _ = c.socket.shutdown(Shutdown::Both);
+            }
+            closed_all = true;
+        }
+
+        // Collect completions first, because handling them submits new
+        // operations, and the queues cannot be borrowed at the same time.
+        let completions: Vec<cqueue::Entry> = ring.completion().collect();
+        for cqe in completions {
+            match cqe.user_data() {
+                ACCEPT => {
+                    if server.is_none() {
+                        // Connection that was accepted just before shutdown
+                        // gets closed right away.
+                        if cqe.result() >= 0 {
+                            drop(unsafe { TcpStream::from_raw_fd(cqe.result()) });
+                        }
+                        continue;
+                    }
+                    if cqe.result() < 0 {
+                        println!("Error accepting connection: {}",
+                            io::Error::from_raw_os_error(-cqe.result()));
+                    } else {
+                        // Safety: the kernel gave us a new descriptor we own
+                        let socket = unsafe { TcpStream::from_raw_fd(cqe.result()) };
+                        accept_client(&mut ring, &mut clients, buffers, socket)?;
+                    }
+                    // If the kernel has stopped the multishot operation, for
+                    // example because the completion queue overflowed,
+                    // start accepting again.
+                    if !cqueue::more(cqe.flags()) {
+                        push(&mut ring, &opcode::AcceptMulti::new(types::Fd(listen_fd)).build().user_data(ACCEPT))?;
+                    }
+                }
+                CANCEL => {}
+                key => {
+                    let key = key as usize;
+                    if !handle_client(&mut ring, &mut clients, buffers, key, cqe.result())? {
+                        let c = clients.remove(key);
+                        finished.push((c.address, c.echoed));
+                    }
+                }
+            }
+        }
+    }
+
+    println!("Bytes echoed per client:");
+    for (address, n) in finished {
+        println!("  {}: {}", address, n);
+    }
+    Ok(())
+}
+
+
+// Store new client, and start reading from it.
+fn accept_client(
+    ring: &mut IoUring,
+    clients: &mut Slab<Client>,
+    buffers: *mut u8,
+    socket: TcpStream,
+) -> io::Result<()> {
+    // The client may have reset the connection already. Dropping the socket
+    // closes it, and we keep serving the others.
+    let address = match socket.peer_addr() {
+        Ok(address) => address,
+        Err(e) => {
+            println!("Could not get peer address of new connection: {}", e);
+            return Ok(());
+        }
+    };
+    if clients.len() >= BUF_COUNT {
+        // All buffers are in use. Dropping the socket closes it.
+        println!("No free buffers, rejecting {}", address);
+        return Ok(());
+    }
+    println!("Accepting connection from {}", address);
+
+    // Slab reuses keys of removed entries, so with at most BUF_COUNT
+    // clients the key is always a valid buffer index.
+    let fd = socket.as_raw_fd();
+    let key = clients.insert(Client { socket, address, state: State::Reading, echoed: 0 });
+    push(ring, &read_op(fd, buffers, key))
+}
+
+
+// Handle completion of client's operation, and submit the next one. Returns
+// false when the client should be removed.
+fn handle_client(
+    ring: &mut IoUring,
+    clients: &mut Slab<Client>,
+    buffers: *mut u8,
+    key: usize,
+    result: i32,
+) -> io::Result<bool> {
+    let c = &mut clients[key];
+    if result < 0 {
+        println!("Error serving client {}: {}", c.address, io::Error::from_raw_os_error(-result));
+        return Ok(false);
+    }
+    let n = result as usize;
+    let fd = c.socket.as_raw_fd();
+
+    match c.state {
+        State::Reading => {
+            // return value of 0 bytes means that socket is closed by
+            // the other end.
+            if n == 0 {
+                println!("Client {} closed connection", c.address);
+                return Ok(false);
+            }
+            // Echo the same bytes back from the same buffer
+            c.state = State::Writing { pos: 0, len: n };
+            push(ring, &write_op(fd, buffers, key, 0, n))?;
+        }
+        State::Writing { pos, len } => {
+            c.echoed += n as u64;
+            let pos = pos + n;
+            if pos < len {
+                // Partial write, continue with the rest
+                c.state = State::Writing { pos, len };
+                push(ring, &write_op(fd, buffers, key, pos, len))?;
+            } else {
+                c.state = State::Reading;
+                push(ring, &read_op(fd, buffers, key))?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+
+// Read into client's registered buffer. The user data tells whose operation
+// completed.
+fn read_op(fd: i32, buffers: *mut u8, key: usize) -> squeue::Entry {
+    let buf = buffers.wrapping_add(key * BUF_SIZE);
+    opcode::ReadFixed::new(types::Fd(fd), buf, BUF_SIZE as u32, key as u16)
+        .build()
+        .user_data(key as u64)
+}
+
+// Write buf[pos..len] from client's registered buffer.
+fn write_op(fd: i32, buffers: *mut u8, key: usize, pos: usize, len: usize) -> squeue::Entry {
+    let buf = buffers.wrapping_add(key * BUF_SIZE + pos) as *const u8;
+    opcode::WriteFixed::new(types::Fd(fd), buf, (len - pos) as u32, key as u16)
+        .build()
+        .user_data(key as u64)
+}
+
+// Add operation to submission queue. If the queue is full, submit what is
+// there to make room.
+fn push(ring: &mut IoUring, entry: &squeue::Entry) -> io::Result<()> {
+    loop {
+        // Safety: buffers referred by the entry stay valid until completion,
+        // because the memory area lives until the end of main.
+        if unsafe { ring.submission().push(entry) }.is_ok() {
+            return Ok(());
+        }
+        ring.submit()?;
+    }
+}